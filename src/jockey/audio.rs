@@ -4,7 +4,7 @@ use cpal::traits::{DeviceTrait, HostTrait, StreamTrait};
 use num_complex::Complex;
 use rustfft::{Fft, FftPlanner};
 
-use super::Config;
+use super::{Config, Track};
 use crate::util::RingBuffer;
 
 pub const AUDIO_SAMPLES: usize = 512;
@@ -50,6 +50,7 @@ pub struct Audio {
     l_samples: Arc<Mutex<RingBuffer<f32>>>,
     r_samples: Arc<Mutex<RingBuffer<f32>>>,
     stream: Option<cpal::Stream>,
+    active: bool,
     channels: Channels,
     sample_freq: usize,
     pub attack: f32,
@@ -99,6 +100,7 @@ impl Audio {
             l_samples: Arc::new(Mutex::new(RingBuffer::new(size))),
             r_samples: Arc::new(Mutex::new(RingBuffer::new(size))),
             stream: None,
+            active: false,
             channels: Channels::None,
             fft,
             attack: 0.5,
@@ -224,25 +226,42 @@ impl Audio {
     }
 
     pub fn update_samples(&mut self) {
-        if self.stream.is_none() {
+        self.active = self.stream.is_some();
+        if !self.active {
             return;
         }
 
-        let l_samples_p = Arc::clone(&self.l_samples);
-        let l_samples = l_samples_p.lock().unwrap();
-        l_samples.copy_to_slice(&mut self.l_signal);
+        self.l_samples
+            .lock()
+            .unwrap()
+            .copy_to_slice(&mut self.l_signal);
+        let stereo = matches!(self.channels, Channels::Stereo);
+        if stereo {
+            self.r_samples
+                .lock()
+                .unwrap()
+                .copy_to_slice(&mut self.r_signal);
+        }
+
+        self.update_volume(stereo);
+    }
 
+    /// Takes the samples from a playing track instead of the input device.
+    pub fn update_samples_from(&mut self, track: &Track) {
+        self.active = true;
+        track.window(&mut self.l_signal, &mut self.r_signal);
+        self.update_volume(true);
+    }
+
+    fn update_volume(&mut self, stereo: bool) {
         // calculate volume with RMS
-        self.volume[1] =
-            (self.l_signal.iter().map(|&x| x.powi(2)).sum::<f32>() / l_samples.size as f32).sqrt();
-
-        if let Channels::Stereo = self.channels {
-            let r_samples_p = self.r_samples.clone();
-            let r_samples = r_samples_p.lock().unwrap();
-            r_samples.copy_to_slice(&mut self.r_signal);
-            self.volume[2] = (self.r_signal.iter().map(|&x| x.powi(2)).sum::<f32>()
-                / l_samples.size as f32)
-                .sqrt();
+        let rms = |signal: &[f32]| {
+            (signal.iter().map(|&x| x.powi(2)).sum::<f32>() / signal.len() as f32).sqrt()
+        };
+
+        self.volume[1] = rms(&self.l_signal);
+        if stereo {
+            self.volume[2] = rms(&self.r_signal);
             self.volume[0] = (self.volume[1] + self.volume[2]) / 2.0;
         } else {
             self.volume[0] = self.volume[1];
@@ -255,7 +274,7 @@ impl Audio {
     }
 
     pub fn update_fft(&mut self) {
-        if self.stream.is_none() {
+        if !self.active {
             return;
         }
 
@@ -296,7 +315,7 @@ impl Audio {
     }
 
     fn update_nice_fft(&mut self) {
-        if self.stream.is_none() {
+        if !self.active {
             return;
         }
         let n = self.l_raw_spectrum.len() * 2;
@@ -0,0 +1,185 @@
+use std::{io::Write, path::Path, time::Instant};
+
+use super::{Midi, MIDI_N};
+
+/// File name of recorded automation, relative to the working directory
+pub const AUTOMATION_FILE: &str = "automation.dat";
+
+/// A change of a slider or button, recorded at a point on the timeline.
+///
+/// Stored as plain tuples of time, kind, id and value,
+/// with a kind of 0 for sliders and 1 for buttons.
+type Event = (f32, u8, usize, f32);
+
+const SLIDER: u8 = 0;
+const BUTTON: u8 = 1;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AutomationState {
+    Idle,
+    Recording,
+    Playing,
+}
+
+/// Records slider and button changes against the timeline and plays them back.
+#[derive(Debug)]
+pub struct Automation {
+    pub state: AutomationState,
+    events: Vec<Event>,
+    last_sliders: [f32; MIDI_N],
+    last_buttons: [f32; MIDI_N],
+    last_time: f32,
+}
+
+impl Automation {
+    pub fn new() -> Self {
+        Self {
+            state: AutomationState::Idle,
+            events: Vec::new(),
+            last_sliders: [0.0; MIDI_N],
+            last_buttons: [0.0; MIDI_N],
+            last_time: 0.0,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.events.len()
+    }
+
+    /// Starts a new recording, the current values are recorded as well.
+    pub fn record(&mut self, midi: &Midi, time: f32) {
+        self.state = AutomationState::Recording;
+        self.events.clear();
+        for k in 0..MIDI_N {
+            self.events.push((time, SLIDER, k, midi.slider_targets[k]));
+            self.events.push((time, BUTTON, k, midi.buttons[k].0));
+        }
+
+        self.last_sliders = midi.slider_targets;
+        self.last_buttons = midi.buttons.map(|b| b.0);
+    }
+
+    /// Starts playing back the recorded events from the given time.
+    pub fn play(&mut self, midi: &mut Midi, time: f32) {
+        self.state = AutomationState::Playing;
+        self.seek(midi, time);
+    }
+
+    pub fn stop(&mut self) {
+        if self.state == AutomationState::Recording {
+            self.events.sort_by(|a, b| a.0.total_cmp(&b.0));
+        }
+
+        self.state = AutomationState::Idle;
+    }
+
+    /// Records changes or plays back events, should be called once per frame.
+    pub fn update(&mut self, midi: &mut Midi, time: f32) {
+        match self.state {
+            AutomationState::Idle => (),
+            AutomationState::Recording => self.record_changes(midi, time),
+            AutomationState::Playing => {
+                // jumps back on the timeline restore the values at that time
+                if time < self.last_time {
+                    self.seek(midi, time);
+                }
+
+                let last_time = self.last_time;
+                for &event in self.events.iter() {
+                    if last_time < event.0 && event.0 <= time {
+                        apply(event, midi, true);
+                    }
+                }
+            }
+        }
+
+        self.last_time = time;
+    }
+
+    fn record_changes(&mut self, midi: &Midi, time: f32) {
+        for k in 0..MIDI_N {
+            let slider = midi.slider_targets[k];
+            if slider != self.last_sliders[k] {
+                self.events.push((time, SLIDER, k, slider));
+                self.last_sliders[k] = slider;
+            }
+
+            let button = midi.buttons[k].0;
+            if button != self.last_buttons[k] {
+                self.events.push((time, BUTTON, k, button));
+                self.last_buttons[k] = button;
+            }
+        }
+    }
+
+    /// Sets all values to what they were at the given time, without
+    /// counting the button presses in between.
+    fn seek(&mut self, midi: &mut Midi, time: f32) {
+        for &event in self.events.iter().take_while(|e| e.0 <= time) {
+            apply(event, midi, false);
+        }
+
+        self.last_time = time;
+    }
+
+    pub fn save(&self, path: impl AsRef<Path>) {
+        match std::fs::File::create(path) {
+            Err(err) => log::error!("Failed to save automation: {}", err),
+
+            Ok(mut file) => {
+                if let Err(err) = file.write_all(b"# This file was automatically generated by Sh4derJockey.\n# Please do not edit this file.\n") {
+                    log::error!("Failed to save automation: {:?}", err);
+                    return;
+                }
+
+                match serde_yaml::to_writer(file, &self.events) {
+                    Ok(_) => log::info!("Saved {} automation events", self.events.len()),
+                    Err(err) => log::error!("Failed to save automation: {:?}", err),
+                }
+            }
+        }
+    }
+
+    pub fn load(&mut self, path: impl AsRef<Path>) {
+        let file = match std::fs::File::open(path) {
+            Ok(f) => f,
+            Err(err) => {
+                log::error!("Failed to open automation: {}", err);
+                return;
+            }
+        };
+
+        match serde_yaml::from_reader::<_, Vec<Event>>(file) {
+            Ok(mut events) => {
+                events.retain(|e| e.2 < MIDI_N);
+                events.sort_by(|a, b| a.0.total_cmp(&b.0));
+                log::info!("Loaded {} automation events", events.len());
+                self.events = events;
+            }
+            Err(err) => log::error!("Failed to parse automation: {}", err),
+        }
+    }
+}
+
+/// Applies a recorded event, button presses are only counted if `live` is set.
+fn apply((_, kind, id, value): Event, midi: &mut Midi, live: bool) {
+    match kind {
+        SLIDER => midi.slider_targets[id] = value,
+        BUTTON => {
+            let press_time = midi.press_time(id);
+            let button = &mut midi.buttons[id];
+            if live {
+                match (button.0 == 0.0, value == 0.0) {
+                    (true, false) => {
+                        button.1 = press_time;
+                        button.3 += 1;
+                    }
+                    (false, true) => button.2 = Instant::now(),
+                    _ => (),
+                }
+            }
+            button.0 = value;
+        }
+        _ => (),
+    }
+}
@@ -1,10 +1,136 @@
-use std::time::Instant;
+use std::{
+    collections::VecDeque,
+    time::{Duration, Instant},
+};
+
+use crate::util::RunningAverage;
+
+/// Number of past beats exposed to the shaders
+pub const BEAT_HISTORY_N: usize = 8;
+
+/// A source of the tempo driving the `beat` uniform
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TempoSource {
+    /// Tempo tapped or typed in by hand
+    Tap,
+    /// Clock messages sent by a MIDI device
+    MidiClock,
+}
+
+impl TempoSource {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "tap" => Some(Self::Tap),
+            "midi_clock" => Some(Self::MidiClock),
+            _ => None,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Tap => "tap",
+            Self::MidiClock => "midi_clock",
+        }
+    }
+}
+
+/// Number of tap intervals kept for judging how steady the tapping is
+pub const TAP_HISTORY_N: usize = 8;
+
+/// Number of beat-synced LFOs exposed to the shaders
+pub const LFO_N: usize = 8;
+
+/// Waveform of a beat-synced LFO
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LfoShape {
+    Sine,
+    Triangle,
+    Saw,
+    Square,
+}
+
+impl LfoShape {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "sine" => Some(Self::Sine),
+            "triangle" => Some(Self::Triangle),
+            "saw" => Some(Self::Saw),
+            "square" => Some(Self::Square),
+            _ => None,
+        }
+    }
+}
+
+/// A low frequency oscillator locked to the beat
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Lfo {
+    pub shape: LfoShape,
+    /// Length of one cycle in beats
+    pub beats: f32,
+}
+
+impl Lfo {
+    pub fn new(shape: LfoShape, beats: f32) -> Self {
+        Self { shape, beats }
+    }
+
+    /// Value between 0 and 1 at the given beat, every cycle starts at 0
+    pub fn value(&self, beat: f32) -> f32 {
+        let phase = (beat / self.beats).fract();
+        match self.shape {
+            LfoShape::Sine => 0.5 - 0.5 * (std::f32::consts::TAU * phase).cos(),
+            LfoShape::Triangle => 1.0 - (2.0 * phase - 1.0).abs(),
+            LfoShape::Saw => phase,
+            LfoShape::Square => (phase >= 0.5) as u32 as f32,
+        }
+    }
+}
+
+/// A percussive envelope for kick drums
+///
+/// Jumps to 1 on every beat and decays to 0 over a fraction of the beat,
+/// staying at 0 until the next beat.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct KickEnvelope {
+    /// Length of the decay as a fraction of one beat
+    pub length: f32,
+    /// Exponent of the decay, 1 is linear and larger values fall off faster
+    pub curve: f32,
+}
+
+impl Default for KickEnvelope {
+    fn default() -> Self {
+        Self {
+            length: 0.5,
+            curve: 2.0,
+        }
+    }
+}
+
+impl KickEnvelope {
+    /// Value between 0 and 1 at the given beat
+    pub fn value(&self, beat: f32) -> f32 {
+        let t = beat.fract() / self.length;
+        if t >= 1.0 {
+            return 0.0;
+        }
+
+        (1.0 - t).powf(self.curve)
+    }
+}
 
 #[derive(Debug, Clone)]
 pub struct BeatSync {
     pub first: Instant,
     pub last: Instant,
     pub count: u32,
+    pub tempo: Option<f32>,
+    /// Time between the most recent taps in milliseconds
+    pub taps: RunningAverage<f32, TAP_HISTORY_N>,
+    /// Points in time of the most recent beats, latest first
+    pub beats: VecDeque<Instant>,
+    /// Index of the last beat pushed to `beats` by `update`
+    pub beat_index: f32,
 }
 
 impl BeatSync {
@@ -15,6 +141,10 @@ impl BeatSync {
             first: now,
             last: now,
             count: 0,
+            tempo: None,
+            taps: RunningAverage::new(),
+            beats: VecDeque::with_capacity(BEAT_HISTORY_N),
+            beat_index: 0.0,
         }
     }
 
@@ -24,17 +154,95 @@ impl BeatSync {
 
     pub fn trigger(&mut self) {
         let now = Instant::now();
-        if now.duration_since(self.last).as_secs_f32() > 2.0 {
+        let previous = self.last;
+
+        // the first tap has no interval to the one before
+        if self.tempo.take().is_some()
+            || self.count == 0
+            || now.duration_since(self.last).as_secs_f32() > 2.0
+        {
             self.first = now;
             self.count = 0;
+            self.taps = RunningAverage::new();
+        } else {
+            let interval = now.duration_since(self.last).as_secs_f32();
+            self.taps.push(1000.0 * interval);
         }
 
         self.last = now;
         self.count += 1;
+
+        // a tap replaces the interpolated beat it was meant to hit
+        let half_beat = Duration::from_secs_f32(0.5 / self.rate());
+        if let Some(&beat) = self.beats.front() {
+            if beat > previous && now.duration_since(beat) < half_beat {
+                self.beats.pop_front();
+            }
+        }
+        self.push_beat(now);
+        self.beat_index = self.beat().round();
+    }
+
+    /// Records the beats that passed since the last call.
+    ///
+    /// Must be called regularly, e.g. once per frame.
+    pub fn update(&mut self) {
+        let index = self.beat().floor();
+        if index > self.beat_index {
+            let offset = Duration::from_secs_f32(index / self.rate());
+            self.push_beat(self.first + offset);
+            self.beat_index = index;
+        } else if index < self.beat_index - 1.0 {
+            // the phase was reset, taps just before a beat round the index up instead
+            self.beat_index = index;
+        }
+    }
+
+    fn push_beat(&mut self, time: Instant) {
+        if self.beats.len() == BEAT_HISTORY_N {
+            self.beats.pop_back();
+        }
+        self.beats.push_front(time);
+    }
+
+    /// Sets a fixed tempo while keeping the current beat phase.
+    ///
+    /// The manual tempo is discarded by the next call to `trigger`.
+    pub fn set_bpm(&mut self, bpm: f32) {
+        if !bpm.is_finite() || bpm <= 0.0 {
+            return;
+        }
+
+        let beat = self.beat();
+        let rate = bpm / 60.0;
+        let now = Instant::now();
+
+        self.first = now
+            .checked_sub(Duration::from_secs_f32(beat / rate))
+            .unwrap_or(now);
+        self.tempo = Some(bpm);
+    }
+
+    /// Shifts the beat phase by the given number of seconds.
+    ///
+    /// Positive values move the beat forward, negative values hold it back.
+    pub fn nudge(&mut self, seconds: f32) {
+        let offset = Duration::from_secs_f32(seconds.abs());
+        let shift = |t: Instant| match seconds > 0.0 {
+            true => t.checked_sub(offset).unwrap_or(t),
+            false => t + offset,
+        };
+
+        self.first = shift(self.first);
+        self.last = shift(self.last);
     }
 
     /// Average number of beats per seconds
     pub fn rate(&self) -> f32 {
+        if let Some(bpm) = self.tempo {
+            return bpm / 60.0;
+        }
+
         let deltas = self.count.saturating_sub(1);
         if deltas > 1 {
             deltas as f32 / self.last.duration_since(self.first).as_secs_f32()
@@ -52,6 +260,33 @@ impl BeatSync {
     pub fn beat(&self) -> f32 {
         self.rate() * self.first.elapsed().as_secs_f32()
     }
+
+    /// Point in time of the next beat
+    pub fn next_beat(&self) -> Instant {
+        let remaining = (1.0 - self.beat().fract()) / self.rate();
+        Instant::now() + Duration::from_secs_f32(remaining)
+    }
+
+    /// Time between the most recent taps in milliseconds, oldest first
+    pub fn tap_intervals(&self) -> Vec<f32> {
+        let taps = &self.taps;
+        match taps.len < TAP_HISTORY_N {
+            true => taps.values().to_vec(),
+            false => [&taps.buffer[taps.index..], &taps.buffer[..taps.index]].concat(),
+        }
+    }
+
+    /// Seconds since each of the most recent beats, latest first
+    ///
+    /// Beats that didn't happen yet are reported as -1.
+    pub fn history(&self) -> [f32; BEAT_HISTORY_N] {
+        let mut out = [-1.0; BEAT_HISTORY_N];
+        for (time, beat) in out.iter_mut().zip(self.beats.iter()) {
+            *time = beat.elapsed().as_secs_f32();
+        }
+
+        out
+    }
 }
 
 #[cfg(test)]
@@ -73,5 +308,112 @@ mod test {
 
         assert!(sync.beat().sub(2.0).abs() < 0.2, "{}", sync.beat());
         assert!(sync.rate().sub(3.0).abs() < 0.2, "{}", sync.rate());
+        assert_eq!(sync.tap_intervals().len(), 2);
+    }
+
+    #[test]
+    fn tap_intervals_oldest_first() {
+        let mut sync = BeatSync::new();
+        for k in 0..TAP_HISTORY_N + 3 {
+            sync.taps.push(k as f32);
+        }
+
+        let intervals = sync.tap_intervals();
+        assert_eq!(intervals.len(), TAP_HISTORY_N);
+        assert_eq!(intervals[0], 3.0);
+        assert_eq!(intervals[TAP_HISTORY_N - 1], (TAP_HISTORY_N + 2) as f32);
+    }
+
+    #[test]
+    fn kick_envelope() {
+        let kick = KickEnvelope {
+            length: 0.25,
+            curve: 1.0,
+        };
+
+        assert_eq!(kick.value(3.0), 1.0);
+        assert!(
+            kick.value(3.125).sub(0.5).abs() < 1e-6,
+            "{}",
+            kick.value(3.125)
+        );
+        assert_eq!(kick.value(3.25), 0.0);
+        assert_eq!(kick.value(3.9), 0.0);
+    }
+
+    #[test]
+    fn manual_tempo() {
+        let mut sync = BeatSync::new();
+        std::thread::sleep(Duration::from_millis(100));
+
+        let before = sync.beat();
+        sync.set_bpm(120.0);
+        assert!(sync.bpm().sub(120.0).abs() < 1e-3, "{}", sync.bpm());
+        assert!(sync.beat().sub(before).abs() < 0.05, "{}", sync.beat());
+
+        sync.nudge(0.25);
+        assert!(
+            sync.beat().sub(before + 0.5).abs() < 0.05,
+            "{}",
+            sync.beat()
+        );
+
+        sync.nudge(-0.25);
+        assert!(sync.beat().sub(before).abs() < 0.05, "{}", sync.beat());
+
+        sync.trigger();
+        assert_eq!(sync.tempo, None);
+    }
+
+    #[test]
+    fn history() {
+        let mut sync = BeatSync::new();
+        assert_eq!(sync.history(), [-1.0; BEAT_HISTORY_N]);
+
+        sync.trigger();
+        std::thread::sleep(Duration::from_millis(200));
+        sync.trigger();
+
+        let history = sync.history();
+        assert!(history[0] < 0.05, "{:?}", history);
+        assert!(
+            history[1].sub(history[0]).sub(0.2).abs() < 0.05,
+            "{:?}",
+            history
+        );
+        assert_eq!(history[2], -1.0);
+    }
+
+    #[test]
+    fn history_between_taps() {
+        let mut sync = BeatSync::new();
+        sync.set_bpm(600.0);
+        sync.update();
+        assert_eq!(sync.history()[0], -1.0);
+
+        std::thread::sleep(Duration::from_millis(150));
+        sync.update();
+
+        let history = sync.history();
+        assert!(history[0] < 0.1, "{:?}", history);
+        assert_eq!(history[1], -1.0);
+    }
+
+    #[test]
+    fn lfo_shapes() {
+        let sine = Lfo::new(LfoShape::Sine, 2.0);
+        assert!(sine.value(0.0).abs() < 1e-6);
+        assert!(sine.value(1.0).sub(1.0).abs() < 1e-6);
+
+        let triangle = Lfo::new(LfoShape::Triangle, 1.0);
+        assert!(triangle.value(0.25).sub(0.5).abs() < 1e-6);
+        assert!(triangle.value(1.5).sub(1.0).abs() < 1e-6);
+
+        let saw = Lfo::new(LfoShape::Saw, 0.5);
+        assert!(saw.value(0.125).sub(0.25).abs() < 1e-6);
+
+        let square = Lfo::new(LfoShape::Square, 4.0);
+        assert_eq!(square.value(1.0), 0.0);
+        assert_eq!(square.value(3.0), 1.0);
     }
 }
@@ -1,10 +1,79 @@
+use std::convert::TryFrom;
+
 use anyhow::{format_err, Result};
 use serde_yaml::Value;
 
-#[derive(Debug, Default, Clone)]
+use super::{KickEnvelope, Lfo, LfoShape, TempoSource, LFO_N, MIDI_N};
+
+#[derive(Debug, Clone)]
 pub struct Config {
     pub midi_devices: Vec<String>,
     pub audio_device: Option<String>,
+    pub window_title: Option<String>,
+    pub window_icon: Option<String>,
+    pub borderless: bool,
+    pub always_on_top: bool,
+    /// Position of the top left corner of the output window on the desktop
+    pub window_position: Option<[i32; 2]>,
+    pub tempo_sources: Vec<TempoSource>,
+    pub msaa: u16,
+    pub lfos: Vec<Lfo>,
+    pub kick: KickEnvelope,
+    pub target_fps: Option<f32>,
+    pub unfocused_fps: Option<f32>,
+    pub transport_port: Option<u16>,
+    pub output_swizzle: Option<[usize; 4]>,
+    pub slider_smoothing: Vec<f32>,
+    pub quantized_buttons: Vec<usize>,
+    pub outputs: Vec<OutputConfig>,
+}
+
+/// An additional window showing a single buffer of the pipeline
+#[derive(Debug, Clone, PartialEq)]
+pub struct OutputConfig {
+    pub buffer: String,
+    pub title: Option<String>,
+    /// Region of the buffer shown in the window, as x, y, width and height in `[0, 1]`
+    pub crop: [f32; 4],
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            midi_devices: Vec::new(),
+            audio_device: None,
+            window_title: None,
+            window_icon: None,
+            borderless: false,
+            always_on_top: false,
+            window_position: None,
+            tempo_sources: vec![TempoSource::MidiClock, TempoSource::Tap],
+            msaa: 0,
+            lfos: default_lfos(),
+            kick: KickEnvelope::default(),
+            target_fps: None,
+            unfocused_fps: None,
+            transport_port: None,
+            output_swizzle: None,
+            slider_smoothing: Vec::new(),
+            quantized_buttons: Vec::new(),
+            outputs: Vec::new(),
+        }
+    }
+}
+
+/// LFOs used when the config doesn't define any
+fn default_lfos() -> Vec<Lfo> {
+    vec![
+        Lfo::new(LfoShape::Sine, 0.25),
+        Lfo::new(LfoShape::Sine, 0.5),
+        Lfo::new(LfoShape::Sine, 1.0),
+        Lfo::new(LfoShape::Sine, 2.0),
+        Lfo::new(LfoShape::Triangle, 1.0),
+        Lfo::new(LfoShape::Saw, 1.0),
+        Lfo::new(LfoShape::Square, 1.0),
+        Lfo::new(LfoShape::Saw, 4.0),
+    ]
 }
 
 impl Config {
@@ -61,6 +130,340 @@ impl Config {
             }
         };
 
+        let window_title = match object.get("window_title") {
+            Some(Value::String(s)) => Some(s.clone()),
+            None => None,
+            s => {
+                return Err(format_err!(
+                    "Expected window_title to be a string, got: {:?}",
+                    s
+                ))
+            }
+        };
+
+        let window_icon = match object.get("window_icon") {
+            Some(Value::String(s)) => Some(s.clone()),
+            None => None,
+            s => {
+                return Err(format_err!(
+                    "Expected window_icon to be a path to an image, got: {:?}",
+                    s
+                ))
+            }
+        };
+
+        let borderless = match object.get("borderless") {
+            Some(Value::Bool(b)) => *b,
+            None => false,
+            s => {
+                return Err(format_err!(
+                    "Expected borderless to be a bool, got: {:?}",
+                    s
+                ))
+            }
+        };
+
+        let always_on_top = match object.get("always_on_top") {
+            Some(Value::Bool(b)) => *b,
+            None => false,
+            s => {
+                return Err(format_err!(
+                    "Expected always_on_top to be a bool, got: {:?}",
+                    s
+                ))
+            }
+        };
+
+        let window_position = match object.get("window_position") {
+            Some(Value::Sequence(xs)) if xs.len() == 2 => {
+                let mut position = [0; 2];
+                for (out, val) in position.iter_mut().zip(xs) {
+                    *out = match val.as_i64().and_then(|n| i32::try_from(n).ok()) {
+                        Some(n) => n,
+                        None => {
+                            return Err(format_err!(
+                                "Expected window_position to be a list of 2 integers, got: {:?}",
+                                xs
+                            ))
+                        }
+                    };
+                }
+                Some(position)
+            }
+            None => None,
+            s => {
+                return Err(format_err!(
+                    "Expected window_position to be a list of 2 integers, got: {:?}",
+                    s
+                ))
+            }
+        };
+
+        // sources earlier in the list take priority, as long as they're active
+        let tempo_sources = match object.get("tempo_source") {
+            Some(Value::String(s)) => vec![s.as_str()],
+            Some(Value::Sequence(xs)) => xs.iter().filter_map(Value::as_str).collect(),
+            None => vec!["midi_clock", "tap"],
+            s => {
+                return Err(format_err!(
+                    "Expected tempo_source to be a string or a list of strings, got: {:?}",
+                    s
+                ))
+            }
+        };
+
+        let mut tempo_sources = tempo_sources
+            .into_iter()
+            .map(|name| {
+                TempoSource::from_name(name).ok_or_else(|| {
+                    format_err!(
+                        "Expected tempo source to be \"midi_clock\" or \"tap\", got: {:?}",
+                        name
+                    )
+                })
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        // tapping is always possible, so it's the last resort
+        if !tempo_sources.contains(&TempoSource::Tap) {
+            tempo_sources.push(TempoSource::Tap);
+        }
+
+        // number of samples per pixel of the output window, 0 disables it
+        let msaa = match object.get("msaa").map(Value::as_u64) {
+            Some(Some(n)) if n <= 16 && (n == 0 || n.is_power_of_two()) => n as _,
+            None => 0,
+            Some(_) => {
+                return Err(format_err!(
+                    "Expected msaa to be one of 0, 1, 2, 4, 8 or 16, got: {:?}",
+                    object.get("msaa")
+                ))
+            }
+        };
+
+        let lfos = match object.get("lfos") {
+            Some(Value::Sequence(xs)) => {
+                if xs.len() > LFO_N {
+                    return Err(format_err!(
+                        "Expected at most {} lfos, got {}",
+                        LFO_N,
+                        xs.len()
+                    ));
+                }
+
+                let mut lfos = Vec::with_capacity(xs.len());
+                for val in xs {
+                    let shape = match val.get("shape").map(Value::as_str) {
+                        Some(Some(name)) => LfoShape::from_name(name).ok_or_else(|| {
+                            format_err!(
+                                "Expected lfo shape to be \"sine\", \"triangle\", \"saw\" or \"square\", got: {:?}",
+                                name
+                            )
+                        })?,
+                        None => LfoShape::Sine,
+                        Some(None) => {
+                            return Err(format_err!(
+                                "Expected lfo shape to be a string, got: {:?}",
+                                val.get("shape")
+                            ))
+                        }
+                    };
+
+                    let beats = match val.get("beats").map(Value::as_f64) {
+                        Some(Some(n)) if n > 0.0 => n as _,
+                        None => 1.0,
+                        Some(_) => {
+                            return Err(format_err!(
+                                "Expected lfo beats to be a positive number, got: {:?}",
+                                val.get("beats")
+                            ))
+                        }
+                    };
+
+                    lfos.push(Lfo::new(shape, beats));
+                }
+                lfos
+            }
+            None => default_lfos(),
+            Some(s) => {
+                return Err(format_err!(
+                    "Expected lfos to be a list of lfos, got: {:?}",
+                    s
+                ))
+            }
+        };
+
+        // decay of the beat_kick uniform
+        let kick = match object.get("kick") {
+            Some(val @ Value::Mapping(_)) => {
+                let mut kick = KickEnvelope::default();
+
+                match val.get("length").map(Value::as_f64) {
+                    Some(Some(n)) if n > 0.0 && n <= 1.0 => kick.length = n as _,
+                    None => {}
+                    Some(_) => {
+                        return Err(format_err!(
+                            "Expected kick length to be a number between 0 and 1, got: {:?}",
+                            val.get("length")
+                        ))
+                    }
+                }
+
+                match val.get("curve").map(Value::as_f64) {
+                    Some(Some(n)) if n > 0.0 => kick.curve = n as _,
+                    None => {}
+                    Some(_) => {
+                        return Err(format_err!(
+                            "Expected kick curve to be a positive number, got: {:?}",
+                            val.get("curve")
+                        ))
+                    }
+                }
+
+                kick
+            }
+            None => KickEnvelope::default(),
+            Some(s) => {
+                return Err(format_err!(
+                    "Expected kick to be a map with length and curve, got: {:?}",
+                    s
+                ))
+            }
+        };
+
+        let target_fps = match object.get("target_fps").map(Value::as_f64) {
+            Some(Some(fps)) if fps > 0.0 => Some(fps as _),
+            None => None,
+            Some(_) => {
+                return Err(format_err!(
+                    "Expected target_fps to be a positive number, got: {:?}",
+                    object.get("target_fps")
+                ))
+            }
+        };
+
+        // frame rate limit while none of the windows has focus
+        let unfocused_fps = match object.get("unfocused_fps").map(Value::as_f64) {
+            Some(Some(fps)) if fps > 0.0 => Some(fps as _),
+            None => None,
+            Some(_) => {
+                return Err(format_err!(
+                    "Expected unfocused_fps to be a positive number, got: {:?}",
+                    object.get("unfocused_fps")
+                ))
+            }
+        };
+
+        // port to receive the playback position from an external transport on
+        let transport_port = match object.get("transport_port").map(Value::as_u64) {
+            Some(Some(n)) if n > 0 && n <= u16::MAX as u64 => Some(n as _),
+            None => None,
+            Some(_) => {
+                return Err(format_err!(
+                    "Expected transport_port to be a port number, got: {:?}",
+                    object.get("transport_port")
+                ))
+            }
+        };
+
+        // channel order of the output window, e.g. "bgr" for some LED walls
+        let output_swizzle = match object.get("output_swizzle") {
+            Some(Value::String(s)) => Some(parse_swizzle(s).ok_or_else(|| {
+                format_err!(
+                    "Expected output_swizzle to be 3 or 4 of the letters r, g, b and a, got: {:?}",
+                    s
+                )
+            })?),
+            None => None,
+            s => {
+                return Err(format_err!(
+                    "Expected output_swizzle to be a string, got: {:?}",
+                    s
+                ))
+            }
+        };
+
+        // keeping the channels in place doesn't need an extra pass
+        let output_swizzle = output_swizzle.filter(|&s| s != [0, 1, 2, 3]);
+
+        // the swizzle copies the window, which can't be done from a multisampled one
+        if output_swizzle.is_some() && msaa > 0 {
+            return Err(format_err!("output_swizzle can't be combined with msaa"));
+        }
+
+        // time constant in seconds for all sliders, or one for each slider
+        let slider_smoothing = match object.get("slider_smoothing") {
+            Some(Value::Number(n)) => vec![n.as_f64().unwrap_or_default() as f32; MIDI_N],
+            Some(Value::Sequence(xs)) if xs.len() <= MIDI_N => xs
+                .iter()
+                .map(|x| x.as_f64().map(|f| f as f32))
+                .collect::<Option<Vec<_>>>()
+                .ok_or_else(|| {
+                    format_err!(
+                        "Expected slider_smoothing to be a list of numbers, got: {:?}",
+                        xs
+                    )
+                })?,
+            None => Vec::new(),
+            s => {
+                return Err(format_err!(
+                    "Expected slider_smoothing to be a number or a list of at most {} numbers, got: {:?}",
+                    MIDI_N,
+                    s
+                ))
+            }
+        };
+
+        if slider_smoothing.iter().any(|&tau| tau < 0.0) {
+            return Err(format_err!(
+                "Expected slider_smoothing to be positive, got: {:?}",
+                slider_smoothing
+            ));
+        }
+
+        // buttons whose presses only take effect on the next beat
+        let mut quantized_buttons = Vec::new();
+        match object.get("quantized_buttons") {
+            Some(Value::Sequence(xs)) => {
+                for val in xs {
+                    match val.as_u64() {
+                        Some(id) if (id as usize) < MIDI_N => quantized_buttons.push(id as _),
+                        _ => {
+                            return Err(format_err!(
+                                "Expected button id {:?} to be a number between 0 and {}",
+                                val,
+                                MIDI_N - 1
+                            ))
+                        }
+                    }
+                }
+            }
+            None => {}
+            Some(s) => {
+                return Err(format_err!(
+                    "Expected quantized_buttons to be a list of button ids, got: {:?}",
+                    s
+                ))
+            }
+        };
+
+        // extra windows, e.g. for a second projector
+        let mut outputs = Vec::new();
+        match object.get("outputs") {
+            Some(Value::Sequence(xs)) => {
+                for val in xs {
+                    outputs.push(parse_output(val)?);
+                }
+            }
+            None => {}
+            Some(s) => {
+                return Err(format_err!(
+                    "Expected outputs to be a list of windows, got: {:?}",
+                    s
+                ))
+            }
+        };
+
         let mut ndi_sources = Vec::new();
         match object.get("ndi_sources") {
             Some(Value::Sequence(xs)) => {
@@ -88,6 +491,87 @@ impl Config {
         Ok(Self {
             midi_devices,
             audio_device,
+            window_title,
+            window_icon,
+            borderless,
+            always_on_top,
+            window_position,
+            tempo_sources,
+            msaa,
+            lfos,
+            kick,
+            target_fps,
+            unfocused_fps,
+            transport_port,
+            output_swizzle,
+            slider_smoothing,
+            quantized_buttons,
+            outputs,
         })
     }
 }
+
+/// Parses an entry of the outputs list, like `{buffer: "left", crop: [0, 0, 0.5, 1]}`.
+fn parse_output(object: &Value) -> Result<OutputConfig> {
+    let buffer = match object.get("buffer") {
+        Some(Value::String(s)) => s.clone(),
+        s => {
+            return Err(format_err!(
+                "Expected buffer of output to be a string, got: {:?}",
+                s
+            ))
+        }
+    };
+
+    let title = match object.get("title") {
+        Some(Value::String(s)) => Some(s.clone()),
+        None => None,
+        s => {
+            return Err(format_err!(
+                "Expected title of output to be a string, got: {:?}",
+                s
+            ))
+        }
+    };
+
+    let crop = match object.get("crop") {
+        Some(Value::Sequence(xs)) => match xs.iter().map(Value::as_f64).collect::<Vec<_>>()[..] {
+            [Some(x), Some(y), Some(w), Some(h)] if w > 0.0 && h > 0.0 => {
+                [x as f32, y as f32, w as f32, h as f32]
+            }
+            _ => {
+                return Err(format_err!(
+                    "Expected crop of output to be a list of x, y, width and height, got: {:?}",
+                    xs
+                ))
+            }
+        },
+        None => [0.0, 0.0, 1.0, 1.0],
+        s => {
+            return Err(format_err!(
+                "Expected crop of output to be a list of x, y, width and height, got: {:?}",
+                s
+            ))
+        }
+    };
+
+    Ok(OutputConfig {
+        buffer,
+        title,
+        crop,
+    })
+}
+
+/// Parses a swizzle like "bgr" into channel indices, alpha is kept if omitted.
+fn parse_swizzle(s: &str) -> Option<[usize; 4]> {
+    let mut channels = [0, 1, 2, 3];
+    if !(3..=4).contains(&s.len()) {
+        return None;
+    }
+
+    for (channel, c) in channels.iter_mut().zip(s.chars()) {
+        *channel = "rgba".find(c)?;
+    }
+
+    Some(channels)
+}
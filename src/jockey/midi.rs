@@ -3,27 +3,73 @@ use std::{
     io::Write,
     path::{Path, PathBuf},
     sync::mpsc::{channel, Receiver},
-    time::Instant,
+    time::{Duration, Instant},
 };
 
+use gl::types::GLint;
 use midir::{Ignore, MidiInput, MidiInputConnection, MidiInputPort};
 
 use super::Config;
+use crate::util::RunningAverage;
 
 pub const MIDI_N: usize = 32;
 
+/// Number of held notes exposed to the shaders
+pub const HELD_NOTES_N: usize = 16;
+
+/// Index of the slider reserved as crossfader, exposed as `xfade`
+pub const XFADE_SLIDER: usize = MIDI_N - 1;
+
+/// Binding id of the slider controlling the playback speed,
+/// stored alongside the regular slider bindings
+pub const SPEED_BINDING: usize = MIDI_N;
+
+/// Playback speed when the speed slider is all the way up
+pub const SPEED_MAX: f32 = 2.0;
+
+/// Number of clock messages per quarter note
+const CLOCK_PPQN: usize = 24;
+
+/// Time between checks for newly plugged in or removed MIDI devices
+const CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Maps MIDI messages to the id of the slider or button they control
+type Bindings = HashMap<[u8; 2], usize>;
+
+/// Contents of the midi config file, the bindings and the selected device
+type MidiSettings = (Bindings, Bindings, Option<String>);
+
 pub struct Midi {
     pub conns: Vec<MidiInputConnection<()>>,
     pub queues: Vec<Receiver<[u8; 3]>>,
+    pub devices: Vec<String>,
+    pub ports: Vec<String>,
+    pub selected_device: Option<String>,
     pub last_button: [u8; 2],
     pub last_slider: [u8; 2],
     pub sliders: [f32; MIDI_N],
+    pub slider_targets: [f32; MIDI_N],
+    pub slider_smoothing: [f32; MIDI_N],
     pub buttons: [(f32, Instant, Instant, u32); MIDI_N],
-    pub button_bindings: HashMap<[u8; 2], usize>,
-    pub slider_bindings: HashMap<[u8; 2], usize>,
+    pub button_quantize: [bool; MIDI_N],
+    pub next_beat: Instant,
+    pub button_bindings: Bindings,
+    pub slider_bindings: Bindings,
+    pub program: Option<u8>,
+    pub speed: Option<f32>,
+    pub clock_started: bool,
+    /// Channel and key of the notes held down, in the order they were pressed
+    pub held_notes: Vec<[u8; 2]>,
+    last_msb: Option<([u8; 2], u8)>,
+    last_tick: Option<Instant>,
+    tick_count: usize,
+    tick_interval: RunningAverage<f32, CLOCK_PPQN>,
     preferred_devices: Vec<String>,
     config_file: Option<PathBuf>,
     port_count: usize,
+    last_check: Instant,
+    last_smooth: Instant,
+    backend_failed: bool,
 }
 
 #[derive(Debug, Clone, Copy)]
@@ -32,6 +78,9 @@ pub enum MessageKind {
     NoteOff { channel: u8, key: u8, _velocity: u8 },
     KeyPressure { channel: u8, key: u8, pressure: u8 },
     ControlChange { channel: u8, key: u8, value: u8 },
+    ProgramChange { channel: u8, program: u8 },
+    Clock,
+    Start,
 }
 
 impl Midi {
@@ -41,16 +90,37 @@ impl Midi {
         let buttons = [(0.0, now, now, 0); MIDI_N];
         let mut button_bindings = HashMap::new();
         let mut slider_bindings = HashMap::new();
+        let mut selected_device = None;
 
         let config_file = base_path.map(|path| path.join("midi-config.dat"));
         let preferred_devices = config.midi_devices.clone();
 
+        let mut button_quantize = [false; MIDI_N];
+        for &id in config.quantized_buttons.iter() {
+            button_quantize[id] = true;
+        }
+
+        // sliders without a time constant in the config follow right away
+        let mut slider_smoothing = [0.0; MIDI_N];
+        for (tau, &value) in slider_smoothing
+            .iter_mut()
+            .zip(config.slider_smoothing.iter())
+        {
+            *tau = value;
+        }
+
         if let Some(path) = &config_file {
-            if let Ok(file) = std::fs::File::open(path) {
-                match serde_yaml::from_reader(file) {
-                    Ok((b, s)) => {
+            if let Ok(text) = std::fs::read_to_string(path) {
+                // files written before devices could be selected only hold the bindings
+                let parsed = serde_yaml::from_str::<MidiSettings>(&text).or_else(|_| {
+                    serde_yaml::from_str::<(Bindings, Bindings)>(&text).map(|(b, s)| (b, s, None))
+                });
+
+                match parsed {
+                    Ok((b, s, d)) => {
                         button_bindings = b;
                         slider_bindings = s;
+                        selected_device = d;
                         log::info!("Loaded midi bindings successfully");
                     }
                     _ => log::error!(
@@ -63,28 +133,66 @@ impl Midi {
         let mut this = Self {
             conns: Vec::new(),
             queues: Vec::new(),
+            devices: Vec::new(),
+            ports: Vec::new(),
+            selected_device,
             last_button: [0, 0],
             last_slider: [0, 0],
             sliders,
+            slider_targets: sliders,
+            slider_smoothing,
             buttons,
+            button_quantize,
+            next_beat: now,
             button_bindings,
             slider_bindings,
+            program: None,
+            speed: None,
+            clock_started: false,
+            held_notes: Vec::new(),
+            last_msb: None,
+            last_tick: None,
+            tick_count: 0,
+            tick_interval: RunningAverage::new(),
             preferred_devices,
             config_file,
             port_count: 0,
+            last_check: now,
+            last_smooth: now,
+            backend_failed: false,
         };
 
         this.connect();
         this
     }
 
-    pub fn check_connections(&mut self) {
-        let midi_in = match MidiInput::new("Sh4derJockey") {
-            Ok(s) => s,
+    /// Creates a MIDI input, only logging the first of consecutive failures.
+    fn create_input(&mut self) -> Option<MidiInput> {
+        match MidiInput::new("Sh4derJockey") {
+            Ok(s) => {
+                self.backend_failed = false;
+                Some(s)
+            }
             Err(err) => {
-                log::error!("Failed to create Midi input: {:?}", err);
-                return;
+                if !self.backend_failed {
+                    log::error!("Failed to create Midi input: {:?}", err);
+                    self.backend_failed = true;
+                }
+                None
             }
+        }
+    }
+
+    /// Reconnects to all devices once MIDI devices were plugged in or removed.
+    pub fn check_connections(&mut self) {
+        if self.last_check.elapsed() < CHECK_INTERVAL {
+            return;
+        }
+        self.last_check = Instant::now();
+
+        let midi_in = match self.create_input() {
+            Some(s) => s,
+            None => return,
         };
 
         if midi_in.port_count() == self.port_count {
@@ -93,28 +201,41 @@ impl Midi {
 
         self.conns = Vec::new();
         self.queues = Vec::new();
+        self.devices = Vec::new();
         self.connect();
     }
 
     pub fn connect(&mut self) {
-        let mut midi_in = match MidiInput::new("Sh4derJockey") {
-            Ok(s) => s,
-            Err(err) => {
-                log::error!("Failed to create Midi input: {:?}", err);
-                return;
-            }
+        let mut midi_in = match self.create_input() {
+            Some(s) => s,
+            None => return,
         };
 
         midi_in.ignore(Ignore::None);
 
         // Get an input port (read from console if multiple are available)
         let mut in_ports = midi_in.ports();
+        self.ports = in_ports
+            .iter()
+            .map(|port| midi_in.port_name(port).unwrap_or_default())
+            .collect();
+
         if midi_in.port_count() == 0 {
             log::warn!("No midi input port found.");
+            self.port_count = 0;
             return;
         }
 
-        if !self.preferred_devices.is_empty() {
+        // a device picked in the ui takes priority over the config file,
+        // as long as it's plugged in
+        let selected = self
+            .selected_device
+            .as_ref()
+            .filter(|name| self.ports.contains(name));
+
+        if let Some(name) = selected {
+            in_ports.retain(|port| midi_in.port_name(port).ok().as_ref() == Some(name));
+        } else if !self.preferred_devices.is_empty() {
             in_ports.retain(|port| {
                 self.preferred_devices
                     .iter()
@@ -124,11 +245,13 @@ impl Midi {
 
         let mut conns = Vec::new();
         let mut queues = Vec::new();
+        let mut devices = Vec::new();
         for in_port in in_ports.iter() {
             match self.new_connection(in_port) {
                 Ok((conn, rx)) => {
                     conns.push(conn);
                     queues.push(rx);
+                    devices.push(midi_in.port_name(in_port).unwrap_or_default());
                 }
 
                 Err(code) => {
@@ -141,6 +264,7 @@ impl Midi {
 
         self.conns = conns;
         self.queues = queues;
+        self.devices = devices;
         self.port_count = midi_in.port_count();
     }
 
@@ -165,12 +289,15 @@ impl Midi {
                 in_port,
                 format!("sh4der-jockey-read-input-{}", port_name).as_str(),
                 move |_, message, _| {
-                    if message.len() != 3 {
+                    // program changes only carry a single data byte,
+                    // clock messages have none at all
+                    if !(1..=3).contains(&message.len()) {
                         return;
                     }
                     let mut out = [0; 3];
-                    out.copy_from_slice(message);
-                    tx.send(out).unwrap();
+                    out[..message.len()].copy_from_slice(message);
+                    // the receiver is gone while reconnecting
+                    tx.send(out).ok();
                 },
                 (),
             )
@@ -184,6 +311,13 @@ impl Midi {
             let data0 = message[1];
             let data1 = message[2];
 
+            // system real-time messages
+            match status {
+                0xF8 => return Some(MessageKind::Clock),
+                0xFA => return Some(MessageKind::Start),
+                _ => (),
+            }
+
             let kind_bits = 0xF0_u8 & status;
             let channel = status & 0x0F_u8;
             match kind_bits {
@@ -211,6 +345,11 @@ impl Midi {
                     value: data1,
                 }),
 
+                0xC0 => Some(MessageKind::ProgramChange {
+                    channel,
+                    program: data0,
+                }),
+
                 _ => None,
             }
         }
@@ -230,14 +369,21 @@ impl Midi {
                             key,
                             velocity,
                         } => {
+                            // a note on without velocity releases the note
+                            self.held_notes.retain(|&note| note != [channel, key]);
+                            if velocity > 0 {
+                                self.held_notes.push([channel, key]);
+                            }
+
                             self.last_button = [channel, key];
                             if let Some(&id) = self.button_bindings.get(&self.last_button) {
                                 self.buttons[id].0 = velocity as f32 / 127.0;
-                                self.buttons[id].1 = Instant::now();
+                                self.buttons[id].1 = self.press_time(id);
                                 self.buttons[id].3 += 1;
                             }
                         }
                         MessageKind::NoteOff { channel, key, .. } => {
+                            self.held_notes.retain(|&note| note != [channel, key]);
                             self.last_button = [channel, key];
                             if let Some(&id) = self.button_bindings.get(&self.last_button) {
                                 self.buttons[id].0 = 0.0;
@@ -259,10 +405,46 @@ impl Midi {
                             key,
                             value,
                         } => {
-                            self.last_slider = [channel, key];
-                            if let Some(&id) = self.slider_bindings.get(&self.last_slider) {
-                                self.sliders[id] = value as f32 / 127.0;
+                            // 14-bit controllers send the LSB on key + 32 right after the MSB
+                            let (slider, value) = match self.last_msb.take() {
+                                Some(([c, k], msb)) if c == channel && k + 32 == key => {
+                                    let fine = (msb as u16) << 7 | value as u16;
+                                    ([c, k], fine as f32 / 16383.0)
+                                }
+                                _ => {
+                                    if key < 32 {
+                                        self.last_msb = Some(([channel, key], value));
+                                    }
+
+                                    self.last_slider = [channel, key];
+                                    ([channel, key], value as f32 / 127.0)
+                                }
+                            };
+
+                            match self.slider_bindings.get(&slider) {
+                                Some(&SPEED_BINDING) => self.speed = Some(SPEED_MAX * value),
+                                Some(&id) => self.slider_targets[id] = value,
+                                None => (),
+                            }
+                        }
+                        MessageKind::ProgramChange { program, .. } => {
+                            self.program = Some(program);
+                        }
+                        MessageKind::Clock => {
+                            let now = Instant::now();
+                            match self.last_tick {
+                                // start over if the clock was lost in between
+                                Some(last) if now.duration_since(last).as_secs_f32() < 0.5 => {
+                                    let interval = now.duration_since(last).as_secs_f32();
+                                    self.tick_interval.push(interval);
+                                    self.tick_count += 1;
+                                }
+                                _ => self.tick_count = 0,
                             }
+                            self.last_tick = Some(now);
+                        }
+                        MessageKind::Start => {
+                            self.clock_started = true;
                         }
                     },
                 }
@@ -270,6 +452,56 @@ impl Midi {
         }
     }
 
+    /// Keys of the most recently pressed notes that are still held, oldest
+    /// first, and how many of them there are. Unused entries are -1.
+    pub fn held_keys(&self) -> ([GLint; HELD_NOTES_N], usize) {
+        let skip = self.held_notes.len().saturating_sub(HELD_NOTES_N);
+        let mut keys = [-1; HELD_NOTES_N];
+        for (out, &[_, key]) in keys.iter_mut().zip(&self.held_notes[skip..]) {
+            *out = key as _;
+        }
+
+        (keys, self.held_notes.len() - skip)
+    }
+
+    /// Moves the sliders towards the last values received, using a one-pole
+    /// lowpass filter, so the steps between MIDI values don't show.
+    pub fn smooth_sliders(&mut self) {
+        let now = Instant::now();
+        let delta = now.duration_since(self.last_smooth).as_secs_f32();
+        self.last_smooth = now;
+
+        let sliders = self.sliders.iter_mut().zip(self.slider_targets.iter());
+        for ((slider, &target), &tau) in sliders.zip(self.slider_smoothing.iter()) {
+            *slider = match tau > 0.0 {
+                true => *slider + (target - *slider) * (1.0 - (-delta / tau).exp()),
+                false => target,
+            };
+        }
+    }
+
+    /// Time from which a press of the given button counts,
+    /// presses of quantized buttons wait for the next beat.
+    pub fn press_time(&self, id: usize) -> Instant {
+        match self.button_quantize[id] {
+            true => self.next_beat,
+            false => Instant::now(),
+        }
+    }
+
+    /// Tempo of the incoming MIDI clock, if there is one.
+    ///
+    /// The clock is considered lost if no message arrived for half a second.
+    pub fn clock_bpm(&self) -> Option<f32> {
+        let last = self.last_tick?;
+        if self.tick_count < CLOCK_PPQN || last.elapsed().as_secs_f32() > 0.5 {
+            return None;
+        }
+
+        let quarter_note = CLOCK_PPQN as f32 * self.tick_interval.get();
+        Some(60.0 / quarter_note)
+    }
+
     fn store_bindings(&self) {
         let Some(path) = &self.config_file else {
             return;
@@ -284,7 +516,11 @@ impl Midi {
                     return;
                 }
 
-                let tuple = (&self.button_bindings, &self.slider_bindings);
+                let tuple = (
+                    &self.button_bindings,
+                    &self.slider_bindings,
+                    &self.selected_device,
+                );
                 match serde_yaml::to_writer(file, &tuple) {
                     Ok(_) => log::info!("Stored midi bindings successfully"),
                     Err(err) => log::error!("Failed to store midi bindings: {:?}", err),
@@ -293,8 +529,28 @@ impl Midi {
         }
     }
 
+    /// Only listens to the given device from now on, or to all devices
+    /// allowed by the config file if `None` is given.
+    ///
+    /// Bindings are kept, so they work across devices sending the same messages.
+    pub fn select_device(&mut self, device: Option<String>) {
+        if self.selected_device == device {
+            return;
+        }
+
+        self.selected_device = device;
+        self.store_bindings();
+
+        // close the old connections before opening new ones
+        self.conns = Vec::new();
+        self.queues = Vec::new();
+        self.devices = Vec::new();
+        self.last_msb = None;
+        self.connect();
+    }
+
     pub fn bind_slider(&mut self, id: usize) {
-        if id < MIDI_N {
+        if id < MIDI_N || id == SPEED_BINDING {
             self.slider_bindings.retain(|_, bid| *bid != id);
             self.slider_bindings.insert(self.last_slider, id);
             self.store_bindings();
@@ -310,7 +566,7 @@ impl Midi {
     }
 
     pub fn unbind_slider(&mut self, id: usize) {
-        if id < MIDI_N {
+        if id < MIDI_N || id == SPEED_BINDING {
             self.slider_bindings.retain(|_, bid| *bid != id);
             self.store_bindings();
         }
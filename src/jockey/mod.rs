@@ -1,5 +1,5 @@
 use std::{
-    collections::{hash_map::DefaultHasher, HashMap},
+    collections::{hash_map::DefaultHasher, BTreeMap, HashMap},
     ffi::CString,
     future::Future,
     hash::{Hash, Hasher},
@@ -8,7 +8,10 @@ use std::{
     path::{Path, PathBuf},
     pin::Pin,
     rc::Rc,
-    sync::atomic::{AtomicBool, Ordering},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc, Mutex,
+    },
     time::{Duration, Instant},
 };
 
@@ -21,32 +24,57 @@ use notify::Watcher;
 use crate::util::*;
 
 mod audio;
+mod automation;
 mod beatsync;
 mod config;
 mod midi;
 mod network;
+mod output;
 mod pipeline;
 mod stage;
+mod timings;
+mod track;
+mod transport;
 mod uniforms;
+mod video;
 
 pub use audio::*;
+pub use automation::*;
 pub use beatsync::*;
 pub use config::*;
 pub use midi::*;
 pub use network::*;
+pub use output::*;
 pub use pipeline::*;
 pub use stage::*;
+pub use timings::*;
+pub use track::*;
+pub use transport::*;
 pub use uniforms::*;
+pub use video::*;
 
 static mut PIPELINE_STALE: AtomicBool = AtomicBool::new(false);
 static mut PROJECT_STALE: AtomicBool = AtomicBool::new(false);
 
+/// Files written by the tool itself, like the log, changes to them don't
+/// trigger a rebuild
+static OUTPUT_FILES: Mutex<Vec<PathBuf>> = Mutex::new(Vec::new());
+
+/// Stops changes to a file written by the tool from rebuilding the pipeline.
+pub fn ignore_output_file(path: &Path) {
+    match canonical_file(path) {
+        Some(path) => OUTPUT_FILES.lock().unwrap().push(path),
+        None => log::warn!("Failed to resolve output file {:?}", path),
+    }
+}
+
 /// A struct for all the ugly internals.
 pub struct MegaContext {
     pub imgui: imgui::Context,
     pub renderer: imgui_opengl_renderer::Renderer,
     pub vao: GLuint,
     pub vbo: GLuint,
+    pub ebo: GLuint,
     pub watcher: Option<notify::RecommendedWatcher>,
     pub context: glutin::WindowedContext<glutin::PossiblyCurrent>,
     pub ui_context: glutin::WindowedContext<glutin::PossiblyCurrent>,
@@ -64,6 +92,15 @@ pub struct Jockey {
     pub done: bool,
     pub frame_perf: RunningAverage<f32, 128>,
     pub beat_sync: BeatSync,
+    pub tempo_sources: Vec<TempoSource>,
+    pub tempo_source: TempoSource,
+    pub lfos: Vec<Lfo>,
+    pub kick: KickEnvelope,
+    pub target_fps: Option<f32>,
+    pub unfocused_fps: Option<f32>,
+    pub focused: bool,
+    /// Set by the UI, the render targets are reset on the next event update
+    pub reset_requested: bool,
     pub last_build: Instant,
     pub last_frame: Instant,
     pub last_frame_ui: Instant,
@@ -71,6 +108,11 @@ pub struct Jockey {
     pub midi: Midi,
     pub audio: Audio,
     pub ndi: Ndi,
+    pub transport: Transport,
+    pub automation: Automation,
+    pub track: Option<Track>,
+    /// Track picked in the UI, decoded on a background thread
+    pub pending_track: Option<mpsc::Receiver<Result<TrackData, String>>>,
     pub pipeline_files: Vec<String>,
     pub pipeline_index: usize,
     pub pipeline: Pipeline,
@@ -78,6 +120,7 @@ pub struct Jockey {
     pub time: f32,
     pub time_since_build: f32,
     pub speed: f32,
+    pub fixed_dt: Option<f32>,
     pub time_range: (f32, f32),
     pub custom_res: (i32, i32),
     pub custom_ratio: (i32, i32),
@@ -85,6 +128,22 @@ pub struct Jockey {
     pub frame: u32,
     pub alt_pressed: bool,
     pub console: String,
+    pub timings: Option<StageTimings>,
+    pub safe_mode: bool,
+    pub show_metrics: bool,
+    pub show_demo: bool,
+    pub output_swizzle: Option<OutputSwizzle>,
+    pub supersample: Option<Supersample>,
+    pub show_pass: ShowPass,
+    pub outputs: Vec<OutputWindow>,
+    pub output_configs: Vec<OutputConfig>,
+    pub mouse_pos: Option<[f32; 2]>,
+    pub probe_target: Option<CString>,
+    pub probe_color: Option<[f32; 4]>,
+    pub pending_resize: Option<((u32, u32), Instant)>,
+    pub refresh_rate: f32,
+    pub common_block: UniformBuffer,
+    pub gl_info: GlInfo,
 }
 
 impl std::fmt::Debug for Jockey {
@@ -98,6 +157,38 @@ impl std::fmt::Debug for Jockey {
 }
 
 static CONFIG_ENV: &'static str = "SH4DER_DIR";
+static WINDOW_TITLE: &'static str = "Sh4derJockey";
+
+/// Gitignore-style list of files which don't trigger a rebuild
+static IGNORE_FILE: &'static str = ".sh4derignore";
+
+/// Number of frames rendered before a benchmark starts measuring
+const BENCH_WARMUP_FRAMES: usize = 30;
+
+/// Time without resize events before the render targets are reallocated
+const RESIZE_DEBOUNCE: Duration = Duration::from_millis(150);
+
+/// Refresh rate assumed when the monitor doesn't report one
+const DEFAULT_REFRESH_RATE: f32 = 60.0;
+
+/// Difference in seconds between the transport and a loaded track before the track is moved
+const TRACK_SEEK_THRESHOLD: f32 = 0.1;
+
+/// Draws the latest result of a stage with `show` to the screen `fb`.
+fn show_target(
+    show_pass: &ShowPass,
+    vao: GLuint,
+    stage: &Stage,
+    buffers: &HashMap<CString, Rc<dyn Texture>>,
+    fb: GLuint,
+    [width, height]: [u32; 2],
+) {
+    if let Some(tex) = stage.target.as_ref().filter(|_| stage.show) {
+        if let Some(tex) = buffers.get(tex) {
+            show_pass.draw(vao, tex.as_ref(), fb, width, height);
+        }
+    }
+}
 
 fn config_folder_path() -> Option<PathBuf> {
     // Fetch config folder path from enviroment variable
@@ -151,12 +242,137 @@ fn config_folder_path() -> Option<PathBuf> {
     None
 }
 
+/// Loads the icon of the output window from an image file.
+fn load_window_icon(path: &str) -> Option<glutin::window::Icon> {
+    let image = match image::open(path) {
+        Ok(image) => image.to_rgba8(),
+        Err(err) => {
+            log::error!("Failed to load window icon {}: {}", path, err);
+            return None;
+        }
+    };
+
+    let (width, height) = image.dimensions();
+    match glutin::window::Icon::from_rgba(image.into_raw(), width, height) {
+        Ok(icon) => Some(icon),
+        Err(err) => {
+            log::error!("Invalid window icon {}: {}", path, err);
+            None
+        }
+    }
+}
+
+/// Moves the top left corner of the window, including its decorations, to a
+/// position on the desktop in physical pixels.
+pub fn move_window(window: &glutin::window::Window, [x, y]: [i32; 2]) {
+    window.set_outer_position(glutin::dpi::PhysicalPosition::new(x, y));
+}
+
+/// Refresh rate of the monitor the window is currently on.
+///
+/// winit doesn't expose the active video mode, so this takes the fastest mode
+/// matching the monitor's current size.
+fn monitor_refresh_rate(window: &glutin::window::Window) -> f32 {
+    let monitor = match window.current_monitor() {
+        Some(s) => s,
+        None => return DEFAULT_REFRESH_RATE,
+    };
+
+    let size = monitor.size();
+    monitor
+        .video_modes()
+        .filter(|mode| mode.size() == size)
+        .map(|mode| mode.refresh_rate())
+        .filter(|&rate| rate > 0)
+        .max()
+        .map(|rate| rate as f32)
+        .unwrap_or(DEFAULT_REFRESH_RATE)
+}
+
+/// Lists all pipeline files in the current working directory.
+///
+/// The files are sorted by name, so they can be selected by index.
+fn find_pipeline_files() -> Vec<String> {
+    let mut files: Vec<_> = std::fs::read_dir(".")
+        .unwrap()
+        .map(|s| s.unwrap().file_name().into_string().unwrap())
+        .filter(|s| s.ends_with(".yaml"))
+        .filter(|s| s != "config.yaml")
+        .collect();
+
+    files.sort();
+    files
+}
+
+/// Builds every pipeline in the current working directory without opening
+/// the control panel or connecting to any MIDI or audio devices.
+///
+/// Returns `true` if all pipelines were built successfully.
+pub fn validate_pipelines() -> bool {
+    let events_loop = glutin::event_loop::EventLoop::new();
+    let window_builder = glutin::window::WindowBuilder::new()
+        .with_inner_size(glutin::dpi::LogicalSize::new(1280.0, 720.0))
+        .with_visible(false)
+        .with_title("Sh4derJockey");
+
+    let built_context = glutin::ContextBuilder::new()
+        .with_gl(glutin::GlRequest::Latest)
+        .build_windowed(window_builder, &events_loop)
+        .expect("Failed to create windowed context");
+
+    let context = unsafe {
+        built_context
+            .make_current()
+            .expect("Failed to activate windowed context")
+    };
+
+    gl::load_with(|s| context.get_proc_address(s) as _);
+
+    let screen_size = context.window().inner_size();
+    let screen_size = (screen_size.width, screen_size.height);
+
+    let pipeline_files = find_pipeline_files();
+    if pipeline_files.is_empty() {
+        log::error!("Failed to find pipeline file");
+        return false;
+    }
+
+    let mut failed = 0;
+    for path in pipeline_files.iter() {
+        let mut partial: Pin<PipelinePartial> =
+            Box::pin(Pipeline::load(path.to_owned(), screen_size));
+
+        let result = loop {
+            if let Some(result) = futures::FutureExt::now_or_never(&mut partial) {
+                break result;
+            }
+        };
+
+        match result {
+            Ok(_) => log::info!("Built pipeline {} successfully", path),
+            Err(err) => {
+                log::error!("Failed to build pipeline {}:\n{}", path, err);
+                failed += 1;
+            }
+        }
+    }
+
+    println!(
+        "{} of {} pipelines built successfully",
+        pipeline_files.len() - failed,
+        pipeline_files.len()
+    );
+
+    failed == 0
+}
+
 impl Jockey {
     /// Initializes the tool.
     ///
     /// This will spin up a Winit window, initialize Imgui,
     /// create a OpenGL context and more!
-    pub fn init() -> Self {
+    /// With `gl_debug`, the context is created as a debug context.
+    pub fn init(gl_debug: bool) -> Self {
         let config_folder_path = config_folder_path();
         match &config_folder_path {
             Some(path) => log::info!("Using config folder: {}", path.to_string_lossy()),
@@ -214,14 +430,26 @@ impl Jockey {
         Self::init_imgui_style(imgui.style_mut());
 
         // Set up winit for OpenGL stuff
+        // drivers only report most of their messages to debug contexts
         let context_builder = glutin::ContextBuilder::new()
             .with_vsync(true)
-            .with_gl(request);
+            .with_stencil_buffer(8)
+            .with_gl(request)
+            .with_gl_debug_flag(gl_debug);
+
+        // smooth edges of geometry drawn straight to the window
+        let context_builder = match config.msaa {
+            0 => context_builder,
+            samples => context_builder.with_multisampling(samples),
+        };
 
         let window_builder = glutin::window::WindowBuilder::new()
             .with_inner_size(glutin::dpi::LogicalSize::new(1280.0, 720.0))
             .with_resizable(true)
-            .with_title("Sh4derJockey");
+            .with_title(config.window_title.as_deref().unwrap_or(WINDOW_TITLE))
+            .with_window_icon(config.window_icon.as_deref().and_then(load_window_icon))
+            .with_decorations(!config.borderless)
+            .with_always_on_top(config.always_on_top);
 
         #[cfg(target_os = "windows")]
         let window_builder = glutin::platform::windows::WindowBuilderExtWindows::with_drag_and_drop(
@@ -239,16 +467,36 @@ impl Jockey {
                 .expect("Failed to activate windowed context")
         };
 
+        if let Some(position) = config.window_position {
+            move_window(context.window(), position);
+        }
+
         let prog_addr = |s| context.get_proc_address(s) as _;
         gl::load_with(prog_addr);
 
+        let gl_info = GlInfo::query();
+        log::info!("OpenGL version: {}", gl_info.version);
+        log::info!("OpenGL renderer: {} ({})", gl_info.renderer, gl_info.vendor);
+        log::info!("GLSL version: {}", gl_info.glsl_version);
+        log::info!("Texture units: {}", gl_info.max_texture_units);
+        match gl_info.max_patch_vertices {
+            0 => log::warn!("Tessellation is not supported by this context"),
+            n => log::info!("Max patch vertices: {}", n),
+        }
+
         // setup OpenGL
         let mut vao = 0;
         let mut vbo = 0;
+        let mut ebo = 0;
 
         unsafe {
             gl::GenVertexArrays(1, &mut vao);
             gl::GenBuffers(1, &mut vbo);
+            gl::GenBuffers(1, &mut ebo);
+
+            if config.msaa > 0 {
+                gl::Enable(gl::MULTISAMPLE);
+            }
             gl_debug_check!();
         }
 
@@ -259,6 +507,7 @@ impl Jockey {
             renderer,
             vao,
             vbo,
+            ebo,
             watcher: None,
             context,
             ui_context,
@@ -269,15 +518,26 @@ impl Jockey {
         let pipeline = Pipeline::splash_screen();
         let midi = Midi::new(&config, config_folder_path.as_deref());
         let ndi = Ndi::with_config_path(config_folder_path.clone());
+        let transport = Transport::new(config.transport_port);
 
         let console = "No pipeline has been built yet".into();
 
         let now = Instant::now();
+        let refresh_rate = monitor_refresh_rate(ctx.context.window());
+        let common_block = UniformBuffer::new(std::mem::size_of::<CommonBlock>());
         let mut this = Self {
             ctx,
             done: false,
             frame_perf,
             beat_sync: BeatSync::new(),
+            tempo_sources: config.tempo_sources.clone(),
+            tempo_source: TempoSource::Tap,
+            lfos: config.lfos.clone(),
+            kick: config.kick,
+            target_fps: config.target_fps,
+            unfocused_fps: config.unfocused_fps,
+            focused: true,
+            reset_requested: false,
             last_build: now,
             last_frame: now,
             last_frame_ui: now,
@@ -285,6 +545,10 @@ impl Jockey {
             midi,
             audio,
             ndi,
+            transport,
+            automation: Automation::new(),
+            track: None,
+            pending_track: None,
             pipeline_files: Vec::new(),
             pipeline,
             pipeline_index: 0,
@@ -292,6 +556,7 @@ impl Jockey {
             time: 0.0,
             time_since_build: 0.0,
             speed: 1.0,
+            fixed_dt: None,
             time_range: (0.0, 60.0),
             custom_res: (512, 512),
             custom_ratio: (1, 1),
@@ -299,9 +564,27 @@ impl Jockey {
             frame: 0,
             alt_pressed: false,
             console,
+            timings: None,
+            safe_mode: false,
+            show_metrics: false,
+            show_demo: false,
+            output_swizzle: None,
+            supersample: None,
+            show_pass: ShowPass::new(),
+            outputs: Vec::new(),
+            output_configs: Vec::new(),
+            mouse_pos: None,
+            probe_target: None,
+            probe_color: None,
+            pending_resize: None,
+            refresh_rate,
+            common_block,
+            gl_info,
         };
 
+        this.open_outputs(&config.outputs);
         this.ctx.context = unsafe { this.ctx.context.make_current().unwrap() };
+        this.output_swizzle = config.output_swizzle.map(OutputSwizzle::new);
         this.update_pipeline();
         gl_debug_check!();
         this
@@ -375,12 +658,7 @@ impl Jockey {
     /// successfully, the new Pipeline struct will stomp the old one.
     pub fn update_pipeline(&mut self) {
         // find pipeline files in working directory
-        self.pipeline_files = std::fs::read_dir(".")
-            .unwrap()
-            .map(|s| s.unwrap().file_name().into_string().unwrap())
-            .filter(|s| s.ends_with(".yaml"))
-            .filter(|s| s != "config.yaml")
-            .collect();
+        self.pipeline_files = find_pipeline_files();
 
         log::info!("Found pipeline files: {:?}", &self.pipeline_files);
 
@@ -405,11 +683,15 @@ impl Jockey {
         self.pipeline_partial = Some(Box::pin(Pipeline::load(path.to_owned(), screen_size)));
     }
 
-    fn update_pipeline_incremental(&mut self, timeout: Duration) {
+    /// Continues building the pending pipeline until it's done or the
+    /// timeout is reached.
+    ///
+    /// Returns the error message if the build finished but failed.
+    fn update_pipeline_incremental(&mut self, timeout: Duration) -> Result<(), String> {
         let start = Instant::now();
         while let Some(part) = self.pipeline_partial.as_mut() {
             if start.elapsed() > timeout {
-                return;
+                return Ok(());
             }
 
             if let Some(result) = futures::FutureExt::now_or_never(part) {
@@ -417,7 +699,36 @@ impl Jockey {
 
                 // set waker on current working directory
                 self.ctx.watcher = Some({
-                    let event_fn = |_| unsafe { PIPELINE_STALE.store(true, Ordering::Release) };
+                    let root = std::env::current_dir().unwrap_or_default();
+                    let ignore = IgnoreList::load(IGNORE_FILE);
+                    let event_fn = move |event: notify::Result<notify::Event>| {
+                        // only rebuild if any of the changed files is relevant
+                        if let Ok(event) = event {
+                            let outputs = OUTPUT_FILES.lock().unwrap();
+                            let relevant = event.paths.iter().any(|path| {
+                                let output = canonical_file(path)
+                                    .map_or(false, |path| outputs.contains(&path));
+                                let path = path.strip_prefix(&root).unwrap_or(path);
+                                !output && !ignore.is_ignored(path)
+                            });
+
+                            if !relevant {
+                                return;
+                            }
+
+                            // the config file applies to the whole project
+                            let config = event.paths.iter().any(|path| {
+                                path.strip_prefix(&root).unwrap_or(path) == Path::new("config.yaml")
+                            });
+
+                            if config {
+                                unsafe { PROJECT_STALE.store(true, Ordering::Release) }
+                            }
+                        }
+
+                        unsafe { PIPELINE_STALE.store(true, Ordering::Release) }
+                    };
+
                     let mut watcher = notify::recommended_watcher(event_fn).unwrap();
                     watcher
                         .watch(Path::new("."), notify::RecursiveMode::Recursive)
@@ -432,41 +743,220 @@ impl Jockey {
                     Err(err) => {
                         self.console = format!("Failed to build pipeline:\n{}", err);
                         log::error!("{}", &self.console);
-                        return;
+                        return Err(err);
                     }
                 };
 
                 // set new pipeline
-                self.pipeline = new_pipeline;
+                self.set_pipeline(new_pipeline, update);
 
                 // log build time
                 let build_time = self.last_build.elapsed().as_secs_f64();
                 self.console = format!("Build pipeline over a span of {}s", build_time);
                 log::info!("{}", &self.console);
 
-                // toggle blend modes
-                unsafe {
-                    match self.pipeline.blending {
-                        true => gl::Enable(gl::BLEND),
-                        false => gl::Disable(gl::BLEND),
-                    }
-                    gl_debug_check!();
+                for warning in self.pipeline.warnings.iter() {
+                    log::warn!("{}", warning);
+                    self.console += &format!("\nWarning: {}", warning);
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Blocks until the pipeline requested on startup has been built.
+    ///
+    /// Returns an error if no pipeline was found or the build failed.
+    pub fn wait_for_pipeline(&mut self) -> Result<(), String> {
+        if self.pipeline_partial.is_none() {
+            return Err("No pipeline file found".into());
+        }
+
+        self.update_pipeline_incremental(Duration::MAX)
+    }
+
+    /// Lets the active tempo source with the highest priority drive the beat.
+    fn update_tempo(&mut self) {
+        let clock_bpm = self.midi.clock_bpm();
+        let source = self
+            .tempo_sources
+            .iter()
+            .copied()
+            .find(|source| match source {
+                TempoSource::MidiClock => clock_bpm.is_some(),
+                TempoSource::Tap => true,
+            })
+            .unwrap_or(TempoSource::Tap);
+
+        if source != self.tempo_source {
+            log::info!("Switching tempo source to {}", source.name());
+            self.tempo_source = source;
+        }
+
+        if let (TempoSource::MidiClock, Some(bpm)) = (source, clock_bpm) {
+            // keeps the current phase, so switching sources doesn't jump
+            self.beat_sync.set_bpm(bpm);
+
+            // the midi clock starts on a downbeat, which is kept until the
+            // clock has a tempo, as that takes a few ticks after the start
+            if std::mem::take(&mut self.midi.clock_started) {
+                self.beat_sync.first = Instant::now();
+            }
+        }
+    }
+
+    /// Replaces the current pipeline and applies its global settings.
+    fn set_pipeline(&mut self, pipeline: Pipeline, update: UpdateRequest) {
+        let mut old_pipeline = std::mem::replace(&mut self.pipeline, pipeline);
+
+        // keep the contents of persistent storage buffers, the empty ones
+        // of the new pipeline are deleted together with the old pipeline
+        for (name, buffer) in self.pipeline.storage.iter_mut() {
+            if let Some(old_buffer) = old_pipeline.storage.get_mut(name) {
+                if buffer.persistent && old_buffer.size == buffer.size {
+                    std::mem::swap(buffer, old_buffer);
                 }
+            }
+        }
+        drop(old_pipeline);
+
+        // toggle blend modes
+        unsafe {
+            match self.pipeline.blending {
+                true => gl::Enable(gl::BLEND),
+                false => gl::Disable(gl::BLEND),
+            }
+            gl_debug_check!();
+        }
+
+        // render the screen to a larger buffer first, if the pipeline asks for it
+        let size = self.ctx.context.window().inner_size();
+        self.supersample = match self.pipeline.supersample {
+            1 => None,
+            factor => Some(Supersample::new(factor, size.width, size.height)),
+        };
+
+        // copy audio configs
+        self.audio.attack = update.smoothing_attack;
+        self.audio.decay = update.smoothing_decay;
+        if update.audio_samples != self.audio.size {
+            self.audio.resize(update.audio_samples);
+        }
+
+        // update ndi module
+        let requests = self.pipeline.requested_ndi_sources.values();
+        if let Err(err) = self.ndi.connect(&requests) {
+            log::error!("Failed to connect to NDI sources: {}", err);
+        }
+    }
+
+    /// Renders a pipeline for a number of frames without the control panel
+    /// and prints a summary of the render times of each stage.
+    ///
+    /// Returns `true` if the pipeline was built and rendered successfully.
+    pub fn bench(&mut self, frames: usize, path: Option<&str>) -> bool {
+        self.ctx.ui_context.window().set_visible(false);
+        take_mut::take(&mut self.ctx.context, |s| unsafe {
+            s.make_current().unwrap()
+        });
 
-                // copy audio configs
-                self.audio.attack = update.smoothing_attack;
-                self.audio.decay = update.smoothing_decay;
-                if update.audio_samples != self.audio.size {
-                    self.audio.resize(update.audio_samples);
+        let path = match path {
+            Some(s) => s.to_owned(),
+            None => match find_pipeline_files().into_iter().next() {
+                Some(s) => s,
+                None => {
+                    log::error!("Failed to find pipeline file");
+                    return false;
                 }
+            },
+        };
 
-                // update ndi module
-                let requests = self.pipeline.requested_ndi_sources.values();
-                if let Err(err) = self.ndi.connect(&requests) {
-                    log::error!("Failed to connect to NDI sources: {}", err);
+        let screen_size = self.ctx.context.window().inner_size();
+        let screen_size = (screen_size.width as u32, screen_size.height as u32);
+
+        let mut partial: Pin<PipelinePartial> =
+            Box::pin(Pipeline::load(path.to_owned(), screen_size));
+        let result = loop {
+            if let Some(result) = futures::FutureExt::now_or_never(&mut partial) {
+                break result;
+            }
+        };
+
+        match result {
+            Ok((pipeline, update)) => self.set_pipeline(pipeline, update),
+            Err(err) => {
+                log::error!("Failed to build pipeline {}:\n{}", path, err);
+                return false;
+            }
+        }
+
+        // give drivers and caches some time to settle
+        for _ in 0..BENCH_WARMUP_FRAMES {
+            self.handle_events();
+            self.draw();
+        }
+
+        let stage_count = self.pipeline.stages.len();
+        let mut sums = vec![0.0; stage_count];
+        let mut mins = vec![f32::INFINITY; stage_count];
+        let mut maxs = vec![0.0_f32; stage_count];
+        let mut runs = vec![0_usize; stage_count];
+
+        let start = Instant::now();
+        for _ in 0..frames {
+            self.handle_events();
+            if self.done {
+                log::warn!("Benchmark was interrupted");
+                return false;
+            }
+
+            // stages which were skipped this frame don't push a new time
+            let indices: Vec<_> = self.pipeline.stages.iter().map(|s| s.perf.index).collect();
+            self.draw();
+
+            for (k, stage) in self.pipeline.stages.iter().enumerate() {
+                if stage.perf.index == indices[k] {
+                    continue;
                 }
+
+                let stage_ms = stage.perf.last();
+                runs[k] += 1;
+                sums[k] += stage_ms;
+                mins[k] = mins[k].min(stage_ms);
+                maxs[k] = maxs[k].max(stage_ms);
             }
         }
+        let elapsed = start.elapsed().as_secs_f32();
+
+        println!("Pipeline {} at {}x{}", path, screen_size.0, screen_size.1);
+        println!(
+            "{:>5}  {:<24} {:>10} {:>10} {:>10}",
+            "stage", "target", "avg [ms]", "min [ms]", "max [ms]"
+        );
+        for (k, stage) in self.pipeline.stages.iter().enumerate() {
+            let target = match &stage.target {
+                Some(name) => name.to_string_lossy(),
+                None => "screen".into(),
+            };
+
+            println!(
+                "{:>5}  {:<24} {:>10.4} {:>10.4} {:>10.4}",
+                k,
+                target,
+                sums[k] / runs[k].max(1) as f32,
+                mins[k].min(maxs[k]),
+                maxs[k]
+            );
+        }
+        println!(
+            "{} frames in {:.3}s ({:.2} fps)",
+            frames,
+            elapsed,
+            frames as f32 / elapsed
+        );
+
+        true
     }
 
     pub fn handle_events(&mut self) {
@@ -474,6 +964,15 @@ impl Jockey {
             s.make_current().unwrap()
         });
 
+        // save power while none of the windows has focus
+        if let Some(fps) = self.unfocused_fps.filter(|_| !self.focused) {
+            let frame_time = Duration::from_secs_f32(1.0 / fps);
+            let elapsed = self.last_frame.elapsed();
+            if elapsed < frame_time {
+                std::thread::sleep(frame_time - elapsed);
+            }
+        }
+
         let do_update_project = unsafe { PROJECT_STALE.swap(false, Ordering::AcqRel) };
 
         // reload all things that depend on the project-level config file
@@ -492,6 +991,41 @@ impl Jockey {
                 drop(audio);
                 Audio::new(AUDIO_SAMPLES, &config)
             });
+
+            self.tempo_sources = config.tempo_sources.clone();
+            self.lfos = config.lfos.clone();
+            self.kick = config.kick;
+            self.target_fps = config.target_fps;
+            self.unfocused_fps = config.unfocused_fps;
+
+            let swizzle = self.output_swizzle.as_ref().map(|s| s.channels);
+            if swizzle != config.output_swizzle {
+                self.output_swizzle = config.output_swizzle.map(OutputSwizzle::new);
+            }
+
+            // the socket must be closed before binding the same port again
+            if self.transport.port != config.transport_port {
+                take_mut::take(&mut self.transport, |transport| {
+                    drop(transport);
+                    Transport::new(config.transport_port)
+                });
+            }
+
+            let window = self.ctx.context.window();
+            window.set_title(config.window_title.as_deref().unwrap_or(WINDOW_TITLE));
+            window.set_window_icon(config.window_icon.as_deref().and_then(load_window_icon));
+            window.set_decorations(!config.borderless);
+            window.set_always_on_top(config.always_on_top);
+            if let Some(position) = config.window_position {
+                move_window(window, position);
+            }
+
+            if self.output_configs != config.outputs {
+                self.open_outputs(&config.outputs);
+                take_mut::take(&mut self.ctx.context, |s| unsafe {
+                    s.make_current().unwrap()
+                });
+            }
         }
 
         let platform = &mut self.ctx.platform;
@@ -499,14 +1033,79 @@ impl Jockey {
         let imgui = &mut self.ctx.imgui;
         let window = self.ctx.context.window();
         let ui_window = self.ctx.ui_context.window();
-        let pipeline = &mut self.pipeline;
+        let pending_resize = &mut self.pending_resize;
+        let refresh_rate = &mut self.refresh_rate;
         let alt_pressed = &mut self.alt_pressed;
+        let mouse_pos = &mut self.mouse_pos;
+        let focused = &mut self.focused;
         let mut done = false;
 
+        let output_ids: Vec<_> = self
+            .outputs
+            .iter()
+            .map(|o| o.context.window().id())
+            .collect();
+        let mut closed_outputs = Vec::new();
+
+        self.beat_sync.update();
         self.midi.check_connections();
+        self.midi.next_beat = self.beat_sync.next_beat();
         self.midi.handle_input();
+        self.automation.update(&mut self.midi, self.time);
+        self.midi.smooth_sliders();
+        self.update_tempo();
+
+        if let Some(speed) = self.midi.speed.take() {
+            self.speed = speed;
+        }
+
+        // an external transport overrides the local playback position,
+        // time keeps running at the given speed in between messages
+        self.transport.handle_input();
+        if let Some(speed) = self.transport.speed.take() {
+            self.speed = speed;
+        }
+        if let Some(time) = self.transport.time.take() {
+            self.time = time;
+            // only jump on larger differences, seeking interrupts the audio
+            if let Some(track) = self.track.as_mut() {
+                if (track.position() - time).abs() > TRACK_SEEK_THRESHOLD {
+                    track.seek(time);
+                }
+            }
+        }
+
+        // pick up a track once it's decoded, the dialog may have been cancelled
+        if let Some(receiver) = self.pending_track.as_ref() {
+            match receiver.try_recv() {
+                Ok(result) => {
+                    self.pending_track = None;
+                    match result.and_then(Track::new) {
+                        Ok(mut track) => {
+                            track.seek(self.time);
+                            self.track = Some(track);
+                        }
+                        Err(err) => log::error!("{}", err),
+                    }
+                }
+                Err(mpsc::TryRecvError::Disconnected) => self.pending_track = None,
+                Err(mpsc::TryRecvError::Empty) => {}
+            }
+        }
+
+        // a loaded track drives the timeline
+        if let Some(track) = self.track.as_ref() {
+            self.time = track.position();
+        }
+
+        // switch pipelines by midi program change
+        let mut select_pipeline = self.midi.program.take().map(|p| p as usize);
 
         let mut take_screenshot = false;
+        let mut export_buffer = false;
+        let mut bake_uniforms = false;
+        let mut clear_caches = false;
+        let mut reset_buffers = std::mem::take(&mut self.reset_requested);
         let mut do_update_pipeline = unsafe { PIPELINE_STALE.swap(false, Ordering::AcqRel) }
             && self.last_build.elapsed().as_millis() > 300;
 
@@ -524,12 +1123,43 @@ impl Jockey {
                     }
 
                     match event {
+                        // closing an output window keeps the program running
+                        glutin::event::WindowEvent::CloseRequested
+                            if output_ids.contains(&window_id) =>
+                        {
+                            closed_outputs.push(window_id);
+                        }
+
                         glutin::event::WindowEvent::CloseRequested => done = true,
 
+                        // focus moving between windows is lost and gained in turn
+                        glutin::event::WindowEvent::Focused(state) => *focused = *state,
+
+                        // defer reallocating the buffers until the user stops resizing
                         glutin::event::WindowEvent::Resized(size) if window_id == main_id => {
                             let width = size.width as u32;
                             let height = size.height as u32;
-                            pipeline.resize_buffers(width, height);
+                            *pending_resize = Some(((width, height), Instant::now()));
+                        }
+
+                        // the window might have moved to a different monitor
+                        glutin::event::WindowEvent::Moved(_) if window_id == main_id => {
+                            *refresh_rate = monitor_refresh_rate(window);
+                        }
+
+                        // track the mouse relative to the output window, with y pointing up
+                        glutin::event::WindowEvent::CursorMoved { position, .. }
+                            if window_id == main_id =>
+                        {
+                            let size = window.inner_size();
+                            *mouse_pos = Some([
+                                position.x as f32 / size.width as f32,
+                                1.0 - position.y as f32 / size.height as f32,
+                            ]);
+                        }
+
+                        glutin::event::WindowEvent::CursorLeft { .. } if window_id == main_id => {
+                            *mouse_pos = None;
                         }
 
                         #[allow(deprecated)]
@@ -547,6 +1177,12 @@ impl Jockey {
                                     do_update_pipeline = true;
                                 }
 
+                                // rebuild without reusing anything from earlier builds
+                                if ctrl && shift && !(alt || logo) {
+                                    clear_caches = true;
+                                    do_update_pipeline = true;
+                                }
+
                                 // toggle fullscreen mode
                                 if alt && !(shift || ctrl || logo) && window.id() == window_id {
                                     if window.fullscreen().is_some() {
@@ -563,6 +1199,31 @@ impl Jockey {
                                 }
                             }
 
+                            // switch pipelines with the number keys
+                            if input.state == glutin::event::ElementState::Pressed
+                                && window_id == main_id
+                                && !(shift || ctrl || alt || logo)
+                            {
+                                use glutin::event::VirtualKeyCode::*;
+                                let index = match input.virtual_keycode {
+                                    Some(Key1) => Some(0),
+                                    Some(Key2) => Some(1),
+                                    Some(Key3) => Some(2),
+                                    Some(Key4) => Some(3),
+                                    Some(Key5) => Some(4),
+                                    Some(Key6) => Some(5),
+                                    Some(Key7) => Some(6),
+                                    Some(Key8) => Some(7),
+                                    Some(Key9) => Some(8),
+                                    Some(Key0) => Some(9),
+                                    _ => None,
+                                };
+
+                                if index.is_some() {
+                                    select_pipeline = index;
+                                }
+                            }
+
                             if Some(glutin::event::VirtualKeyCode::S) == input.virtual_keycode
                                 && input.state == glutin::event::ElementState::Pressed
                             {
@@ -570,6 +1231,30 @@ impl Jockey {
                                     take_screenshot = true;
                                 }
                             }
+
+                            if Some(glutin::event::VirtualKeyCode::E) == input.virtual_keycode
+                                && input.state == glutin::event::ElementState::Pressed
+                                && ctrl
+                                && !(shift || alt || logo)
+                            {
+                                export_buffer = true;
+                            }
+
+                            if Some(glutin::event::VirtualKeyCode::G) == input.virtual_keycode
+                                && input.state == glutin::event::ElementState::Pressed
+                                && ctrl
+                                && !(shift || alt || logo)
+                            {
+                                bake_uniforms = true;
+                            }
+
+                            if Some(glutin::event::VirtualKeyCode::R) == input.virtual_keycode
+                                && input.state == glutin::event::ElementState::Pressed
+                                && ctrl
+                                && !(shift || alt || logo)
+                            {
+                                reset_buffers = true;
+                            }
                         }
 
                         _ => (),
@@ -582,10 +1267,48 @@ impl Jockey {
 
         self.done = done;
 
+        if !closed_outputs.is_empty() {
+            self.outputs
+                .retain(|o| !closed_outputs.contains(&o.context.window().id()));
+            take_mut::take(&mut self.ctx.context, |s| unsafe {
+                s.make_current().unwrap()
+            });
+        }
+
         if take_screenshot {
             self.save_frame();
         }
 
+        if export_buffer {
+            self.save_buffer_exr();
+        }
+
+        if bake_uniforms {
+            self.save_uniforms_glsl();
+        }
+
+        if reset_buffers {
+            self.reset_buffers();
+        }
+
+        if let Some(index) = select_pipeline {
+            match index < self.pipeline_files.len() {
+                true if index != self.pipeline_index => {
+                    log::info!("Switching to pipeline {}", &self.pipeline_files[index]);
+                    self.pipeline_index = index;
+                    do_update_pipeline = true;
+                }
+                true => (),
+                false => log::warn!("No pipeline file with index {}", index),
+            }
+        }
+
+        if clear_caches {
+            Cache::clear();
+            clear_source_cache();
+            log::warn!("Forced a full rebuild, all cached images and shader sources are reloaded");
+        }
+
         // live shader reloading hype
         if do_update_pipeline {
             self.update_pipeline();
@@ -604,17 +1327,41 @@ impl Jockey {
             s.make_current().unwrap()
         });
 
-        // build pipeline a little
-        self.update_pipeline_incremental(Duration::from_micros(50));
+        // build pipeline a little, errors are shown in the control panel
+        self.update_pipeline_incremental(Duration::from_micros(50))
+            .ok();
+
+        // resize buffers once the window size has settled
+        if let Some(((width, height), last)) = self.pending_resize {
+            if last.elapsed() > RESIZE_DEBOUNCE {
+                self.pipeline.resize_buffers(width, height);
+                self.pending_resize = None;
+            }
+        }
 
         // compute uniforms
         let screen_size = self.ctx.context.window().inner_size();
         let (width, height) = (screen_size.width as u32, screen_size.height as u32);
+        let dpi_scale = self.ctx.context.window().scale_factor() as f32;
+        let pass_count = self.pipeline.stages.len();
+        let refresh_rate = self.refresh_rate;
         let beat = self.beat_sync.beat();
+        let beat_phase = beat.fract();
+        let beat_sine = 0.5 + 0.5 * (std::f32::consts::TAU * beat_phase).cos();
+        let beat_kick = self.kick.value(beat);
+        let beat_history = self.beat_sync.history();
+        let mut lfo = [0.0; LFO_N];
+        for (value, osc) in lfo.iter_mut().zip(self.lfos.iter()) {
+            *value = osc.value(beat);
+        }
         let now = Instant::now();
         let time = self.time;
         let time_since_build = self.time_since_build;
-        let delta = self.speed * now.duration_since(self.last_frame).as_secs_f32();
+        let dt = match self.fixed_dt {
+            Some(dt) => dt,
+            None => now.duration_since(self.last_frame).as_secs_f32(),
+        };
+        let delta = self.speed * dt;
         let frame = self.frame;
         self.time += delta;
         self.time_since_build += delta;
@@ -622,8 +1369,11 @@ impl Jockey {
         self.frame = self.frame.wrapping_add(1);
 
         {
-            // update audio samples texture
-            self.audio.update_samples();
+            // update audio samples texture, a playing track replaces the input device
+            match self.track.as_ref().filter(|t| t.is_playing()) {
+                Some(track) => self.audio.update_samples_from(track),
+                None => self.audio.update_samples(),
+            }
             self.audio.update_fft();
 
             fn audio_tex_update(
@@ -659,6 +1409,16 @@ impl Jockey {
                 self.ndi.update_texture(src_name, tex);
             }
 
+            for (tex_name, video) in self.pipeline.videos.iter_mut() {
+                let tex = self.pipeline.buffers.get_mut(tex_name).unwrap();
+                let tex = Rc::get_mut(tex)
+                    .unwrap()
+                    .as_any_mut()
+                    .downcast_mut::<Texture2D>()
+                    .unwrap();
+                video.update_texture(time, tex);
+            }
+
             audio_tex_update(
                 &mut self.pipeline.buffers,
                 &SAMPLES_NAME,
@@ -695,12 +1455,112 @@ impl Jockey {
                 &self.audio.l_spectrum_integrated,
                 &self.audio.r_spectrum_integrated,
             );
+
+            self.pipeline.bump_live_versions();
+        }
+
+        {
+            // upload the uniforms shared by all stages at once
+            let vec3 = |v: [f32; 3]| [v[0], v[1], v[2], 0.0];
+            let audio = &self.audio;
+            let mut block = CommonBlock {
+                time,
+                time_since_build,
+                time_delta: delta,
+                frame_count: frame as _,
+                beat,
+                beat_phase,
+                beat_sine,
+                beat_kick: [beat_kick, 0.0, 0.0, 0.0],
+                xfade: self.midi.sliders[XFADE_SLIDER],
+                refresh_rate,
+                target_dt: 1.0 / refresh_rate,
+                dpi_scale,
+                volume: vec3(audio.volume),
+                volume_integrated: vec3(audio.volume_integrated),
+                bass: vec3(audio.bass),
+                bass_smooth: vec3(audio.bass_smooth),
+                bass_integrated: vec3(audio.bass_integrated),
+                bass_smooth_integrated: vec3(audio.bass_smooth_integrated),
+                mid: vec3(audio.mid),
+                mid_smooth: vec3(audio.mid_smooth),
+                mid_integrated: vec3(audio.mid_integrated),
+                mid_smooth_integrated: vec3(audio.mid_smooth_integrated),
+                high: vec3(audio.high),
+                high_smooth: vec3(audio.high_smooth),
+                high_integrated: vec3(audio.high_integrated),
+                high_smooth_integrated: vec3(audio.high_smooth_integrated),
+                ..Default::default()
+            };
+
+            for (k, &time) in beat_history.iter().enumerate() {
+                block.beat_history[k][0] = time;
+            }
+
+            for (k, &value) in lfo.iter().enumerate() {
+                block.lfo[k][0] = value;
+            }
+
+            for (k, &value) in self.midi.sliders.iter().enumerate() {
+                block.sliders[k][0] = value;
+            }
+
+            for (k, button) in self.midi.buttons.iter().enumerate() {
+                block.buttons[k] = [
+                    button.0,
+                    button.1.elapsed().as_secs_f32(),
+                    button.2.elapsed().as_secs_f32(),
+                    button.3 as f32,
+                ];
+            }
+
+            self.common_block.update(&block);
+            self.common_block.bind(COMMON_BLOCK_BINDING);
+        }
+
+        // the screen stages draw to the supersampling buffer instead of the window
+        let factor = self.pipeline.supersample;
+        let screen_fb = match self.supersample.as_mut() {
+            Some(supersample) => {
+                supersample.resize(width, height);
+                supersample.fb.fb_id
+            }
+            None => 0,
+        };
+
+        // start from a known state, unless the screen is used for feedback
+        if let Some([r, g, b, a]) = self.pipeline.clear_screen {
+            unsafe {
+                gl::BindFramebuffer(gl::FRAMEBUFFER, screen_fb);
+                gl::Viewport(0, 0, (factor * width) as _, (factor * height) as _);
+                gl::ClearColor(r, g, b, a);
+                gl::Clear(gl::COLOR_BUFFER_BIT);
+                gl_debug_check!();
+            }
         }
 
         // render all shader stages
         for (pass_num, stage) in self.pipeline.stages.iter_mut().enumerate() {
             let stage_start = Instant::now();
 
+            // shown targets are drawn every frame, the screen is cleared in between
+            let size = [factor * width, factor * height];
+            let (vao, buffers) = (self.ctx.vao, &self.pipeline.buffers);
+
+            // skip stage on frames it doesn't run, its target keeps the last result
+            let skip = (stage.once && stage.ran)
+                || (!stage.once && frame % stage.interval != 0)
+                // skip stage while the control it depends on is dialed out
+                || matches!(&stage.condition, Some(c) if !c.is_met(&self.midi))
+                // safe mode only runs plain fragment stages
+                || (self.safe_mode
+                    && (!matches!(stage.kind, StageKind::Frag { .. }) || stage.feedback()));
+
+            if skip {
+                show_target(&self.show_pass, vao, stage, buffers, screen_fb, size);
+                continue;
+            }
+
             // skip stage if target is never used
             if !matches!(stage.kind, StageKind::Comp { .. }) {
                 if let Some(name) = &stage.target {
@@ -710,163 +1570,173 @@ impl Jockey {
                 }
             }
 
+            stage.ran = true;
+
             // get size of the render target
             let target_res = match stage.resolution() {
                 Some(s) => s,
-                _ => [width, height, 0],
+                _ => [factor * width, factor * height, 0],
             };
+            let target_res = stage.mip_resolution(target_res);
+
+            // skip stage if nothing it reads changed since it last ran
+            if stage.skip_unchanged {
+                let versions = &self.pipeline.versions;
+                let inputs =
+                    StageInputs::new(stage, target_res, &self.midi, self.focused, versions);
+                if stage.last_inputs.as_ref() == Some(&inputs) {
+                    show_target(&self.show_pass, vao, stage, buffers, screen_fb, size);
+                    continue;
+                }
+                stage.last_inputs = Some(inputs);
+            }
 
             unsafe {
                 // Use shader program
                 gl::UseProgram(stage.prog_id);
                 gl_debug_check!();
 
-                {
-                    // Add time, beat, resolution and volume
-                    let r_loc = gl::GetUniformLocation(stage.prog_id, R_NAME.as_ptr());
-                    let k_loc = gl::GetUniformLocation(stage.prog_id, K_NAME.as_ptr());
-                    let res_loc = gl::GetUniformLocation(stage.prog_id, RESOLUTION_NAME.as_ptr());
-                    let pass_loc = gl::GetUniformLocation(stage.prog_id, PASS_INDEX_NAME.as_ptr());
-                    let time_loc = gl::GetUniformLocation(stage.prog_id, TIME_NAME.as_ptr());
-                    let time_since_build_loc =
-                        gl::GetUniformLocation(stage.prog_id, TIME_SINCE_BUILD_NAME.as_ptr());
-                    let frame_loc =
-                        gl::GetUniformLocation(stage.prog_id, FRAME_COUNT_NAME.as_ptr());
-                    let delta_loc = gl::GetUniformLocation(stage.prog_id, TIME_DELTA_NAME.as_ptr());
-                    let beat_loc = gl::GetUniformLocation(stage.prog_id, BEAT_NAME.as_ptr());
-                    let volume_loc = gl::GetUniformLocation(stage.prog_id, VOLUME_NAME.as_ptr());
-                    let volume_integrated_loc =
-                        gl::GetUniformLocation(stage.prog_id, VOLUME_INTEGRATED_NAME.as_ptr());
-                    let bass_loc = gl::GetUniformLocation(stage.prog_id, BASS_NAME.as_ptr());
-                    let mid_loc = gl::GetUniformLocation(stage.prog_id, MID_NAME.as_ptr());
-                    let high_loc = gl::GetUniformLocation(stage.prog_id, HIGH_NAME.as_ptr());
-                    let smooth_bass_loc =
-                        gl::GetUniformLocation(stage.prog_id, BASS_SMOOTH_NAME.as_ptr());
-                    let smooth_mid_loc =
-                        gl::GetUniformLocation(stage.prog_id, MID_SMOOTH_NAME.as_ptr());
-                    let smooth_high_loc =
-                        gl::GetUniformLocation(stage.prog_id, HIGH_SMOOTH_NAME.as_ptr());
-
-                    let bass_integrated_loc =
-                        gl::GetUniformLocation(stage.prog_id, BASS_INTEGRATED_NAME.as_ptr());
-                    let mid_integrated_loc =
-                        gl::GetUniformLocation(stage.prog_id, MID_INTEGRATED_NAME.as_ptr());
-                    let high_integrated_loc =
-                        gl::GetUniformLocation(stage.prog_id, HIGH_INTEGRATED_NAME.as_ptr());
-                    let smooth_bass_integrated_loc =
-                        gl::GetUniformLocation(stage.prog_id, BASS_SMOOTH_INTEGRATED_NAME.as_ptr());
-                    let smooth_mid_integrated_loc =
-                        gl::GetUniformLocation(stage.prog_id, MID_SMOOTH_INTEGRATED_NAME.as_ptr());
-                    let smooth_high_integrated_loc =
-                        gl::GetUniformLocation(stage.prog_id, HIGH_SMOOTH_INTEGRATED_NAME.as_ptr());
+                // Locations were looked up once when the stage was built
+                let locs = &stage.locs;
 
+                {
+                    // Add resolution and stage index
                     gl::Uniform4f(
-                        res_loc,
+                        locs.resolution,
                         target_res[0] as f32,
                         target_res[1] as f32,
                         target_res[0] as f32 / target_res[1] as f32, // x/y
                         target_res[1] as f32 / target_res[0] as f32, // x/y
                     );
-                    gl::Uniform3f(r_loc, target_res[0] as _, target_res[1] as _, time);
+                    gl::Uniform2f(
+                        locs.aspect,
+                        target_res[0] as f32 / target_res[1] as f32, // x/y
+                        1.0,
+                    );
+                    gl::Uniform1i(locs.pass_index, pass_num as _);
+                    gl::Uniform1i(locs.pass_count, pass_count as _);
+                    gl::Uniform1i(locs.focused, self.focused as _);
+                    gl::Uniform1i(locs.first_frame, self.pipeline.first_frame as _);
+                    gl_debug_check!();
+
+                    // Add the held MIDI notes, not part of the uniform block either
+                    let (keys, count) = self.midi.held_keys();
+                    gl::Uniform1iv(locs.held_notes, keys.len() as _, keys.as_ptr());
+                    gl::Uniform1i(locs.held_note_count, count as _);
+                    gl_debug_check!();
+                }
+
+                // stages using the uniform block get the rest from there
+                if !stage.common_block {
+                    // Add time, beat and volume
+                    gl::Uniform1f(locs.dpi_scale, dpi_scale);
+                    gl::Uniform1f(locs.refresh_rate, refresh_rate);
+                    gl::Uniform1f(locs.target_dt, 1.0 / refresh_rate);
+                    gl::Uniform3f(locs.r, target_res[0] as _, target_res[1] as _, time);
                     gl::Uniform3f(
-                        volume_loc,
+                        locs.volume,
                         self.audio.volume[0], // average L/R
                         self.audio.volume[1], // L
                         self.audio.volume[2], // R
                     );
                     gl::Uniform3f(
-                        bass_loc,
+                        locs.bass,
                         self.audio.bass[0],
                         self.audio.bass[1],
                         self.audio.bass[2],
                     );
                     gl::Uniform3f(
-                        mid_loc,
+                        locs.mid,
                         self.audio.mid[0],
                         self.audio.mid[1],
                         self.audio.mid[2],
                     );
                     gl::Uniform3f(
-                        high_loc,
+                        locs.high,
                         self.audio.high[0],
                         self.audio.high[1],
                         self.audio.high[2],
                     );
                     gl::Uniform3f(
-                        smooth_bass_loc,
+                        locs.bass_smooth,
                         self.audio.bass_smooth[0],
                         self.audio.bass_smooth[1],
                         self.audio.bass_smooth[2],
                     );
                     gl::Uniform3f(
-                        smooth_mid_loc,
+                        locs.mid_smooth,
                         self.audio.mid_smooth[0],
                         self.audio.mid_smooth[1],
                         self.audio.mid_smooth[2],
                     );
                     gl::Uniform3f(
-                        smooth_high_loc,
+                        locs.high_smooth,
                         self.audio.high_smooth[0],
                         self.audio.high_smooth[1],
                         self.audio.high_smooth[2],
                     );
                     gl::Uniform3f(
-                        volume_integrated_loc,
+                        locs.volume_integrated,
                         self.audio.volume_integrated[0], // average L/R
                         self.audio.volume_integrated[1], // L
                         self.audio.volume_integrated[2], // R
                     );
                     gl::Uniform3f(
-                        bass_integrated_loc,
+                        locs.bass_integrated,
                         self.audio.bass_integrated[0],
                         self.audio.bass_integrated[1],
                         self.audio.bass_integrated[2],
                     );
                     gl::Uniform3f(
-                        mid_integrated_loc,
+                        locs.mid_integrated,
                         self.audio.mid_integrated[0],
                         self.audio.mid_integrated[1],
                         self.audio.mid_integrated[2],
                     );
                     gl::Uniform3f(
-                        high_integrated_loc,
+                        locs.high_integrated,
                         self.audio.high_integrated[0],
                         self.audio.high_integrated[1],
                         self.audio.high_integrated[2],
                     );
                     gl::Uniform3f(
-                        smooth_bass_integrated_loc,
+                        locs.bass_smooth_integrated,
                         self.audio.bass_smooth_integrated[0],
                         self.audio.bass_smooth_integrated[1],
                         self.audio.bass_smooth_integrated[2],
                     );
                     gl::Uniform3f(
-                        smooth_mid_integrated_loc,
+                        locs.mid_smooth_integrated,
                         self.audio.mid_smooth_integrated[0],
                         self.audio.mid_smooth_integrated[1],
                         self.audio.mid_smooth_integrated[2],
                     );
                     gl::Uniform3f(
-                        smooth_high_integrated_loc,
+                        locs.high_smooth_integrated,
                         self.audio.high_smooth_integrated[0],
                         self.audio.high_smooth_integrated[1],
                         self.audio.high_smooth_integrated[2],
                     );
-                    gl::Uniform2i(k_loc, pass_num as _, frame as _);
-                    gl::Uniform1i(pass_loc, pass_num as _);
-                    gl::Uniform1i(frame_loc, frame as _);
-                    gl::Uniform1f(time_loc, time);
-                    gl::Uniform1f(time_since_build_loc, time_since_build);
-                    gl::Uniform1f(beat_loc, beat);
-                    gl::Uniform1f(delta_loc, delta);
+                    gl::Uniform2i(locs.k, pass_num as _, frame as _);
+                    gl::Uniform1i(locs.frame_count, frame as _);
+                    gl::Uniform1f(locs.time, time);
+                    gl::Uniform1f(locs.time_since_build, time_since_build);
+                    gl::Uniform1f(locs.beat, beat);
+                    gl::Uniform1f(locs.beat_phase, beat_phase);
+                    gl::Uniform1f(locs.beat_sine, beat_sine);
+                    gl::Uniform1f(locs.beat_kick, beat_kick);
+                    gl::Uniform1fv(
+                        locs.beat_history,
+                        beat_history.len() as _,
+                        beat_history.as_ptr(),
+                    );
+                    gl::Uniform1fv(locs.lfo, lfo.len() as _, lfo.as_ptr());
+                    gl::Uniform1f(locs.time_delta, delta);
                     gl_debug_check!();
                 }
 
-                {
+                if !stage.common_block {
                     // Add sliders and buttons
-                    let s_loc = gl::GetUniformLocation(stage.prog_id, SLIDERS_NAME.as_ptr());
-                    let b_loc = gl::GetUniformLocation(stage.prog_id, BUTTONS_NAME.as_ptr());
-
                     let mut buttons = [0.0; 4 * MIDI_N];
                     for (k, button) in self.midi.buttons.iter().enumerate() {
                         buttons[k * 4 + 0] = button.0;
@@ -875,29 +1745,38 @@ impl Jockey {
                         buttons[k * 4 + 3] = button.3 as f32;
                     }
 
-                    gl::Uniform1fv(s_loc, self.midi.sliders.len() as _, &self.midi.sliders as _);
-                    gl::Uniform4fv(b_loc, self.midi.buttons.len() as _, &buttons as _);
+                    gl::Uniform1fv(
+                        locs.sliders,
+                        self.midi.sliders.len() as _,
+                        &self.midi.sliders as _,
+                    );
+                    gl::Uniform4fv(locs.buttons, self.midi.buttons.len() as _, &buttons as _);
+                    gl::Uniform1f(locs.xfade, self.midi.sliders[XFADE_SLIDER]);
                     gl_debug_check!();
                 }
 
                 // Add custom uniforms
                 for (name, uniform) in &stage.unis {
-                    let loc = gl::GetUniformLocation(stage.prog_id, name.as_ptr());
-                    uniform.bind(loc);
+                    uniform.bind(locs.unis[name]);
+                    gl_debug_check!();
+                }
+
+                // Bind storage buffers
+                for (k, name) in stage.storage_deps.iter().enumerate() {
+                    let buffer = self.pipeline.storage.get(name).unwrap();
+                    buffer.bind(k as _);
                     gl_debug_check!();
                 }
 
                 // Add vertex count uniform
                 if let StageKind::Vert { count, .. } = stage.kind {
-                    let loc = gl::GetUniformLocation(stage.prog_id, VERTEX_COUNT_NAME.as_ptr());
-                    gl::Uniform1i(loc, count as _);
+                    gl::Uniform1i(locs.vertex_count, count as _);
                     gl_debug_check!();
                 }
 
                 // Add and bind uniform texture dependencies
-                for (k, name) in stage.deps.iter().enumerate() {
+                for (k, (name, &(loc, res_loc))) in stage.deps.iter().zip(&locs.deps).enumerate() {
                     let tex = self.pipeline.buffers.get(name).unwrap();
-                    let loc = gl::GetUniformLocation(stage.prog_id, name.as_ptr());
                     debug_assert_ne!(loc, -1);
 
                     gl::ActiveTexture(gl::TEXTURE0 + k as GLenum);
@@ -909,14 +1788,6 @@ impl Jockey {
                     gl::Uniform1i(loc, k as _);
                     gl_debug_check!();
 
-                    let name_len = name.as_bytes().len();
-                    let res_loc = alloca::with_bytes_zeroed(name_len + 5, |res_name| {
-                        res_name[..name_len].copy_from_slice(name.as_bytes());
-                        res_name[name_len..].copy_from_slice("_res\0".as_bytes());
-
-                        gl::GetUniformLocation(stage.prog_id, res_name.as_ptr() as _)
-                    });
-
                     let res = tex.resolution();
                     gl_debug_check!();
 
@@ -934,11 +1805,6 @@ impl Jockey {
             match &stage.kind {
                 StageKind::Comp { dispatch, .. } => unsafe {
                     gl::DispatchCompute(dispatch[0], dispatch[1], dispatch[2]);
-                    gl::MemoryBarrier(
-                        gl::TEXTURE_UPDATE_BARRIER_BIT
-                            | gl::TEXTURE_FETCH_BARRIER_BIT
-                            | gl::SHADER_IMAGE_ACCESS_BARRIER_BIT,
-                    );
                     gl_debug_check!();
                 },
                 _ => unsafe {
@@ -953,20 +1819,35 @@ impl Jockey {
                             .expect("Render target should be a framebuffer");
                         (tex_id, fb_id)
                     } else {
-                        (0, 0) // The screen is always id=0
+                        (0, screen_fb) // The screen is id=0, unless it's supersampled
                     };
 
                     // Specify render target
                     gl::BindFramebuffer(gl::FRAMEBUFFER, target_fb);
-                    gl::Viewport(0, 0, target_res[0] as _, target_res[1] as _);
+                    if stage.target_mip > 0 {
+                        gl::FramebufferTexture2D(
+                            gl::FRAMEBUFFER,
+                            gl::COLOR_ATTACHMENT0,
+                            gl::TEXTURE_2D,
+                            target_tex,
+                            stage.target_mip as _,
+                        );
+                    }
+                    match stage.viewport {
+                        Some([x, y, w, h]) => gl::Viewport(x, y, w, h),
+                        None => gl::Viewport(0, 0, target_res[0] as _, target_res[1] as _),
+                    }
                     gl_debug_check!();
 
-                    // Specify fragment shader color output
-                    gl::BindFragDataLocation(stage.prog_id, 0, OUT_COLOR_NAME.as_ptr());
-                    gl_debug_check!();
+                    // Restrict all drawing and clearing to the scissor rectangle
+                    if let Some([x, y, w, h]) = stage.scissor {
+                        gl::Enable(gl::SCISSOR_TEST);
+                        gl::Scissor(x, y, w, h);
+                        gl_debug_check!();
+                    }
 
                     // Specify the layout of the vertex data
-                    let pos_attr = gl::GetAttribLocation(stage.prog_id, POSITION_NAME.as_ptr());
+                    let pos_attr = stage.locs.position;
                     if pos_attr != -1 {
                         gl_debug_check!();
                         gl::EnableVertexAttribArray(pos_attr as GLuint);
@@ -989,32 +1870,140 @@ impl Jockey {
                         gl_debug_check!();
                     }
 
+                    // Set stencil test
+                    match &stage.stencil {
+                        Some(stencil) => {
+                            gl::Enable(gl::STENCIL_TEST);
+                            gl::StencilMask(0xFF);
+                            if let Some(value) = stencil.clear {
+                                gl::ClearStencil(value);
+                                gl::Clear(gl::STENCIL_BUFFER_BIT);
+                            }
+
+                            let [sfail, dpfail, dppass] = stencil.ops;
+                            gl::StencilFunc(stencil.func, stencil.reference, stencil.mask);
+                            gl::StencilOp(sfail, dpfail, dppass);
+                        }
+                        None => gl::Disable(gl::STENCIL_TEST),
+                    }
+                    gl_debug_check!();
+
+                    // Set fade factor for accumulation buffers
+                    if let Some(fade) = stage.accumulate {
+                        gl::BlendColor(fade, fade, fade, fade);
+                        gl_debug_check!();
+                    }
+
                     // Draw stuff
                     if let StageKind::Vert {
                         count,
                         mode,
                         thickness,
-                        ..
+                        ref strips,
+                        patch_vertices,
                     } = stage.kind
                     {
-                        gl::ClearColor(0.0, 0.0, 0.0, 0.0);
-                        gl::Clear(gl::COLOR_BUFFER_BIT);
-                        gl_debug_check!();
+                        if stage.accumulate.is_none() {
+                            // only clear the region covered by the viewport,
+                            // a scissor rectangle is already in place
+                            let rect = match stage.scissor {
+                                Some(_) => None,
+                                None => stage.viewport,
+                            };
+                            if let Some([x, y, w, h]) = rect {
+                                gl::Enable(gl::SCISSOR_TEST);
+                                gl::Scissor(x, y, w, h);
+                            }
+
+                            gl::ClearColor(0.0, 0.0, 0.0, 0.0);
+                            gl::Clear(gl::COLOR_BUFFER_BIT);
+                            if rect.is_some() {
+                                gl::Disable(gl::SCISSOR_TEST);
+                            }
+                            gl_debug_check!();
+                        }
 
                         gl::PointSize(thickness);
                         gl::LineWidth(thickness);
                         gl_debug_check!();
 
-                        draw_vertices(self.ctx.vao, count, mode);
+                        if let Some(n) = patch_vertices {
+                            gl::PatchParameteri(gl::PATCH_VERTICES, n);
+                            gl_debug_check!();
+                        }
+
+                        match strips {
+                            Some(indices) => {
+                                draw_indexed(self.ctx.vao, self.ctx.ebo, indices, mode)
+                            }
+                            None => draw_vertices(self.ctx.vao, count, mode),
+                        }
+                        gl_debug_check!();
+                    } else if let StageKind::Frag {
+                        geometry: Some(geometry),
+                    } = &stage.kind
+                    {
+                        draw_triangles(self.ctx.vao, geometry);
                         gl_debug_check!();
                     } else {
                         draw_fullscreen(self.ctx.vao);
                         gl_debug_check!();
                     }
 
+                    if stage.scissor.is_some() {
+                        gl::Disable(gl::SCISSOR_TEST);
+                        gl_debug_check!();
+                    }
+
+                    // Read the pixel under the mouse, the render target is still bound
+                    if stage.target == self.probe_target {
+                        if let Some([u, v]) = self.mouse_pos {
+                            // the mouse covers the area the stage has drawn to
+                            let [x, y, w, h] = stage.viewport.unwrap_or([
+                                0,
+                                0,
+                                target_res[0] as _,
+                                target_res[1] as _,
+                            ]);
+                            let x = x + (u * w as f32) as GLint;
+                            let y = y + (v * h as f32) as GLint;
+
+                            let mut color = [0.0_f32; 4];
+                            gl::ReadnPixels(
+                                x,
+                                y,
+                                1,
+                                1,
+                                gl::RGBA,
+                                gl::FLOAT,
+                                std::mem::size_of_val(&color) as _,
+                                color.as_mut_ptr() as _,
+                            );
+                            gl_debug_check!();
+
+                            self.probe_color = Some(color);
+                        }
+                    }
+
+                    // Attach the base level again for the other stages
+                    if stage.target_mip > 0 {
+                        gl::FramebufferTexture2D(
+                            gl::FRAMEBUFFER,
+                            gl::COLOR_ATTACHMENT0,
+                            gl::TEXTURE_2D,
+                            target_tex,
+                            0,
+                        );
+                        gl_debug_check!();
+                    }
+
                     // Generate mip maps
-                    // don't do it for the screen buffer
-                    if target_tex != 0 {
+                    // don't do it for the screen buffer or stages writing a single level
+                    if target_tex != 0
+                        && stage.builder.mipmap
+                        && stage.target_mip == 0
+                        && !self.safe_mode
+                    {
                         gl::BindTexture(gl::TEXTURE_2D, target_tex);
                         gl::GenerateMipmap(gl::TEXTURE_2D);
                         gl_debug_check!();
@@ -1024,15 +2013,81 @@ impl Jockey {
                     if let Some(name) = &stage.target {
                         self.pipeline.buffers.get(name).unwrap().swap();
                     }
+
+                    // Draw the target to the screen, stretched to the window size,
+                    // after the mipmaps so minified targets are filtered properly
+                    let size = [factor * width, factor * height];
+                    let (vao, buffers) = (self.ctx.vao, &self.pipeline.buffers);
+                    show_target(&self.show_pass, vao, stage, buffers, screen_fb, size);
                 },
             }
 
+            // wait for writes to images and storage buffers before they are read
+            if stage.barrier != 0 {
+                unsafe {
+                    gl::MemoryBarrier(stage.barrier);
+                    gl_debug_check!();
+                }
+            }
+
+            // images and storage buffers may have been written as well as read
+            let versions = &mut self.pipeline.versions;
+            bump_versions(stage.outputs(), versions);
+
+            // the stage's own writes don't count as a change of its inputs
+            if stage.skip_unchanged {
+                let deps = dep_versions(stage.inputs(), versions);
+                if let Some(inputs) = stage.last_inputs.as_mut() {
+                    inputs.deps = deps;
+                }
+            }
+
             // log render time
             let stage_time = stage_start.elapsed().as_secs_f32();
             stage.perf.push(1000.0 * stage_time);
         }
 
+        self.pipeline.first_frame = false;
+
+        // export stage timings
+        if let Some(timings) = self.timings.as_mut() {
+            let name = self
+                .pipeline_files
+                .get(self.pipeline_index)
+                .map_or("", String::as_str);
+
+            timings.record(name, &self.pipeline.stages);
+        }
+
+        if let Some(supersample) = self.supersample.as_ref() {
+            supersample.resolve(self.ctx.vao, width, height);
+        }
+
+        if let Some(swizzle) = self.output_swizzle.as_mut() {
+            swizzle.apply(self.ctx.vao, width, height);
+        }
+
         self.ctx.context.swap_buffers().unwrap();
+
+        for output in self.outputs.iter_mut() {
+            output.present(&self.pipeline.buffers);
+        }
+    }
+
+    /// Opens a window for each output, replacing the ones currently open.
+    ///
+    /// The context of the last window stays current.
+    fn open_outputs(&mut self, configs: &[OutputConfig]) {
+        self.outputs.clear();
+        self.output_configs = configs.to_vec();
+
+        for config in configs {
+            let shared = self.ctx.context.context();
+            match OutputWindow::new(config, shared, &self.ctx.events_loop) {
+                Ok(output) => self.outputs.push(output),
+                Err(err) => log::error!("{}", err),
+            }
+        }
     }
 
     /// Wrapper function for all the imgui stuff.
@@ -1068,7 +2123,24 @@ impl Jockey {
             imgui::sys::igDockSpaceOverViewport(viewport, flags, window_class);
         }
 
+        // built-in imgui windows, enabled with --debug-ui
+        if self.show_metrics {
+            ui.show_metrics_window(&mut self.show_metrics);
+        }
+
+        if self.show_demo {
+            ui.show_demo_window(&mut self.show_demo);
+        }
+
         if let Some(window) = imgui::Window::new(im_str!("Pipelines")).begin(&ui) {
+            if self.safe_mode {
+                ui.text_colored(
+                    [1.0, 0.6, 0.2, 1.0],
+                    "Safe mode: only fragment stages without feedback are rendered",
+                );
+                ui.separator();
+            }
+
             if ui.button_with_size(im_str!("Select project folder"), [0.0; 2]) {
                 std::thread::spawn(|| {
                     let Some(path) = rfd::FileDialog::new().pick_folder() else {
@@ -1087,6 +2159,10 @@ impl Jockey {
                 });
             }
 
+            if ui.button_with_size(im_str!("Reset buffers"), [0.0; 2]) {
+                self.reset_requested = true;
+            }
+
             ui.separator();
             match self.pipeline_files.len() {
                 0 => ui.text("No yaml file found"),
@@ -1208,25 +2284,54 @@ impl Jockey {
         }
 
         if let Some(window) = imgui::Window::new(im_str!("Timeline")).begin(&ui) {
+            if let Some(dt) = self.fixed_dt {
+                ui.text(format!("Fixed time step: {:.2} fps", 1.0 / dt));
+            }
+
             if ui.button_with_size(im_str!("Play"), [64.0, 18.0]) {
                 self.speed = 1.0;
+                if let Some(track) = self.track.as_mut() {
+                    track.play();
+                }
             }
 
             ui.same_line();
             if ui.button_with_size(im_str!("Stop"), [64.0, 18.0]) {
                 self.speed = 0.0;
+                if let Some(track) = self.track.as_mut() {
+                    track.pause();
+                }
             }
 
             ui.same_line();
             if ui.button_with_size(im_str!("Reset"), [64.0, 18.0]) {
                 self.time = 0.0;
                 self.frame = 0;
+                if let Some(track) = self.track.as_mut() {
+                    track.seek(0.0);
+                }
             }
 
             let (start, end) = &mut self.time_range;
-            imgui::Slider::new(im_str!("time"))
+            let seek = imgui::Slider::new(im_str!("time"))
                 .range(*start..=*end)
                 .build(&ui, &mut self.time);
+            if seek {
+                if let Some(track) = self.track.as_mut() {
+                    track.seek(self.time);
+                }
+            }
+            // the speed slider can be bound to a midi slider, like the ones in the sliders window
+            if !self.alt_pressed {
+                if ui.small_button(im_str!("bind")) {
+                    self.midi.bind_slider(SPEED_BINDING);
+                }
+            } else {
+                if ui.small_button(im_str!("unbind")) {
+                    self.midi.unbind_slider(SPEED_BINDING);
+                }
+            }
+            ui.same_line();
             imgui::Slider::new(im_str!("speed"))
                 .range(-2.0..=2.0)
                 .build(&ui, &mut self.speed);
@@ -1238,6 +2343,89 @@ impl Jockey {
             ui.set_next_item_width(64.0);
             ui.input_float(im_str!("end"), end).build();
 
+            // slider and button changes can be recorded against the timeline
+            ui.separator();
+            match self.automation.state {
+                AutomationState::Idle => {
+                    if ui.button_with_size(im_str!("Record"), [64.0, 18.0]) {
+                        self.automation.record(&self.midi, self.time);
+                    }
+
+                    ui.same_line();
+                    if ui.button_with_size(im_str!("Replay"), [64.0, 18.0]) {
+                        if self.automation.len() == 0 {
+                            self.automation.load(AUTOMATION_FILE);
+                        }
+                        self.automation.play(&mut self.midi, self.time);
+                    }
+
+                    ui.same_line();
+                    ui.text(format!("{} events", self.automation.len()));
+                }
+                AutomationState::Recording => {
+                    if ui.button_with_size(im_str!("Stop"), [64.0, 18.0]) {
+                        self.automation.stop();
+                        self.automation.save(AUTOMATION_FILE);
+                    }
+
+                    ui.same_line();
+                    ui.text_colored([1.0, 0.3, 0.3, 1.0], "Recording automation");
+                }
+                AutomationState::Playing => {
+                    if ui.button_with_size(im_str!("Stop"), [64.0, 18.0]) {
+                        self.automation.stop();
+                    }
+
+                    ui.same_line();
+                    ui.text(format!("Replaying {} events", self.automation.len()));
+                }
+            }
+
+            // an audio file can be played along with the timeline
+            ui.separator();
+            // the dialog and decoding would stall the output, so they run on a thread
+            if ui.button_with_size(im_str!("Load track"), [64.0, 18.0])
+                && self.pending_track.is_none()
+            {
+                let (sender, receiver) = mpsc::channel();
+                self.pending_track = Some(receiver);
+                std::thread::spawn(move || {
+                    let file = rfd::FileDialog::new()
+                        .add_filter("Audio", &["wav", "mp3", "ogg", "flac"])
+                        .pick_file();
+
+                    if let Some(path) = file {
+                        sender.send(TrackData::decode(&path)).ok();
+                    }
+                });
+            }
+
+            ui.same_line();
+            match self.track.as_ref() {
+                Some(track) => {
+                    if ui.button_with_size(im_str!("Unload"), [64.0, 18.0]) {
+                        self.track = None;
+                    } else {
+                        ui.same_line();
+                        ui.text(format!(
+                            "{} ({:.1}s / {:.1}s)",
+                            track.path.file_name().unwrap_or_default().to_string_lossy(),
+                            track.position(),
+                            track.duration()
+                        ));
+                    }
+                }
+                None if self.pending_track.is_some() => ui.text_disabled("Loading track..."),
+                None => ui.text_disabled("No track loaded"),
+            }
+
+            if let Some(port) = self.transport.port {
+                match self.transport.active() {
+                    true => ui.text(format!("Following transport on port {}", port)),
+                    false => ui.text_disabled(format!("Waiting for transport on port {}", port)),
+                }
+            }
+
             window.end();
         }
 
@@ -1270,7 +2458,7 @@ impl Jockey {
                     && ui.is_item_hovered()
                 {
                     self.midi.buttons[k].0 = 1.0;
-                    self.midi.buttons[k].1 = Instant::now();
+                    self.midi.buttons[k].1 = self.midi.press_time(k);
                     self.midi.buttons[k].3 += 1;
                 }
 
@@ -1289,6 +2477,36 @@ impl Jockey {
         }
 
         if let Some(window) = imgui::Window::new(im_str!("Sliders")).begin(&ui) {
+            match self.midi.devices.as_slice() {
+                [] => ui.text_disabled("No MIDI device connected"),
+                devices => ui.text(format!("MIDI: {}", devices.join(", "))),
+            }
+
+            if ui.small_button(im_str!("Bake to GLSL")) {
+                self.save_uniforms_glsl();
+            }
+
+            if imgui::CollapsingHeader::new(im_str!("Devices")).build(&ui) {
+                let mut selected = None;
+                if ui.radio_button_bool(im_str!("all"), self.midi.selected_device.is_none()) {
+                    selected = Some(None);
+                }
+
+                for name in self.midi.ports.iter() {
+                    let cst = CString::new(name.as_bytes()).unwrap();
+                    let ims = unsafe { imgui::ImStr::from_cstr_unchecked(&cst) };
+                    let active = self.midi.selected_device.as_ref() == Some(name);
+                    if ui.radio_button_bool(ims, active) {
+                        selected = Some(Some(name.clone()));
+                    }
+                }
+
+                if let Some(device) = selected {
+                    self.midi.select_device(device);
+                }
+            }
+            ui.separator();
+
             for k in 0..self.midi.sliders.len() {
                 let token = ui.push_id(k as i32);
                 if !self.alt_pressed {
@@ -1304,11 +2522,50 @@ impl Jockey {
                 ui.same_line();
 
                 let mut buffer = [0_u8; 16];
-                write!(buffer.as_mut(), "slider{}\0", k).unwrap();
+                match k {
+                    XFADE_SLIDER => write!(buffer.as_mut(), "xfade ({})\0", k).unwrap(),
+                    _ => write!(buffer.as_mut(), "slider{}\0", k).unwrap(),
+                }
                 let cstr = unsafe { std::ffi::CStr::from_bytes_with_nul_unchecked(&buffer) };
                 let ims = unsafe { imgui::ImStr::from_cstr_unchecked(cstr) };
+                // moving the slider by hand skips the smoothing
                 let slider = &mut self.midi.sliders[k];
-                imgui::Slider::new(ims).range(0.0..=1.0).build(&ui, slider);
+                if imgui::Slider::new(ims).range(0.0..=1.0).build(&ui, slider) {
+                    self.midi.slider_targets[k] = *slider;
+                }
+            }
+
+            window.end();
+        }
+
+        if let Some(window) = imgui::Window::new(im_str!("Uniforms")).begin(&ui) {
+            for (k, stage) in self.pipeline.stages.iter_mut().enumerate() {
+                if stage.controls.is_empty() {
+                    continue;
+                }
+
+                ui.text(stage.label(k));
+                let token = ui.push_id(k as i32);
+                for (name, (min, max)) in stage.controls.iter() {
+                    let uniform = match stage.unis.get_mut(name) {
+                        Some(s) => s,
+                        None => continue,
+                    };
+
+                    let mut values = match uniform.to_vector() {
+                        Some(s) => s,
+                        None => continue,
+                    };
+
+                    let ims = unsafe { imgui::ImStr::from_cstr_unchecked(name) };
+                    if imgui::Slider::new(ims)
+                        .range(*min..=*max)
+                        .build_array(&ui, &mut values)
+                    {
+                        *uniform = Uniform::from_vector(&values).unwrap();
+                    }
+                }
+                token.pop();
             }
 
             window.end();
@@ -1335,18 +2592,60 @@ impl Jockey {
         }
 
         if let Some(window) = imgui::Window::new(im_str!("Beat Sync")).begin(&ui) {
-            if ui.button_with_size(im_str!("Tab here"), [128.0, 32.0]) {
+            // tapping only has an effect if no other source is active
+            let tapping = self.tempo_source == TempoSource::Tap;
+            if ui.button_with_size(im_str!("Tab here"), [128.0, 32.0]) && tapping {
                 self.beat_sync.trigger();
             }
             ui.same_line();
             ui.text(format!(
-                "BPM: {}\ncount: {}",
+                "BPM: {}\ncount: {}\nsource: {}",
                 self.beat_sync.bpm(),
-                self.beat_sync.count
+                self.beat_sync.count,
+                self.tempo_source.name()
             ));
 
             imgui::ProgressBar::new(self.beat_sync.beat().fract()).build(&ui);
 
+            let mut bpm = self.beat_sync.bpm();
+            if ui
+                .input_float(im_str!("BPM"), &mut bpm)
+                .enter_returns_true(true)
+                .build()
+            {
+                self.beat_sync.set_bpm(bpm);
+            }
+
+            if ui.button_with_size(im_str!("<<"), [32.0, 0.0]) {
+                self.beat_sync.nudge(-0.02);
+            }
+            ui.same_line();
+            if ui.button_with_size(im_str!("<"), [32.0, 0.0]) {
+                self.beat_sync.nudge(-0.005);
+            }
+            ui.same_line();
+            if ui.button_with_size(im_str!(">"), [32.0, 0.0]) {
+                self.beat_sync.nudge(0.005);
+            }
+            ui.same_line();
+            if ui.button_with_size(im_str!(">>"), [32.0, 0.0]) {
+                self.beat_sync.nudge(0.02);
+            }
+            ui.same_line();
+            ui.text("nudge");
+
+            // a single bad tap stands out from the others
+            let taps = self.beat_sync.tap_intervals();
+            if !taps.is_empty() {
+                ui.plot_lines(im_str!("taps [ms]"), &taps).build();
+                ui.text(format!(
+                    "min {:.0} ms, max {:.0} ms, std dev {:.1} ms",
+                    self.beat_sync.taps.min(),
+                    self.beat_sync.taps.max(),
+                    self.beat_sync.taps.std_dev()
+                ));
+            }
+
             window.end();
         }
 
@@ -1357,28 +2656,215 @@ impl Jockey {
                 frame_ms
             ));
 
-            ui.plot_lines(im_str!("dt [ms]"), &self.frame_perf.buffer)
-                .build();
+            if imgui::CollapsingHeader::new(im_str!("Frame time"))
+                .default_open(true)
+                .build(&ui)
+            {
+                ui.plot_lines(im_str!("dt [ms]"), &self.frame_perf.buffer)
+                    .build();
+
+                // spikes hardly move the average, but show up in the max
+                let perf = &self.frame_perf;
+                ui.text(format!(
+                    "min {:.2} ms, max {:.2} ms, std dev {:.2} ms",
+                    perf.min(),
+                    perf.max(),
+                    perf.std_dev()
+                ));
+            }
+
+            // stages over their budget are highlighted
+            let over_budget = [1.0, 0.3, 0.3, 1.0];
+            let frame_budget_ms = 1000.0 / self.target_fps.unwrap_or(self.refresh_rate);
+            let stage_sum_ms: f32 = self.pipeline.stages.iter().map(|s| s.perf.get()).sum();
+            let total = format!(
+                "Total: {:.4} ms of {:.2} ms budget ({:.2}% stress)",
+                stage_sum_ms,
+                frame_budget_ms,
+                100.0 * stage_sum_ms / frame_ms
+            );
+            match stage_sum_ms > frame_budget_ms {
+                true => ui.text_colored(over_budget, total),
+                false => ui.text(total),
+            }
+
+            let mipmapped = self
+                .pipeline
+                .stages
+                .iter()
+                .filter(|s| s.target.is_some() && s.builder.mipmap)
+                .count();
+            ui.text(format!("Mipmapped targets: {}", mipmapped));
+
+            // the driver a shader runs on, for bug reports
+            if imgui::CollapsingHeader::new(im_str!("System")).build(&ui) {
+                let info = &self.gl_info;
+                ui.text(format!("OpenGL: {}", info.version));
+                ui.text(format!("GLSL: {}", info.glsl_version));
+                ui.text(format!("Renderer: {}", info.renderer));
+                ui.text(format!("Vendor: {}", info.vendor));
+                ui.text(format!("Texture units: {}", info.max_texture_units));
+                match info.max_patch_vertices {
+                    0 => ui.text("Tessellation: unsupported"),
+                    n => ui.text(format!("Max patch vertices: {}", n)),
+                }
+            }
 
-            let mut stage_sum_ms = 0.0;
+            if imgui::CollapsingHeader::new(im_str!("Stages"))
+                .default_open(true)
+                .build(&ui)
+            {
+                // keep the stage list scrollable for large pipelines
+                if let Some(child) = imgui::ChildWindow::new("stage-perf")
+                    .size([0.0, 0.0])
+                    .horizontal_scrollbar(true)
+                    .begin(&ui)
+                {
+                    for (k, stage) in self.pipeline.stages.iter().enumerate() {
+                        let stage_ms = stage.perf.get();
+                        let mut line = format!(
+                            "{}: {:.4} ms (max {:.4}, std dev {:.4})",
+                            stage.label(k),
+                            stage_ms,
+                            stage.perf.max(),
+                            stage.perf.std_dev()
+                        );
+                        if let Some(budget) = stage.budget {
+                            line += &format!(" / {:.4} ms", budget);
+                        }
+                        if let Some(tex_name) = stage.target.as_ref() {
+                            line += &format!(" (-> {:?})", tex_name);
+                        }
+
+                        match stage.budget {
+                            Some(budget) if stage_ms > budget => ui.text_colored(over_budget, line),
+                            _ => ui.text(line),
+                        }
+
+                        // open the shader files on double click
+                        if ui.is_item_hovered()
+                            && ui.is_mouse_double_clicked(imgui::MouseButton::Left)
+                        {
+                            for path in stage.sources.iter() {
+                                if let Err(err) = open_file(path) {
+                                    log::error!("Failed to open {}: {}", path, err);
+                                }
+                            }
+                        }
+                    }
+
+                    child.end();
+                }
+            }
+
+            window.end();
+        }
+
+        // what the linker kept of each program, to find uniforms that are never set
+        if let Some(window) = imgui::Window::new(im_str!("Programs")).begin(&ui) {
             for (k, stage) in self.pipeline.stages.iter().enumerate() {
-                let stage_ms = stage.perf.get();
-                stage_sum_ms += stage_ms;
+                let mut label = stage.label(k);
                 if let Some(tex_name) = stage.target.as_ref() {
+                    label += &format!(" (-> {:?})", tex_name);
+                }
+
+                let label = CString::new(label).unwrap();
+                let ims = unsafe { imgui::ImStr::from_cstr_unchecked(&label) };
+                let token = ui.push_id(k as i32);
+                if imgui::CollapsingHeader::new(ims).build(&ui) {
+                    let sections = [
+                        ("uniform", &stage.info.uniforms),
+                        ("attribute", &stage.info.attributes),
+                    ];
+
+                    for (kind, vars) in sections.iter() {
+                        for var in vars.iter() {
+                            let type_name = match var.type_name() {
+                                Some(name) => name.to_owned(),
+                                None => format!("0x{:04x}", var.type_),
+                            };
+
+                            let mut line = format!("{} {} {}", kind, type_name, var.name);
+                            if var.size > 1 {
+                                line += &format!(" [{}]", var.size);
+                            }
+                            if var.location >= 0 {
+                                line += &format!(" @ {}", var.location);
+                            }
+                            ui.text(line);
+                        }
+                    }
+                }
+                token.pop();
+            }
+
+            window.end();
+        }
+
+        if let Some(window) = imgui::Window::new(im_str!("Color Probe")).begin(&ui) {
+            if ui.radio_button_bool(im_str!("screen"), self.probe_target.is_none()) {
+                self.probe_target = None;
+                self.probe_color = None;
+            }
+
+            let mut targets: Vec<_> = self
+                .pipeline
+                .stages
+                .iter()
+                .filter_map(|stage| stage.target.as_ref())
+                .collect();
+            targets.sort();
+            targets.dedup();
+
+            for name in targets {
+                let ims = unsafe { imgui::ImStr::from_cstr_unchecked(name) };
+                let selected = self.probe_target.as_ref() == Some(name);
+                if ui.radio_button_bool(ims, selected) {
+                    self.probe_target = Some(name.clone());
+                    self.probe_color = None;
+                }
+            }
+
+            ui.separator();
+            match (self.mouse_pos, self.probe_color) {
+                (Some(_), Some(color)) => {
+                    imgui::ColorButton::new(im_str!("##probe-color"), color).build(&ui);
+                    ui.same_line();
                     ui.text(format!(
-                        "Stage {}: {:.4} ms (-> {:?})",
-                        k, stage_ms, tex_name
+                        "{:.4} {:.4} {:.4} {:.4}",
+                        color[0], color[1], color[2], color[3]
                     ));
-                } else {
-                    ui.text(format!("Stage {}: {:.4} ms", k, stage_ms));
                 }
+                _ => ui.text("Hover over the output window to pick a color"),
             }
 
-            ui.text(format!(
-                "Total: {:.4} ms ({:.2}% stress)",
-                stage_sum_ms,
-                100.0 * stage_sum_ms / frame_ms
-            ));
+            if ui.button(im_str!("Save as EXR")) {
+                self.save_buffer_exr();
+            }
+
+            // filtering can be changed on the fly, until the pipeline is rebuilt
+            if imgui::CollapsingHeader::new(im_str!("Filtering")).build(&ui) {
+                let mut names: Vec<_> = self.pipeline.buffers.keys().collect();
+                names.sort();
+
+                for (k, name) in names.into_iter().enumerate() {
+                    let tex = &self.pipeline.buffers[name];
+                    let mut linear = match tex.filter() {
+                        Some(filter) => filter == gl::LINEAR,
+                        None => continue,
+                    };
+
+                    let token = ui.push_id(k as i32);
+                    let ims = unsafe { imgui::ImStr::from_cstr_unchecked(name) };
+                    if ui.checkbox(ims, &mut linear) {
+                        tex.set_filter(match linear {
+                            true => gl::LINEAR,
+                            false => gl::NEAREST,
+                        });
+                    }
+                    token.pop();
+                }
+            }
 
             window.end();
         }
@@ -1432,4 +2918,183 @@ impl Jockey {
         let file_name = format!("frame-{}.png", hash);
         img.save(file_name).unwrap();
     }
+
+    /// Saves the buffer selected in the color probe window as an OpenEXR file.
+    ///
+    /// Unlike screenshots, this keeps the full floating point range of the buffer.
+    pub fn save_buffer_exr(&mut self) {
+        take_mut::take(&mut self.ctx.context, |s| unsafe {
+            s.make_current().unwrap()
+        });
+
+        let (label, width, height) = match &self.probe_target {
+            Some(name) => {
+                let tex = match self.pipeline.buffers.get(name) {
+                    Some(tex) => tex,
+                    None => {
+                        log::error!("Buffer {:?} does not exist", name);
+                        return;
+                    }
+                };
+
+                // EXR files hold a single 2D layer of float pixels
+                let res = tex.resolution();
+                if res[1] == 0 || res[2] != 0 {
+                    log::error!("Only 2D buffers can be saved as EXR, {:?} is not", name);
+                    return;
+                }
+
+                if tex.format().is_integer() {
+                    log::error!("Integer buffers can't be saved as EXR, {:?} is one", name);
+                    return;
+                }
+
+                (name.to_string_lossy().into_owned(), res[0], res[1])
+            }
+            None => {
+                let screen_size = self.ctx.context.window().inner_size();
+                ("screen".to_owned(), screen_size.width, screen_size.height)
+            }
+        };
+
+        let mut pixels = vec![0.0_f32; 4 * (width * height) as usize];
+        let size = std::mem::size_of_val(pixels.as_slice());
+
+        unsafe {
+            match &self.probe_target {
+                Some(name) => {
+                    self.pipeline.buffers[name].bind(0);
+                    gl::GetnTexImage(
+                        gl::TEXTURE_2D,
+                        0,
+                        gl::RGBA,
+                        gl::FLOAT,
+                        size as _,
+                        pixels.as_mut_ptr() as _,
+                    );
+                }
+                None => {
+                    gl::BindFramebuffer(gl::FRAMEBUFFER, 0);
+                    gl::ReadnPixels(
+                        0,
+                        0,
+                        width as _,
+                        height as _,
+                        gl::RGBA,
+                        gl::FLOAT,
+                        size as _,
+                        pixels.as_mut_ptr() as _,
+                    );
+                }
+            }
+            gl_debug_check!();
+        }
+
+        let mut hasher = DefaultHasher::new();
+        Instant::now().hash(&mut hasher);
+        label.hash(&mut hasher);
+        let hash = hasher.finish();
+
+        // OpenGL stores the rows bottom to top
+        let file_name = format!("{}-{}.exr", label, hash);
+        let result =
+            exr::prelude::write_rgba_file(&file_name, width as usize, height as usize, |x, y| {
+                let k = 4 * ((height as usize - 1 - y) * width as usize + x);
+                (pixels[k], pixels[k + 1], pixels[k + 2], pixels[k + 3])
+            });
+
+        match result {
+            Ok(()) => log::info!("Saved {}", file_name),
+            Err(err) => log::error!("Failed to save {}: {}", file_name, err),
+        }
+    }
+
+    /// Clears the feedback buffers of the current pipeline, see `reset` in the
+    /// pipeline file.
+    pub fn reset_buffers(&mut self) {
+        take_mut::take(&mut self.ctx.context, |s| unsafe {
+            s.make_current().unwrap()
+        });
+
+        self.pipeline.reset_buffers();
+        log::info!(
+            "Reset {} render target(s)",
+            self.pipeline.reset_targets.len()
+        );
+    }
+
+    /// Saves the current slider, button and custom uniform values as GLSL constants.
+    ///
+    /// Including the file in place of the uniform declarations freezes the
+    /// current look, so the shader no longer depends on the live controls.
+    pub fn save_uniforms_glsl(&self) {
+        let sliders: Vec<_> = self.midi.sliders.iter().copied().map(glsl_float).collect();
+
+        let mut out = String::new();
+        out.push_str("// Generated by Sh4derJockey, replaces the uniform declarations\n\n");
+        out.push_str(&format!(
+            "const float {}[{}] = float[]({});\n",
+            SLIDERS_NAME.to_string_lossy(),
+            MIDI_N,
+            sliders.join(", ")
+        ));
+
+        let buttons: Vec<_> = self
+            .midi
+            .buttons
+            .iter()
+            .map(|b| {
+                Uniform::Vec4(
+                    b.0,
+                    b.1.elapsed().as_secs_f32(),
+                    b.2.elapsed().as_secs_f32(),
+                    b.3 as f32,
+                )
+                .to_glsl()
+            })
+            .collect();
+        out.push_str(&format!(
+            "const vec4 {}[{}] = vec4[]({});\n",
+            BUTTONS_NAME.to_string_lossy(),
+            MIDI_N,
+            buttons.join(", ")
+        ));
+        out.push_str(&format!(
+            "const float {} = {};\n",
+            XFADE_NAME.to_string_lossy(),
+            glsl_float(self.midi.sliders[XFADE_SLIDER])
+        ));
+
+        // custom uniforms of all stages, the first stage declaring a name wins
+        let mut unis = BTreeMap::new();
+        for stage in self.pipeline.stages.iter() {
+            for (name, uniform) in stage.unis.iter() {
+                unis.entry(name.to_string_lossy()).or_insert(*uniform);
+            }
+        }
+
+        if !unis.is_empty() {
+            out.push('\n');
+        }
+
+        for (name, uniform) in unis {
+            out.push_str(&format!(
+                "const {} {} = {};\n",
+                uniform.glsl_type(),
+                name,
+                uniform.to_glsl()
+            ));
+        }
+
+        let mut hasher = DefaultHasher::new();
+        Instant::now().hash(&mut hasher);
+        out.hash(&mut hasher);
+        let hash = hasher.finish();
+
+        let file_name = format!("uniforms-{}.glsl", hash);
+        match std::fs::write(&file_name, out) {
+            Ok(()) => log::info!("Saved {}", file_name),
+            Err(err) => log::error!("Failed to save {}: {}", file_name, err),
+        }
+    }
 }
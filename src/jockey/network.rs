@@ -217,8 +217,8 @@ impl Ndi {
             if tex.resolution() != [video.width(), video.height(), 0] {
                 *tex = Texture2D::with_params(
                     [video.width(), video.height()],
-                    tex.min_filter,
-                    tex.mag_filter,
+                    tex.min_filter.get(),
+                    tex.mag_filter.get(),
                     tex.wrap_mode,
                     tex.format,
                     tex.mipmap,
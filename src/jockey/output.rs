@@ -0,0 +1,397 @@
+use gl::types::*;
+
+use std::{collections::HashMap, ffi::CString, rc::Rc};
+
+use super::{OutputConfig, PASS_VERT, POSITION_NAME};
+use crate::util::*;
+
+const SWIZZLE_FRAG: &str = include_str!("shaders/swizzle.frag");
+const DOWNSAMPLE_FRAG: &str = include_str!("shaders/downsample.frag");
+const OUTPUT_FRAG: &str = include_str!("shaders/output.frag");
+
+/// Reorders the color channels of the output window, for displays that
+/// expect them in a different order.
+///
+/// The finished frame is copied into a texture and drawn back with the
+/// channels swapped, so intermediate buffers are not affected.
+#[derive(Debug)]
+pub struct OutputSwizzle {
+    pub channels: [usize; 4],
+    prog_id: GLuint,
+    sh_ids: Vec<GLuint>,
+    tex_id: GLuint,
+    size: (u32, u32),
+}
+
+impl OutputSwizzle {
+    /// Creates a swizzle, where `channels[k]` is the index of the channel
+    /// that ends up in the k-th channel of the output.
+    pub fn new(channels: [usize; 4]) -> Self {
+        const CHANNELS: [GLenum; 4] = [gl::RED, gl::GREEN, gl::BLUE, gl::ALPHA];
+
+        let sh_ids = vec![
+            compile_shader(PASS_VERT, gl::VERTEX_SHADER).unwrap(),
+            compile_shader(SWIZZLE_FRAG, gl::FRAGMENT_SHADER).unwrap(),
+        ];
+        let prog_id = link_program(&sh_ids).unwrap();
+
+        let mut tex_id = 0;
+        unsafe {
+            gl::GenTextures(1, &mut tex_id);
+            gl::BindTexture(gl::TEXTURE_2D, tex_id);
+            gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MIN_FILTER, gl::NEAREST as _);
+            gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MAG_FILTER, gl::NEAREST as _);
+
+            let swizzle = channels.map(|k| CHANNELS[k] as GLint);
+            gl::TexParameteriv(gl::TEXTURE_2D, gl::TEXTURE_SWIZZLE_RGBA, swizzle.as_ptr());
+            gl::BindTexture(gl::TEXTURE_2D, 0);
+            gl_debug_check!();
+        }
+
+        Self {
+            channels,
+            prog_id,
+            sh_ids,
+            tex_id,
+            size: (0, 0),
+        }
+    }
+
+    /// Swaps the channels of the window, which has to be the current context.
+    pub fn apply(&mut self, vao: GLuint, width: u32, height: u32) {
+        unsafe {
+            gl::BindFramebuffer(gl::FRAMEBUFFER, 0);
+            gl::BindTexture(gl::TEXTURE_2D, self.tex_id);
+            gl_debug_check!();
+
+            // copy the finished frame
+            if self.size != (width, height) {
+                gl::CopyTexImage2D(
+                    gl::TEXTURE_2D,
+                    0,
+                    gl::RGBA8,
+                    0,
+                    0,
+                    width as _,
+                    height as _,
+                    0,
+                );
+                self.size = (width, height);
+            } else {
+                gl::CopyTexSubImage2D(gl::TEXTURE_2D, 0, 0, 0, 0, 0, width as _, height as _);
+            }
+            gl_debug_check!();
+
+            // draw it back over the whole window
+            gl::UseProgram(self.prog_id);
+            gl::Viewport(0, 0, width as _, height as _);
+            gl::BlendFunc(gl::ONE, gl::ZERO);
+            gl::Disable(gl::STENCIL_TEST);
+            gl::ActiveTexture(gl::TEXTURE0);
+            gl_debug_check!();
+
+            bind_position(self.prog_id);
+            draw_fullscreen(vao);
+            gl::BindTexture(gl::TEXTURE_2D, 0);
+            gl_debug_check!();
+        }
+    }
+}
+
+impl Drop for OutputSwizzle {
+    fn drop(&mut self) {
+        unsafe {
+            for &id in self.sh_ids.iter() {
+                gl::DetachShader(self.prog_id, id);
+                gl::DeleteShader(id);
+            }
+
+            gl::DeleteProgram(self.prog_id);
+            gl::DeleteTextures(1, &self.tex_id);
+        }
+    }
+}
+
+/// Renders the stages drawing to the screen at a multiple of the window
+/// resolution, and averages blocks of pixels down to the window afterwards.
+#[derive(Debug)]
+pub struct Supersample {
+    pub factor: u32,
+    pub fb: Rc<FrameBuffer>,
+    prog_id: GLuint,
+    sh_ids: Vec<GLuint>,
+}
+
+impl Supersample {
+    pub fn new(factor: u32, width: u32, height: u32) -> Self {
+        let sh_ids = vec![
+            compile_shader(PASS_VERT, gl::VERTEX_SHADER).unwrap(),
+            compile_shader(DOWNSAMPLE_FRAG, gl::FRAGMENT_SHADER).unwrap(),
+        ];
+        let prog_id = link_program(&sh_ids).unwrap();
+
+        Self {
+            factor,
+            fb: Self::build_framebuffer(factor, width, height),
+            prog_id,
+            sh_ids,
+        }
+    }
+
+    fn build_framebuffer(factor: u32, width: u32, height: u32) -> Rc<FrameBuffer> {
+        let mut builder = TextureBuilder::new();
+        builder.stencil = true;
+        builder.build_framebuffer((factor * width, factor * height))
+    }
+
+    /// Resizes the buffer to match the window, if it doesn't already.
+    pub fn resize(&mut self, width: u32, height: u32) {
+        if self.fb.resolution()[..2] != [self.factor * width, self.factor * height] {
+            self.fb = Self::build_framebuffer(self.factor, width, height);
+        }
+    }
+
+    /// Averages the rendered image down to the window.
+    pub fn resolve(&self, vao: GLuint, width: u32, height: u32) {
+        unsafe {
+            gl::BindFramebuffer(gl::FRAMEBUFFER, 0);
+            gl::UseProgram(self.prog_id);
+            gl::Viewport(0, 0, width as _, height as _);
+            gl::BlendFunc(gl::ONE, gl::ZERO);
+            gl::Disable(gl::STENCIL_TEST);
+            gl_debug_check!();
+
+            gl::ActiveTexture(gl::TEXTURE0);
+            gl::BindTexture(gl::TEXTURE_2D, self.fb.tex_id);
+            let factor_loc = gl::GetUniformLocation(self.prog_id, b"factor\0".as_ptr() as _);
+            gl::Uniform1i(factor_loc, self.factor as _);
+            gl_debug_check!();
+
+            bind_position(self.prog_id);
+            draw_fullscreen(vao);
+            gl::BindTexture(gl::TEXTURE_2D, 0);
+            gl_debug_check!();
+        }
+    }
+}
+
+impl Drop for Supersample {
+    fn drop(&mut self) {
+        unsafe {
+            for &id in self.sh_ids.iter() {
+                gl::DetachShader(self.prog_id, id);
+                gl::DeleteShader(id);
+            }
+
+            gl::DeleteProgram(self.prog_id);
+        }
+    }
+}
+
+/// Draws the target of a stage with `show` over the whole screen.
+///
+/// Unlike a blit, drawing also works when the window is multisampled.
+#[derive(Debug)]
+pub struct ShowPass {
+    prog_id: GLuint,
+    sh_ids: Vec<GLuint>,
+}
+
+impl ShowPass {
+    pub fn new() -> Self {
+        let sh_ids = vec![
+            compile_shader(PASS_VERT, gl::VERTEX_SHADER).unwrap(),
+            compile_shader(OUTPUT_FRAG, gl::FRAGMENT_SHADER).unwrap(),
+        ];
+        let prog_id = link_program(&sh_ids).unwrap();
+
+        Self { prog_id, sh_ids }
+    }
+
+    /// Stretches the latest contents of a texture over the framebuffer `fb`
+    /// of the given size.
+    pub fn draw(&self, vao: GLuint, tex: &dyn Texture, fb: GLuint, width: u32, height: u32) {
+        unsafe {
+            gl::BindFramebuffer(gl::FRAMEBUFFER, fb);
+            gl::UseProgram(self.prog_id);
+            gl::Viewport(0, 0, width as _, height as _);
+            gl::BlendFunc(gl::ONE, gl::ZERO);
+            gl::Disable(gl::STENCIL_TEST);
+            gl::Disable(gl::SCISSOR_TEST);
+            gl_debug_check!();
+
+            gl::ActiveTexture(gl::TEXTURE0);
+            tex.bind(0);
+            let crop_loc = gl::GetUniformLocation(self.prog_id, b"crop\0".as_ptr() as _);
+            let res_loc = gl::GetUniformLocation(self.prog_id, b"resolution\0".as_ptr() as _);
+            gl::Uniform4f(crop_loc, 0.0, 0.0, 1.0, 1.0);
+            gl::Uniform2f(res_loc, width as _, height as _);
+            gl_debug_check!();
+
+            bind_position(self.prog_id);
+            draw_fullscreen(vao);
+            gl::BindTexture(gl::TEXTURE_2D, 0);
+            gl_debug_check!();
+        }
+    }
+}
+
+impl Drop for ShowPass {
+    fn drop(&mut self) {
+        unsafe {
+            for &id in self.sh_ids.iter() {
+                gl::DetachShader(self.prog_id, id);
+                gl::DeleteShader(id);
+            }
+
+            gl::DeleteProgram(self.prog_id);
+        }
+    }
+}
+
+/// An additional window showing a single buffer of the pipeline.
+///
+/// The window has its own context, which shares textures with the main
+/// context, so the buffer is only drawn to the window after the frame
+/// has been rendered.
+#[derive(Debug)]
+pub struct OutputWindow {
+    pub buffer: CString,
+    pub crop: [f32; 4],
+    pub context: glutin::WindowedContext<glutin::PossiblyCurrent>,
+    size: glutin::dpi::PhysicalSize<u32>,
+    vao: GLuint,
+    prog_id: GLuint,
+    sh_ids: Vec<GLuint>,
+}
+
+impl OutputWindow {
+    /// Opens the window, its context is current afterwards.
+    pub fn new<T: glutin::ContextCurrentState>(
+        config: &OutputConfig,
+        shared: &glutin::Context<T>,
+        events_loop: &glutin::event_loop::EventLoop<()>,
+    ) -> Result<Self, String> {
+        let title = config.title.as_deref().unwrap_or(&config.buffer);
+        let window_builder = glutin::window::WindowBuilder::new()
+            .with_inner_size(glutin::dpi::LogicalSize::new(1280.0, 720.0))
+            .with_resizable(true)
+            .with_title(title);
+
+        // waiting for vsync on every window would divide the frame rate
+        let built_context = glutin::ContextBuilder::new()
+            .with_vsync(false)
+            .with_shared_lists(shared)
+            .build_windowed(window_builder, events_loop)
+            .map_err(|e| format!("Failed to create output window {:?}: {}", title, e))?;
+
+        let context = unsafe {
+            built_context
+                .make_current()
+                .map_err(|(_, e)| format!("Failed to activate output window {:?}: {}", title, e))?
+        };
+
+        // vertex arrays are not shared between contexts
+        let mut vao = 0;
+        unsafe {
+            gl::GenVertexArrays(1, &mut vao);
+            gl_debug_check!();
+        }
+
+        let sh_ids = vec![
+            compile_shader(PASS_VERT, gl::VERTEX_SHADER).unwrap(),
+            compile_shader(OUTPUT_FRAG, gl::FRAGMENT_SHADER).unwrap(),
+        ];
+        let prog_id = link_program(&sh_ids).unwrap();
+        let size = context.window().inner_size();
+
+        Ok(Self {
+            buffer: CString::new(config.buffer.as_str()).unwrap(),
+            crop: config.crop,
+            context,
+            size,
+            vao,
+            prog_id,
+            sh_ids,
+        })
+    }
+
+    /// Draws the buffer to the window, its context is current afterwards.
+    pub fn present(&mut self, buffers: &HashMap<CString, Rc<dyn Texture>>) {
+        take_mut::take(&mut self.context, |s| unsafe { s.make_current().unwrap() });
+
+        let size = self.context.window().inner_size();
+        if size != self.size {
+            self.context.resize(size);
+            self.size = size;
+        }
+
+        unsafe {
+            gl::BindFramebuffer(gl::FRAMEBUFFER, 0);
+            gl::Viewport(0, 0, size.width as _, size.height as _);
+            gl::ClearColor(0.0, 0.0, 0.0, 1.0);
+            gl::Clear(gl::COLOR_BUFFER_BIT);
+            gl_debug_check!();
+
+            // only 2D buffers can be shown, others leave the window black
+            if let Some(tex) = buffers.get(&self.buffer) {
+                let [_, height, depth] = tex.resolution();
+                if height > 0 && depth == 0 {
+                    gl::UseProgram(self.prog_id);
+                    gl::ActiveTexture(gl::TEXTURE0);
+                    tex.bind(0);
+
+                    let crop_loc = gl::GetUniformLocation(self.prog_id, b"crop\0".as_ptr() as _);
+                    let res_loc =
+                        gl::GetUniformLocation(self.prog_id, b"resolution\0".as_ptr() as _);
+                    let [x, y, w, h] = self.crop;
+                    gl::Uniform4f(crop_loc, x, y, w, h);
+                    gl::Uniform2f(res_loc, size.width as _, size.height as _);
+                    gl_debug_check!();
+
+                    bind_position(self.prog_id);
+                    draw_fullscreen(self.vao);
+                    gl::BindTexture(gl::TEXTURE_2D, 0);
+                    gl_debug_check!();
+                }
+            }
+        }
+
+        self.context.swap_buffers().unwrap();
+    }
+}
+
+impl Drop for OutputWindow {
+    fn drop(&mut self) {
+        take_mut::take(&mut self.context, |s| unsafe { s.make_current().unwrap() });
+
+        unsafe {
+            for &id in self.sh_ids.iter() {
+                gl::DetachShader(self.prog_id, id);
+                gl::DeleteShader(id);
+            }
+
+            gl::DeleteProgram(self.prog_id);
+            gl::DeleteVertexArrays(1, &self.vao);
+        }
+    }
+}
+
+/// Points the position attribute of a program at the fullscreen rectangle.
+fn bind_position(prog_id: GLuint) {
+    unsafe {
+        let pos_attr = gl::GetAttribLocation(prog_id, POSITION_NAME.as_ptr());
+        if pos_attr != -1 {
+            gl::EnableVertexAttribArray(pos_attr as GLuint);
+            gl::VertexAttribPointer(
+                pos_attr as GLuint,
+                2,
+                gl::FLOAT,
+                gl::FALSE as GLboolean,
+                0,
+                std::ptr::null(),
+            );
+            gl_debug_check!();
+        }
+    }
+}
@@ -3,6 +3,7 @@ use std::{
     ffi::CString,
     path::Path,
     rc::Rc,
+    time::{Duration, Instant},
 };
 
 use async_std::task::yield_now;
@@ -11,6 +12,18 @@ use serde_yaml::Value;
 use super::uniforms::*;
 use crate::{jockey::*, util::Cache};
 
+/// Number of times a pipeline file is read before giving up on it
+const LOAD_ATTEMPTS: u32 = 3;
+
+/// Time to wait after the first failed read, doubled after every attempt
+const LOAD_RETRY_DELAY: Duration = Duration::from_millis(30);
+
+/// Color the screen is cleared to at the start of every frame by default
+const SCREEN_CLEAR_COLOR: [f32; 4] = [0.0, 0.0, 0.0, 1.0];
+
+/// Color render targets are reset to by default
+const RESET_COLOR: [f32; 4] = [0.0, 0.0, 0.0, 0.0];
+
 /// Type alias for box containing a partially build pipeline
 pub type PipelinePartial = Box<dyn Future<Output = Result<(Pipeline, UpdateRequest), String>>>;
 
@@ -40,8 +53,23 @@ impl Default for UpdateRequest {
 pub struct Pipeline {
     pub stages: Vec<Stage>,
     pub buffers: HashMap<CString, Rc<dyn Texture>>,
+    pub storage: HashMap<CString, StorageBuffer>,
     pub requested_ndi_sources: HashMap<CString, String>,
+    pub videos: HashMap<CString, Video>,
     pub blending: bool,
+    pub clear_screen: Option<[f32; 4]>,
+    pub supersample: u32,
+    /// Problems which don't stop the pipeline from building
+    pub warnings: Vec<String>,
+    /// Images render targets are filled with when they are created
+    pub seeds: HashMap<CString, image::RgbaImage>,
+    /// Render targets cleared by [`Pipeline::reset_buffers`]
+    pub reset_targets: Vec<CString>,
+    pub reset_color: [f32; 4],
+    /// Number of times each texture or storage buffer was written, for `skip_unchanged`
+    pub versions: HashMap<CString, u64>,
+    /// Whether nothing was drawn since the pipeline was built or reset
+    pub first_frame: bool,
 }
 
 impl Pipeline {
@@ -57,12 +85,37 @@ impl Pipeline {
 
         let stages = vec![Stage {
             prog_id,
+            name: None,
+            after: Vec::new(),
             target: None,
-            kind: StageKind::Frag {},
+            kind: StageKind::Frag { geometry: None },
             sh_ids,
+            sources: Vec::new(),
             deps: Vec::new(),
+            storage_deps: Vec::new(),
+            image_deps: Vec::new(),
+            image_formats: Vec::new(),
+            barrier: 0,
+            common_block: false,
             unis: HashMap::new(),
+            locs: Locations::new(prog_id, &HashMap::new()),
+            info: ProgramInfo::new(prog_id),
+            controls: Vec::new(),
             blend: None,
+            accumulate: None,
+            viewport: None,
+            scissor: None,
+            target_mip: 0,
+            init_image: None,
+            show: false,
+            stencil: None,
+            interval: 1,
+            once: false,
+            ran: false,
+            skip_unchanged: false,
+            last_inputs: None,
+            condition: None,
+            budget: None,
             perf: RunningAverage::new(),
             builder: TextureBuilder::new(),
         }];
@@ -70,8 +123,18 @@ impl Pipeline {
         Self {
             stages,
             buffers: HashMap::new(),
+            storage: HashMap::new(),
             requested_ndi_sources: HashMap::new(),
+            videos: HashMap::new(),
             blending: false,
+            clear_screen: Some(SCREEN_CLEAR_COLOR),
+            supersample: 1,
+            warnings: Vec::new(),
+            seeds: HashMap::new(),
+            reset_targets: Vec::new(),
+            reset_color: RESET_COLOR,
+            versions: HashMap::new(),
+            first_frame: true,
         }
     }
 
@@ -88,16 +151,7 @@ impl Pipeline {
         screen_size: (u32, u32),
         cache: &HashMap<CString, Rc<dyn Texture>>,
     ) -> Result<(Self, UpdateRequest), String> {
-        let reader = match std::fs::File::open(path) {
-            Ok(s) => s,
-            Err(e) => return Err(e.to_string()),
-        };
-
-        let object = match serde_yaml::from_reader(reader) {
-            Ok(s) => s,
-            Err(e) => return Err(e.to_string()),
-        };
-
+        let object = read_yaml(path.as_ref()).await?;
         Pipeline::from_yaml_with_cache(object, screen_size, cache).await
     }
 
@@ -280,6 +334,14 @@ impl Pipeline {
                 None => Rc::new(make_noise()),
             };
             buffers.insert(noise_name, noise);
+
+            // the blue noise is always the same, so it's only generated once
+            let blue_noise_name = BLUE_NOISE_NAME.clone();
+            let blue_noise = match cache.get(&blue_noise_name) {
+                Some(old) => Rc::clone(old),
+                None => Rc::new(make_blue_noise()),
+            };
+            buffers.insert(blue_noise_name, blue_noise);
         }
 
         yield_now().await;
@@ -392,6 +454,116 @@ impl Pipeline {
             buffers.insert(name, tex);
         }
 
+        // parse videos section
+        let video_objects = match object.get("videos") {
+            Some(Value::Sequence(s)) => s.clone(),
+            None => Vec::new(),
+            Some(s) => return Err(format!("Expected \"videos\" to be an array, got {:?}", s)),
+        };
+
+        let mut videos = HashMap::new();
+        for object in video_objects {
+            let path = match object.get("path") {
+                Some(Value::String(s)) => s,
+                s => return Err(format!("Expected \"path\" to be a string, got {:?}", s)),
+            };
+
+            let name = match object.get("name") {
+                Some(Value::String(s)) => CString::new(s.as_str()).unwrap(),
+                s => return Err(format!("Expected \"name\" to be a string, got {:?}", s)),
+            };
+
+            let speed = match object.get("speed").map(Value::as_f64) {
+                Some(Some(f)) => f as f32,
+                None => 1.0,
+                Some(None) => {
+                    return Err(format!(
+                        "Expected \"speed\" of video {:?} to be a number, got {:?}",
+                        name,
+                        object.get("speed")
+                    ))
+                }
+            };
+
+            let looping = match object.get("loop").map(Value::as_bool) {
+                Some(Some(flag)) => flag,
+                None => true,
+                Some(None) => {
+                    return Err(format!(
+                        "Expected \"loop\" of video {:?} to be a bool, got {:?}",
+                        name,
+                        object.get("loop")
+                    ))
+                }
+            };
+
+            // check if name is already in use
+            if buffers.get(&name).is_some() {
+                return Err(format!(
+                    "Texture {:?} already exists, please try a different name",
+                    name
+                ));
+            }
+
+            let tex = TextureBuilder::parse(&object, false, true)?
+                .set_float(false)
+                .set_resolution(vec![1, 1])
+                .build_texture();
+
+            videos.insert(name.clone(), Video::open(path, speed, looping)?);
+            buffers.insert(name, tex);
+            yield_now().await;
+        }
+
+        // parse storage buffer section
+        let storage_objects = match object.get("storage") {
+            Some(Value::Sequence(s)) => s.clone(),
+            None => Vec::new(),
+            Some(s) => return Err(format!("Expected \"storage\" to be an array, got {:?}", s)),
+        };
+
+        let mut storage = HashMap::new();
+        for object in storage_objects {
+            let name = match object.get("name") {
+                Some(Value::String(s)) => CString::new(s.as_str()).unwrap(),
+                s => return Err(format!("Expected \"name\" to be a string, got {:?}", s)),
+            };
+
+            let size = match object.get("size").map(Value::as_u64) {
+                Some(Some(n)) if n > 0 => n as usize,
+                s => {
+                    return Err(format!(
+                        "Expected \"size\" of storage buffer {:?} to be a positive number of bytes, got {:?}",
+                        name, s
+                    ))
+                }
+            };
+
+            let persistent = match object.get("persistent") {
+                Some(Value::Bool(b)) => *b,
+                None => false,
+                s => {
+                    return Err(format!(
+                        "Expected \"persistent\" of storage buffer {:?} to be a bool, got {:?}",
+                        name, s
+                    ))
+                }
+            };
+
+            // check if name is already in use
+            if buffers.contains_key(&name) || storage.contains_key(&name) {
+                return Err(format!(
+                    "Buffer {:?} already exists, please try a different name",
+                    name
+                ));
+            }
+
+            let mut buffer = StorageBuffer::new(size);
+            buffer.persistent = persistent;
+            storage.insert(name, buffer);
+            yield_now().await;
+        }
+
         // parse stages section
         let passes = match object.get("stages") {
             Some(Value::Sequence(s)) => s.clone(),
@@ -399,22 +571,105 @@ impl Pipeline {
             s => return Err(format!("Expected \"stages\" to be an array, got {:?}", s)),
         };
 
+        // get global mipmap flag
+        let mipmaps = match object.get("mipmaps").map(Value::as_bool) {
+            Some(Some(flag)) => flag,
+            None => true,
+            Some(s) => return Err(format!("Expected \"mipmaps\" to be a bool, got {:?}", s)),
+        };
+
+        // render screen sized targets and the screen itself at a multiple of the window size
+        let supersample = match object.get("supersample").map(Value::as_u64) {
+            Some(Some(n)) if (1..=4).contains(&n) => n as u32,
+            None => 1,
+            Some(_) => {
+                return Err(format!(
+                    "Expected \"supersample\" to be a number from 1 to 4, got {:?}",
+                    object.get("supersample")
+                ))
+            }
+        };
+        let screen_size = (supersample * screen_size.0, supersample * screen_size.1);
+
         // parse stages
         let mut stages = Vec::with_capacity(passes.len());
         for pass in passes {
-            let stage = Stage::from_yaml(pass)?;
+            let mut stage = Stage::from_yaml(pass)?;
+            if !mipmaps {
+                stage.builder.set_mipmap(false);
+            }
+
             stages.push(stage);
             yield_now().await;
         }
 
+        // run stages after the ones they depend on
+        let nodes: Vec<_> = stages
+            .iter()
+            .map(|s| (s.name.as_deref(), s.after.as_slice()))
+            .collect();
+        let order = execution_order(&nodes)?;
+        let mut slots: Vec<_> = stages.into_iter().map(Some).collect();
+        let mut stages: Vec<_> = order.iter().map(|&k| slots[k].take().unwrap()).collect();
+
+        // targets that are drawn to with a stencil test need a stencil attachment
+        let stencil_targets: HashSet<_> = stages
+            .iter()
+            .filter(|s| s.stencil.is_some())
+            .filter_map(|s| s.target.clone())
+            .collect();
+
+        // targets with stages drawing to a specific mip level need all their mip levels
+        let mip_targets: HashSet<_> = stages
+            .iter()
+            .filter(|s| s.target_mip > 0)
+            .filter_map(|s| s.target.clone())
+            .collect();
+
+        for stage in stages.iter_mut() {
+            if let Some(target) = &stage.target {
+                stage.builder.stencil = stencil_targets.contains(target);
+                if mip_targets.contains(target) {
+                    stage.builder.set_mipmap(true);
+                }
+            }
+        }
+
+        // stages can't draw to mip levels which are cut off or don't exist
+        for stage in stages.iter().filter(|s| s.target_mip > 0) {
+            if let Some(target) = &stage.target {
+                let [width, height] = match stage.builder.resolution.as_slice() {
+                    &[w, h] => [w, h],
+                    _ => [screen_size.0, screen_size.1],
+                };
+                let full = mip_level_count(width, height);
+                let levels = stage.builder.mip_levels.map_or(full, |n| n.min(full));
+
+                if stage.target_mip >= levels {
+                    return Err(format!(
+                        "Stage drawing to {:?} has a \"target_mip\" of {}, but the target only has {} mip levels",
+                        target, stage.target_mip, levels
+                    ));
+                }
+            }
+        }
+
         // create render targets for stages
         let mut res_map = HashMap::new();
+        let mut format_map = HashMap::new();
         for stage in stages.iter() {
             let target = match &stage.target {
                 Some(s) => s,
                 None => continue,
             };
 
+            if storage.contains_key(target) {
+                return Err(format!(
+                    "Target {:?} is already used as a storage buffer, please try a different name",
+                    target
+                ));
+            }
+
             // check if target exists already
             let stage_res = stage.resolution();
             if buffers.contains_key(target) {
@@ -427,6 +682,18 @@ impl Pipeline {
                         ));
                     }
 
+                    // the target is only created once, so the formats must match
+                    if let Some(&buffer_format) = format_map.get(target.as_c_str()) {
+                        if buffer_format != stage.builder.texture_format() {
+                            return Err(format!(
+                                "Texture {:?} already has a different format, got {:?} and {:?}",
+                                target,
+                                buffer_format,
+                                stage.builder.texture_format()
+                            ));
+                        }
+                    }
+
                     // don't create the same target twice
                     continue;
                 } else {
@@ -439,11 +706,12 @@ impl Pipeline {
 
             // record specified stage resolution
             res_map.insert(target.as_c_str(), stage_res);
+            format_map.insert(target.as_c_str(), stage.builder.texture_format());
 
             // create textures
             let texture: Rc<dyn Texture> = match stage.kind {
                 StageKind::Frag { .. } | StageKind::Vert { .. } => {
-                    stage.builder.build_double_framebuffer(screen_size)
+                    build_target(&stages, stage, screen_size)
                 }
                 StageKind::Comp { .. } => stage.builder.build_image(),
             };
@@ -453,9 +721,69 @@ impl Pipeline {
             yield_now().await;
         }
 
+        // fill render targets with the image they start out with
+        let mut seeds = HashMap::new();
+        for stage in stages.iter() {
+            let (target, path) = match (&stage.target, &stage.init_image) {
+                (Some(target), Some(path)) => (target, path),
+                _ => continue,
+            };
+
+            if seeds.contains_key(target) {
+                return Err(format!(
+                    "Target {:?} has more than one \"init_image\"",
+                    target
+                ));
+            }
+
+            if stage.builder.texture_format().is_integer() {
+                return Err(format!(
+                    "Target {:?} has an integer format and can't be filled with an image",
+                    target
+                ));
+            }
+
+            if let Some([_, height, depth]) = stage.resolution() {
+                if height == 0 || depth > 0 {
+                    return Err(format!(
+                        "Only 2D targets can be filled with an image, got {:?}",
+                        target
+                    ));
+                }
+            }
+
+            let reader = image::io::Reader::open(path).map_err(|_| {
+                format!("Failed to open image of target {:?} at {:?}", target, path)
+            })?;
+            yield_now().await;
+
+            let dyn_image = reader.decode().map_err(|_| {
+                format!(
+                    "Failed to decode image of target {:?} at {:?}",
+                    target, path
+                )
+            })?;
+            yield_now().await;
+
+            let image = dyn_image.flipv().to_rgba8();
+            seed_target(buffers[target].as_ref(), &image);
+            seeds.insert(target.clone(), image);
+            yield_now().await;
+        }
+
         // compute uniform dependencies
         let mut used_buffers = HashSet::new();
         for stage in stages.iter_mut() {
+            // connect the common uniform block, if the stage declares it
+            let block_index =
+                unsafe { gl::GetUniformBlockIndex(stage.prog_id, COMMON_BLOCK_NAME.as_ptr()) };
+            if block_index != gl::INVALID_INDEX {
+                unsafe {
+                    gl::UniformBlockBinding(stage.prog_id, block_index, COMMON_BLOCK_BINDING)
+                };
+                stage.common_block = true;
+            }
+
             for tex_name in buffers.keys() {
                 // try to locate the uniform in the program
                 let loc = unsafe { gl::GetUniformLocation(stage.prog_id, tex_name.as_ptr()) };
@@ -463,21 +791,249 @@ impl Pipeline {
                 // add uniform to list of dependencies
                 if loc != -1 {
                     stage.deps.push(tex_name.clone());
+                    stage.locs.add_dep(stage.prog_id, tex_name);
                     used_buffers.insert(tex_name.clone());
+
+                    // images may be written to by any stage, not just compute stages
+                    if uniform_is_image(stage.prog_id, tex_name) {
+                        stage.image_deps.push(tex_name.clone());
+                    }
+                }
+            }
+
+            for buffer_name in storage.keys() {
+                let index = unsafe {
+                    gl::GetProgramResourceIndex(
+                        stage.prog_id,
+                        gl::SHADER_STORAGE_BLOCK,
+                        buffer_name.as_ptr(),
+                    )
+                };
+
+                // storage buffers are bound in the order they are listed
+                if index != gl::INVALID_INDEX {
+                    let binding = stage.storage_deps.len();
+                    unsafe { gl::ShaderStorageBlockBinding(stage.prog_id, index, binding as _) };
+                    stage.storage_deps.push(buffer_name.clone());
                 }
             }
 
             yield_now().await;
         }
 
+        // every dependency is bound to its own texture unit
+        let max_units = max_texture_units();
+        for stage in stages.iter() {
+            if stage.deps.len() > max_units {
+                return Err(format!(
+                    "Textures used by {} exceed the {} available texture units, got {}",
+                    stage.describe(),
+                    max_units,
+                    stage.deps.len()
+                ));
+            }
+        }
+
+        // images are read and written with the format of the buffer, so the
+        // declaration has to match it
+        for stage in stages.iter() {
+            for name in stage.image_deps.iter() {
+                let format = match buffers[name].image_format() {
+                    Some(format) if format.is_image_compatible() => format,
+                    Some(format) => {
+                        return Err(format!(
+                            "Image {:?} of {} has format {:?}, which can't be bound as an image",
+                            name,
+                            stage.describe(),
+                            format.qualifier()
+                        ))
+                    }
+                    None => {
+                        return Err(format!(
+                            "Image {:?} of {} is not the target of a compute stage, use a sampler to read it instead",
+                            name,
+                            stage.describe()
+                        ))
+                    }
+                };
+
+                let declared = stage.image_formats.iter().find(|(n, _)| n == name);
+                if let Some((_, qualifier)) = declared {
+                    if TextureFormat::from_qualifier(qualifier) != Some(format) {
+                        return Err(format!(
+                            "Image {:?} of {} is declared as {:?}, but the buffer has format {:?}",
+                            name,
+                            stage.describe(),
+                            qualifier,
+                            format.qualifier()
+                        ));
+                    }
+                }
+            }
+        }
+
+        // compute stages can only write through images and storage buffers
+        for stage in stages.iter() {
+            if let StageKind::Comp { .. } = stage.kind {
+                if stage.target.is_none()
+                    && stage.storage_deps.is_empty()
+                    && stage.image_deps.is_empty()
+                {
+                    return Err(format!(
+                        "Compute {} has neither a target nor uses any image or storage buffer to write to",
+                        stage.describe()
+                    ));
+                }
+            }
+        }
+
+        // make writes through images and storage buffers visible to later reads
+        let barriers: Vec<_> = stages.iter().map(|s| memory_barrier(s, &stages)).collect();
+        for (stage, barrier) in stages.iter_mut().zip(barriers) {
+            stage.barrier = barrier;
+        }
+
         // check for blend modes
         let blending = stages.iter().any(|s| s.blend.is_some());
 
+        // the screen is cleared before drawing
+        let clear_screen = match object.get("clear_screen") {
+            Some(Value::Bool(true)) => Some(SCREEN_CLEAR_COLOR),
+            Some(Value::Bool(false)) => None,
+            Some(Value::Sequence(xs)) if xs.len() == 4 => {
+                let mut color = [0.0; 4];
+                for (c, x) in color.iter_mut().zip(xs) {
+                    *c = match x.as_f64() {
+                        Some(f) => f as _,
+                        None => {
+                            return Err(format!(
+                                "Expected \"clear_screen\" color to contain floats, got {:?}",
+                                x
+                            ))
+                        }
+                    };
+                }
+                Some(color)
+            }
+            None => Some(SCREEN_CLEAR_COLOR),
+            Some(s) => {
+                return Err(format!(
+                    "Expected \"clear_screen\" to be a bool or an RGBA color, got {:?}",
+                    s
+                ))
+            }
+        };
+
+        // render targets which can be cleared on demand, e.g. saturated feedback
+        let render_targets = stages
+            .iter()
+            .filter(|s| matches!(s.kind, StageKind::Frag { .. } | StageKind::Vert { .. }))
+            .filter_map(|s| s.target.clone())
+            .fold(Vec::new(), |mut names, name| {
+                if !names.contains(&name) {
+                    names.push(name);
+                }
+                names
+            });
+
+        let reset = match object.get("reset") {
+            Some(Value::Mapping(map)) => map.clone(),
+            None => Default::default(),
+            Some(s) => return Err(format!("Expected \"reset\" to be a map, got {:?}", s)),
+        };
+
+        let reset_targets = match reset.get("targets") {
+            Some(Value::Sequence(xs)) => {
+                let mut names = Vec::with_capacity(xs.len());
+                for x in xs {
+                    let name = match x.as_str() {
+                        Some(s) => CString::new(s).unwrap(),
+                        None => {
+                            return Err(format!(
+                                "Expected \"reset.targets\" to contain strings, got {:?}",
+                                x
+                            ))
+                        }
+                    };
+
+                    if !render_targets.contains(&name) {
+                        return Err(format!(
+                            "Expected \"reset.targets\" to name render targets of fragment or vertex stages, got {:?}",
+                            name
+                        ));
+                    }
+
+                    names.push(name);
+                }
+                names
+            }
+            None => render_targets,
+            Some(s) => {
+                return Err(format!(
+                    "Expected \"reset.targets\" to be a list of target names, got {:?}",
+                    s
+                ))
+            }
+        };
+
+        let reset_color = match reset.get("color") {
+            Some(Value::Sequence(xs)) if xs.len() == 4 => {
+                let mut color = [0.0; 4];
+                for (c, x) in color.iter_mut().zip(xs) {
+                    *c = match x.as_f64() {
+                        Some(f) => f as _,
+                        None => {
+                            return Err(format!(
+                                "Expected \"reset.color\" to contain floats, got {:?}",
+                                x
+                            ))
+                        }
+                    };
+                }
+                color
+            }
+            None => RESET_COLOR,
+            Some(s) => {
+                return Err(format!(
+                    "Expected \"reset.color\" to be an RGBA color, got {:?}",
+                    s
+                ))
+            }
+        };
+
+        // uniforms set with the wrong type are silently ignored by the driver
+        let mut warnings = Vec::new();
+        for stage in stages.iter() {
+            for (name, uniform) in stage.unis.iter() {
+                let name = name.to_string_lossy();
+                let declared = match stage.info.uniforms.iter().find(|v| v.name == name) {
+                    Some(var) => var,
+                    None => continue,
+                };
+
+                if declared.type_ != uniform.gl_type() {
+                    let declared_type = match declared.type_name() {
+                        Some(type_name) => type_name.to_owned(),
+                        None => format!("0x{:04x}", declared.type_),
+                    };
+
+                    warnings.push(format!(
+                        "Uniform {:?} in {} is declared as {}, but set to a {} value",
+                        name,
+                        stage.describe(),
+                        declared_type,
+                        uniform.glsl_type()
+                    ));
+                }
+            }
+        }
+
         // remove unnecessary buffers
         buffers.retain(|name, _| {
             let needed = used_buffers.contains(name);
             if !needed {
                 requested_ndi_sources.remove(name);
+                videos.remove(name);
             }
             needed
         });
@@ -486,8 +1042,18 @@ impl Pipeline {
             Self {
                 stages,
                 buffers,
+                storage,
                 requested_ndi_sources,
+                videos,
                 blending,
+                clear_screen,
+                supersample,
+                warnings,
+                seeds,
+                reset_targets,
+                reset_color,
+                versions: HashMap::new(),
+                first_frame: true,
             },
             UpdateRequest {
                 audio_samples,
@@ -498,8 +1064,11 @@ impl Pipeline {
     }
 
     pub fn resize_buffers(&mut self, width: u32, height: u32) {
+        let (width, height) = (self.supersample * width, self.supersample * height);
+        let mut resized = Vec::new();
         for stage in self.stages.iter() {
-            if !stage.builder.resolution.is_empty() {
+            // compute stages without a target don't have a resolution either
+            if !stage.builder.resolution.is_empty() || stage.target.is_none() {
                 continue;
             }
 
@@ -513,10 +1082,269 @@ impl Pipeline {
                 _ => continue,
             };
 
-            self.buffers.insert(
-                name,
-                stage.builder.build_double_framebuffer((width, height)),
-            );
+            let texture = build_target(&self.stages, stage, (width, height));
+            if let Some(image) = self.seeds.get(&name) {
+                seed_target(texture.as_ref(), image);
+            }
+
+            self.buffers.insert(name.clone(), texture);
+            resized.push(name);
         }
+
+        // the new targets are empty, so stages running once have to fill them again
+        for stage in self.stages.iter_mut() {
+            if stage.target.as_ref().map_or(false, |t| resized.contains(t)) {
+                stage.ran = false;
+                stage.last_inputs = None;
+            }
+        }
+
+        for name in resized.iter() {
+            self.bump_version(name);
+        }
+    }
+
+    /// Clears the render targets listed in `reset` to the reset color, and
+    /// restores their initial state from `init_image` and stages running once.
+    pub fn reset_buffers(&mut self) {
+        self.first_frame = true;
+        for name in self.reset_targets.iter() {
+            let texture = match self.buffers.get(name) {
+                Some(texture) => texture,
+                None => continue,
+            };
+
+            texture.clear(self.reset_color);
+            if let Some(image) = self.seeds.get(name) {
+                seed_target(texture.as_ref(), image);
+            }
+
+            *self.versions.entry(name.clone()).or_default() += 1;
+        }
+
+        for stage in self.stages.iter_mut() {
+            if stage
+                .target
+                .as_ref()
+                .map_or(false, |t| self.reset_targets.contains(t))
+            {
+                stage.ran = false;
+                stage.last_inputs = None;
+            }
+        }
+    }
+
+    /// Marks a texture or storage buffer as written, so stages reading it
+    /// with `skip_unchanged` run again.
+    pub fn bump_version(&mut self, name: &CString) {
+        *self.versions.entry(name.clone()).or_default() += 1;
+    }
+
+    /// Marks the textures updated from outside the pipeline every frame as
+    /// written, i.e. audio, video and NDI textures.
+    pub fn bump_live_versions(&mut self) {
+        let audio = [
+            &*SAMPLES_NAME,
+            &*SPECTRUM_RAW_NAME,
+            &*SPECTRUM_NAME,
+            &*SPECTRUM_SMOOTH_NAME,
+            &*SPECTRUM_INTEGRATED_NAME,
+            &*SPECTRUM_SMOOTH_INTEGRATED_NAME,
+        ];
+
+        let live = audio
+            .iter()
+            .map(|name| (*name).clone())
+            .chain(self.videos.keys().cloned())
+            .chain(self.requested_ndi_sources.keys().cloned())
+            .collect::<Vec<_>>();
+
+        for name in live.iter() {
+            self.bump_version(name);
+        }
+    }
+}
+
+/// Finds the barrier bits needed after the given stage, so that all stages
+/// reading what it may have written see the new values. This includes stages
+/// running earlier in the pipeline, which read the results on the next frame.
+fn memory_barrier(stage: &Stage, stages: &[Stage]) -> GLbitfield {
+    let mut barrier = 0;
+    if !stage.storage_deps.is_empty() {
+        barrier |= gl::SHADER_STORAGE_BARRIER_BIT;
+    }
+
+    for name in stage.image_deps.iter() {
+        // written images can also be read back for screenshots or the color probe
+        barrier |= gl::TEXTURE_UPDATE_BARRIER_BIT;
+
+        for other in stages.iter().filter(|s| s.deps.contains(name)) {
+            barrier |= match other.image_deps.contains(name) {
+                true => gl::SHADER_IMAGE_ACCESS_BARRIER_BIT,
+                false => gl::TEXTURE_FETCH_BARRIER_BIT,
+            };
+        }
+    }
+
+    barrier
+}
+
+/// Reads and parses a pipeline file.
+///
+/// Editors that don't save files atomically may briefly leave a truncated
+/// file behind, so failed reads are retried a few times with a growing delay
+/// before the error is reported.
+async fn read_yaml(path: &Path) -> Result<Value, String> {
+    let mut delay = LOAD_RETRY_DELAY;
+    let mut attempt = 1;
+
+    loop {
+        let result = std::fs::File::open(path)
+            .map_err(|e| e.to_string())
+            .and_then(|reader| serde_yaml::from_reader(reader).map_err(|e| e.to_string()));
+
+        match result {
+            Ok(object) => return Ok(object),
+            Err(err) if attempt >= LOAD_ATTEMPTS => return Err(err),
+            Err(err) => log::warn!("Failed to read pipeline file, retrying: {}", err),
+        }
+
+        // wait without blocking the render loop
+        let start = Instant::now();
+        while start.elapsed() < delay {
+            yield_now().await;
+        }
+
+        delay *= 2;
+        attempt += 1;
+    }
+}
+
+/// Sorts the stages topologically by their `after` dependencies.
+///
+/// Each node is the name of a stage and the names of the stages it has to
+/// run after. Stages without a dependency between them keep their order
+/// from the pipeline file. Returns the indices of the stages in execution order.
+fn execution_order(nodes: &[(Option<&str>, &[String])]) -> Result<Vec<usize>, String> {
+    let label = |k: usize| match nodes[k].0 {
+        Some(name) => format!("\"{}\"", name),
+        None => format!("stage {}", k),
+    };
+
+    let mut indices = HashMap::new();
+    for (k, (name, _)) in nodes.iter().enumerate() {
+        if let Some(name) = name {
+            if indices.insert(*name, k).is_some() {
+                return Err(format!("Stage name \"{}\" is used more than once", name));
+            }
+        }
+    }
+
+    let mut deps = Vec::with_capacity(nodes.len());
+    for (k, (_, after)) in nodes.iter().enumerate() {
+        let mut list = Vec::with_capacity(after.len());
+        for name in after.iter() {
+            match indices.get(name.as_str()) {
+                Some(&dep) => list.push(dep),
+                None => {
+                    return Err(format!(
+                        "{} runs after \"{}\", but there is no stage with that name",
+                        label(k),
+                        name
+                    ))
+                }
+            }
+        }
+        deps.push(list);
+    }
+
+    let mut done = vec![false; nodes.len()];
+    let mut order = Vec::with_capacity(nodes.len());
+    while order.len() < nodes.len() {
+        let next = (0..nodes.len()).find(|&k| !done[k] && deps[k].iter().all(|&d| done[d]));
+        match next {
+            Some(k) => {
+                done[k] = true;
+                order.push(k);
+            }
+            None => {
+                let cycle: Vec<_> = (0..nodes.len()).filter(|&k| !done[k]).map(label).collect();
+                return Err(format!(
+                    "Stages depend on each other in a cycle: {}",
+                    cycle.join(", ")
+                ));
+            }
+        }
+    }
+
+    Ok(order)
+}
+
+/// Creates the render target of a fragment or vertex shader stage.
+///
+/// Targets that some stage draws to without overwriting all of their
+/// contents, like accumulation buffers or stages with a viewport, are
+/// single buffered, so all of those stages see each other's output.
+fn build_target(stages: &[Stage], stage: &Stage, screen_size: (u32, u32)) -> Rc<dyn Texture> {
+    let single = stages
+        .iter()
+        .any(|s| s.target == stage.target && s.single_buffered());
+
+    match single {
+        true => stage.builder.build_framebuffer(screen_size),
+        false => stage.builder.build_double_framebuffer(screen_size),
+    }
+}
+
+/// Fills a render target with an image, scaled to the size of the target.
+fn seed_target(tex: &dyn Texture, image: &image::RgbaImage) {
+    let [width, height, _] = tex.resolution();
+    if image.dimensions() == (width, height) {
+        tex.fill_rgba8(image.as_raw());
+        return;
+    }
+
+    let filter = image::imageops::FilterType::Triangle;
+    let scaled = image::imageops::resize(image, width, height, filter);
+    tex.fill_rgba8(scaled.as_raw());
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn execution_order_keeps_file_order() {
+        let nodes = [(None, &[][..]), (Some("a"), &[][..]), (None, &[][..])];
+        assert_eq!(execution_order(&nodes), Ok(vec![0, 1, 2]));
+    }
+
+    #[test]
+    fn execution_order_sorts_dependencies() {
+        let after_b = ["b".to_string()];
+        let after_c = ["c".to_string()];
+        let nodes = [
+            (Some("a"), &after_b[..]),
+            (Some("b"), &after_c[..]),
+            (Some("c"), &[][..]),
+            (None, &[][..]),
+        ];
+        assert_eq!(execution_order(&nodes), Ok(vec![2, 1, 0, 3]));
+    }
+
+    #[test]
+    fn execution_order_errors() {
+        let after_a = ["a".to_string()];
+        let after_b = ["b".to_string()];
+        let after_x = ["x".to_string()];
+
+        let cycle = [(Some("a"), &after_b[..]), (Some("b"), &after_a[..])];
+        assert!(execution_order(&cycle).is_err());
+
+        let unknown = [(Some("a"), &after_x[..])];
+        assert!(execution_order(&unknown).is_err());
+
+        let duplicate = [(Some("a"), &[][..]), (Some("a"), &[][..])];
+        assert!(execution_order(&duplicate).is_err());
     }
 }
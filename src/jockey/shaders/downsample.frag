@@ -0,0 +1,18 @@
+#version 140
+
+uniform sampler2D screen;
+uniform int factor;
+out vec4 color;
+
+void main() {
+    // average the block of pixels covered by this one
+    ivec2 base = ivec2(gl_FragCoord.xy) * factor;
+    vec4 sum = vec4(0);
+    for (int y = 0; y < factor; y++) {
+        for (int x = 0; x < factor; x++) {
+            sum += texelFetch(screen, base + ivec2(x, y), 0);
+        }
+    }
+
+    color = sum / float(factor * factor);
+}
@@ -0,0 +1,11 @@
+#version 140
+
+uniform sampler2D screen;
+uniform vec4 crop;
+uniform vec2 resolution;
+out vec4 color;
+
+void main() {
+    vec2 uv = gl_FragCoord.xy / resolution;
+    color = texture(screen, crop.xy + uv * crop.zw);
+}
@@ -0,0 +1,40 @@
+#pragma once
+
+// Uniforms shared by all stages, see the readme for what they contain.
+// Include this instead of declaring the uniforms one by one.
+layout(std140) uniform Sh4derJockey {
+    float time;
+    float time_since_build;
+    float time_delta;
+    int frame_count;
+
+    float beat;
+    float beat_phase;
+    float beat_sine;
+    float xfade;
+
+    float refresh_rate;
+    float target_dt;
+    float dpi_scale;
+
+    vec3 volume;
+    vec3 volume_integrated;
+    vec3 bass;
+    vec3 bass_smooth;
+    vec3 bass_integrated;
+    vec3 bass_smooth_integrated;
+    vec3 mid;
+    vec3 mid_smooth;
+    vec3 mid_integrated;
+    vec3 mid_smooth_integrated;
+    vec3 high;
+    vec3 high_smooth;
+    vec3 high_integrated;
+    vec3 high_smooth_integrated;
+
+    float beat_history[8];
+    float sliders[32];
+    vec4 buttons[32];
+    float lfo[8];
+    float beat_kick;
+};
@@ -0,0 +1,9 @@
+#version 140
+
+uniform sampler2D screen;
+out vec4 color;
+
+void main() {
+    // the channels are reordered by the texture swizzle
+    color = texelFetch(screen, ivec2(gl_FragCoord.xy), 0);
+}
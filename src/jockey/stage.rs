@@ -3,7 +3,7 @@ use std::{collections::HashMap, ffi::CString};
 use gl::types::*;
 use serde_yaml::Value;
 
-use super::Uniform;
+use super::{Annotation, Locations, Midi, Uniform, HELD_NOTES_N, MIDI_N};
 use crate::util::*;
 
 pub const PASS_VERT: &str = include_str!("shaders/pass.vert");
@@ -18,8 +18,259 @@ pub enum StageKind {
         count: GLsizei,
         mode: GLenum,
         thickness: f32,
+        /// Vertex indices split into strips by a restart index, see `strip_length`
+        strips: Option<Vec<GLuint>>,
+        /// Number of vertices per patch of tessellated stages
+        patch_vertices: Option<GLint>,
     },
-    Frag {},
+    Frag {
+        /// Triangles covered by the stage in clip space, the whole target if `None`
+        geometry: Option<Vec<GLfloat>>,
+    },
+}
+
+/// Stencil test configuration of a stage
+#[derive(Debug, Clone, Copy)]
+pub struct Stencil {
+    pub func: GLenum,
+    pub reference: GLint,
+    pub mask: GLuint,
+    pub ops: [GLenum; 3],
+    pub clear: Option<GLint>,
+}
+
+impl Stencil {
+    pub fn from_yaml(object: &Value) -> Result<Self, String> {
+        fn parse_func(name: &str) -> Result<GLenum, String> {
+            match name {
+                "NEVER" => Ok(gl::NEVER),
+                "LESS" => Ok(gl::LESS),
+                "LEQUAL" => Ok(gl::LEQUAL),
+                "GREATER" => Ok(gl::GREATER),
+                "GEQUAL" => Ok(gl::GEQUAL),
+                "EQUAL" => Ok(gl::EQUAL),
+                "NOTEQUAL" => Ok(gl::NOTEQUAL),
+                "ALWAYS" => Ok(gl::ALWAYS),
+                s => Err(format!("Expected stencil function, got \"{:?}\"", s)),
+            }
+        }
+
+        fn parse_op(name: &str) -> Result<GLenum, String> {
+            match name {
+                "KEEP" => Ok(gl::KEEP),
+                "ZERO" => Ok(gl::ZERO),
+                "REPLACE" => Ok(gl::REPLACE),
+                "INCR" => Ok(gl::INCR),
+                "INCR_WRAP" => Ok(gl::INCR_WRAP),
+                "DECR" => Ok(gl::DECR),
+                "DECR_WRAP" => Ok(gl::DECR_WRAP),
+                "INVERT" => Ok(gl::INVERT),
+                s => Err(format!("Expected stencil operation, got \"{:?}\"", s)),
+            }
+        }
+
+        let func = match object.get("func") {
+            Some(Value::String(s)) => parse_func(s)?,
+            None => gl::ALWAYS,
+            Some(s) => {
+                return Err(format!(
+                    "Expected \"stencil.func\" to be a string, got {:?}",
+                    s
+                ))
+            }
+        };
+
+        let reference = match object.get("ref").map(Value::as_i64) {
+            Some(Some(n)) if (0..=255).contains(&n) => n as _,
+            None => 0,
+            Some(s) => {
+                return Err(format!(
+                    "Expected \"stencil.ref\" to be an integer between 0 and 255, got {:?}",
+                    s
+                ))
+            }
+        };
+
+        let mask = match object.get("mask").map(Value::as_u64) {
+            Some(Some(n)) if n <= 255 => n as _,
+            None => 0xFF,
+            Some(s) => {
+                return Err(format!(
+                    "Expected \"stencil.mask\" to be an integer between 0 and 255, got {:?}",
+                    s
+                ))
+            }
+        };
+
+        let ops = match object.get("op") {
+            Some(Value::Sequence(s)) => match s.as_slice() {
+                [Value::String(sfail), Value::String(dpfail), Value::String(dppass)] => {
+                    [parse_op(sfail)?, parse_op(dpfail)?, parse_op(dppass)?]
+                }
+                s => {
+                    return Err(format!(
+                        "Expected \"stencil.op\" to be a list of three strings, got {:?}",
+                        s
+                    ))
+                }
+            },
+            None => [gl::KEEP; 3],
+            Some(s) => {
+                return Err(format!(
+                    "Expected \"stencil.op\" to be a list of three strings, got {:?}",
+                    s
+                ))
+            }
+        };
+
+        let clear = match object.get("clear").map(Value::as_i64) {
+            Some(Some(n)) if (0..=255).contains(&n) => Some(n as _),
+            None => None,
+            Some(s) => {
+                return Err(format!(
+                    "Expected \"stencil.clear\" to be an integer between 0 and 255, got {:?}",
+                    s
+                ))
+            }
+        };
+
+        Ok(Self {
+            func,
+            reference,
+            mask,
+            ops,
+            clear,
+        })
+    }
+}
+
+/// A slider or button value a stage depends on to run
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Condition {
+    pub button: bool,
+    pub index: usize,
+    pub threshold: f32,
+    pub above: bool,
+}
+
+impl Condition {
+    pub fn from_yaml(object: &Value) -> Result<Self, String> {
+        let (button, index) = match (object.get("slider"), object.get("button")) {
+            (Some(n), None) => (false, n),
+            (None, Some(n)) => (true, n),
+            _ => {
+                return Err(format!(
+                    "Expected \"condition\" to have either a \"slider\" or a \"button\", got {:?}",
+                    object
+                ))
+            }
+        };
+
+        let index = match index.as_u64() {
+            Some(n) if (n as usize) < MIDI_N => n as _,
+            _ => {
+                return Err(format!(
+                    "Expected \"condition\" to refer to a control between 0 and {}, got {:?}",
+                    MIDI_N - 1,
+                    index
+                ))
+            }
+        };
+
+        let above = object.get("above").map(Value::as_f64);
+        let below = object.get("below").map(Value::as_f64);
+        let (threshold, above) = match (above, below) {
+            (None, None) => (0.0, true),
+            (Some(Some(x)), None) => (x as _, true),
+            (None, Some(Some(x))) => (x as _, false),
+            _ => {
+                return Err(format!(
+                    "Expected \"condition\" to have a number for at most one of \"above\" and \"below\", got {:?}",
+                    object
+                ))
+            }
+        };
+
+        Ok(Self {
+            button,
+            index,
+            threshold,
+            above,
+        })
+    }
+
+    /// Checks the current value of the control against the threshold.
+    pub fn is_met(&self, midi: &Midi) -> bool {
+        let value = match self.button {
+            true => midi.buttons[self.index].0,
+            false => midi.sliders[self.index],
+        };
+
+        match self.above {
+            true => value > self.threshold,
+            false => value < self.threshold,
+        }
+    }
+}
+
+/// Everything a stage with `skip_unchanged` reads, apart from time, beat and
+/// audio uniforms, which would make it run every frame.
+#[derive(Debug, Clone, PartialEq)]
+pub struct StageInputs {
+    resolution: [u32; 3],
+    unis: Vec<Uniform>,
+    sliders: [f32; MIDI_N],
+    buttons: [(f32, u32); MIDI_N],
+    held_notes: ([GLint; HELD_NOTES_N], usize),
+    focused: bool,
+    /// Versions of the textures and storage buffers the stage reads
+    pub deps: Vec<u64>,
+}
+
+impl StageInputs {
+    pub fn new(
+        stage: &Stage,
+        resolution: [u32; 3],
+        midi: &Midi,
+        focused: bool,
+        versions: &HashMap<CString, u64>,
+    ) -> Self {
+        let mut buttons = [(0.0, 0); MIDI_N];
+        for (out, button) in buttons.iter_mut().zip(midi.buttons.iter()) {
+            *out = (button.0, button.3);
+        }
+
+        Self {
+            resolution,
+            unis: stage.unis.values().copied().collect(),
+            sliders: midi.sliders,
+            buttons,
+            held_notes: midi.held_keys(),
+            focused,
+            deps: dep_versions(stage.inputs(), versions),
+        }
+    }
+}
+
+/// Current versions of the given textures and storage buffers
+pub fn dep_versions<'a>(
+    names: impl IntoIterator<Item = &'a CString>,
+    versions: &HashMap<CString, u64>,
+) -> Vec<u64> {
+    names
+        .into_iter()
+        .map(|name| versions.get(name).copied().unwrap_or_default())
+        .collect()
+}
+
+/// Marks the given textures and storage buffers as written
+pub fn bump_versions<'a>(
+    names: impl IntoIterator<Item = &'a CString>,
+    versions: &mut HashMap<CString, u64>,
+) {
+    for name in names {
+        *versions.entry(name.clone()).or_default() += 1;
+    }
 }
 
 /// The stage struct
@@ -33,12 +284,44 @@ pub enum StageKind {
 #[derive(Debug)]
 pub struct Stage {
     pub prog_id: GLuint,
+    pub name: Option<String>,
+    pub after: Vec<String>,
     pub target: Option<CString>,
     pub kind: StageKind,
     pub sh_ids: Vec<GLuint>,
+    pub sources: Vec<String>,
     pub deps: Vec<CString>,
+    pub storage_deps: Vec<CString>,
+    pub image_deps: Vec<CString>,
+    /// Format qualifiers of the images declared in the shaders
+    pub image_formats: Vec<(CString, String)>,
+    pub barrier: GLbitfield,
+    pub common_block: bool,
     pub unis: HashMap<CString, Uniform>,
+    pub locs: Locations,
+    pub info: ProgramInfo,
+    pub controls: Vec<(CString, (f32, f32))>,
     pub blend: Option<(GLenum, GLenum)>,
+    pub accumulate: Option<f32>,
+    pub viewport: Option<[GLint; 4]>,
+    pub scissor: Option<[GLint; 4]>,
+    pub target_mip: u32,
+    /// Image the target is filled with when it's created
+    pub init_image: Option<String>,
+    /// Whether the target is also copied to the screen after the stage ran
+    pub show: bool,
+    pub stencil: Option<Stencil>,
+    pub interval: u32,
+    /// Whether the stage only runs once after the pipeline is built
+    pub once: bool,
+    /// Whether a stage running once has already run
+    pub ran: bool,
+    /// Whether the stage is skipped while its inputs stay the same
+    pub skip_unchanged: bool,
+    /// Inputs of the stage when it last ran, only tracked with `skip_unchanged`
+    pub last_inputs: Option<StageInputs>,
+    pub condition: Option<Condition>,
+    pub budget: Option<f32>,
     pub perf: RunningAverage<f32, 128>,
     pub builder: TextureBuilder,
 }
@@ -47,6 +330,8 @@ impl Stage {
     pub fn from_yaml(object: Value) -> Result<Self, String> {
         let perf = RunningAverage::new();
         let deps = Vec::new();
+        let storage_deps = Vec::new();
+        let image_deps = Vec::new();
 
         // get render target name
         let target = match object.get("target") {
@@ -152,22 +437,240 @@ impl Stage {
                     }
                 }
             }
-            Some(Value::String(_)) => {
-                // TODO: Fix this
-                return Err("Aliases for common blend modes are currently unimplemented".into());
-            }
+            Some(Value::String(s)) => match s.as_str() {
+                "alpha" => Some((gl::SRC_ALPHA, gl::ONE_MINUS_SRC_ALPHA)),
+                "premultiplied" => Some((gl::ONE, gl::ONE_MINUS_SRC_ALPHA)),
+                "add" => Some((gl::ONE, gl::ONE)),
+                "multiply" => Some((gl::DST_COLOR, gl::ZERO)),
+                s => {
+                    return Err(format!(
+                        "Expected \"blend_mode\" to be one of alpha, premultiplied, add or multiply, got {:?}",
+                        s
+                    ))
+                }
+            },
             Some(s) => return Err(format!("Invalid blend mode value, got \"{:?}\"", s)),
             None => None,
         };
 
+        // parse accumulation fade factor
+        let accumulate = match object.get("accumulate") {
+            Some(Value::Bool(true)) => Some(1.0),
+            Some(Value::Bool(false)) | None => None,
+            Some(s) => match s.as_f64() {
+                Some(f) if (0.0..=1.0).contains(&f) => Some(f as f32),
+                _ => {
+                    return Err(format!(
+                    "Expected \"accumulate\" to be a bool or a number between 0 and 1, got {:?}",
+                    s
+                ))
+                }
+            },
+        };
+
+        if accumulate.is_some() {
+            if blend.is_some() {
+                return Err(
+                    "Fields \"accumulate\" and \"blend_mode\" are mutually exclusive".into(),
+                );
+            }
+
+            if target.is_none() {
+                return Err("Field \"target\" is mandatory for accumulation stages".into());
+            }
+        }
+
+        // accumulation adds onto the faded previous contents: dst * fade + src
+        let blend = blend.or(accumulate.map(|_| (gl::ONE, gl::CONSTANT_COLOR)));
+
+        // parse rectangles in pixels, like the viewport
+        let parse_rect = |name: &str| match object.get(name) {
+            Some(Value::Sequence(s)) => {
+                let values: Vec<_> = s.iter().filter_map(Value::as_u64).collect();
+                match values.as_slice() {
+                    &[x, y, w, h] if w > 0 && h > 0 => Ok(Some([x as _, y as _, w as _, h as _])),
+                    _ => Err(format!(
+                        "Expected \"{}\" to be a list of four positive integers [x, y, w, h], got {:?}",
+                        name, s
+                    )),
+                }
+            }
+            Some(s) => Err(format!(
+                "Expected field \"{}\" to be a list of four integers, got {:?}",
+                name, s
+            )),
+            None => Ok(None),
+        };
+
+        let viewport = parse_rect("viewport")?;
+        let scissor = parse_rect("scissor")?;
+
+        // parse the mip level of the target which is drawn to
+        let target_mip = match object.get("target_mip").map(Value::as_u64) {
+            Some(Some(n)) if n <= u32::MAX as u64 => n as _,
+            None => 0,
+            Some(_) => {
+                return Err(format!(
+                    "Expected \"target_mip\" to be a non-negative integer, got {:?}",
+                    object.get("target_mip")
+                ))
+            }
+        };
+
+        if target_mip > 0 && target.is_none() {
+            return Err("Field \"target\" is mandatory for stages with a \"target_mip\"".into());
+        }
+
+        // parse the image the target starts out with
+        let init_image = match object.get("init_image") {
+            Some(Value::String(s)) => Some(s.clone()),
+            None => None,
+            s => {
+                return Err(format!(
+                    "Expected \"init_image\" to be a string, got {:?}",
+                    s
+                ))
+            }
+        };
+
+        if init_image.is_some() && target.is_none() {
+            return Err("Field \"target\" is mandatory for stages with an \"init_image\"".into());
+        }
+
+        // parse whether the target is shown on the screen as well
+        let show = match object.get("show") {
+            Some(Value::Bool(b)) => *b,
+            None => false,
+            s => return Err(format!("Expected \"show\" to be a bool, got {:?}", s)),
+        };
+
+        if show && target.is_none() {
+            return Err("Field \"target\" is mandatory for stages with \"show\"".into());
+        }
+
+        if show && target_mip > 0 {
+            return Err("Stages drawing into a \"target_mip\" can't \"show\" their target".into());
+        }
+
+        // parse stencil test
+        let stencil = match object.get("stencil") {
+            Some(s @ Value::Mapping(_)) => Some(Stencil::from_yaml(s)?),
+            Some(s) => {
+                return Err(format!(
+                    "Expected field \"stencil\" to be a mapping, got {:?}",
+                    s
+                ))
+            }
+            None => None,
+        };
+
+        // get stage name, used to refer to it in "after"
+        let name = match object.get("name") {
+            Some(Value::String(s)) => Some(s.clone()),
+            None => None,
+            Some(s) => {
+                return Err(format!(
+                    "Expected field \"name\" to be a string, got {:?}",
+                    s
+                ))
+            }
+        };
+
+        // parse the stages that have to run before this one
+        let after = match object.get("after") {
+            Some(Value::String(s)) => vec![s.clone()],
+            Some(Value::Sequence(seq)) => {
+                let mut after = Vec::with_capacity(seq.len());
+                for value in seq {
+                    match value {
+                        Value::String(s) => after.push(s.clone()),
+                        s => {
+                            return Err(format!(
+                                "Expected stage names in \"after\" to be strings, got {:?}",
+                                s
+                            ))
+                        }
+                    }
+                }
+                after
+            }
+            None => Vec::new(),
+            Some(s) => {
+                return Err(format!(
+                    "Expected field \"after\" to be a string or an array, got {:?}",
+                    s
+                ))
+            }
+        };
+
+        // parse how often the stage runs
+        let interval = match object.get("interval").map(Value::as_u64) {
+            Some(Some(n)) if n > 0 => n as _,
+            None => 1,
+            Some(_) => {
+                return Err(format!(
+                    "Expected \"interval\" to be a positive integer, got {:?}",
+                    object.get("interval")
+                ))
+            }
+        };
+
+        // parse whether the stage only runs once, to initialize its target
+        let once = match object.get("once") {
+            Some(Value::Bool(b)) => *b,
+            None => false,
+            s => return Err(format!("Expected \"once\" to be a bool, got {:?}", s)),
+        };
+
+        // parse whether the stage only runs when its inputs change
+        let skip_unchanged = match object.get("skip_unchanged") {
+            Some(Value::Bool(b)) => *b,
+            None => false,
+            s => {
+                return Err(format!(
+                    "Expected \"skip_unchanged\" to be a bool, got {:?}",
+                    s
+                ))
+            }
+        };
+
+        // the screen is cleared every frame, so a skipped stage would vanish
+        if skip_unchanged && target.is_none() && object.get("cs").is_none() {
+            return Err("Field \"target\" is mandatory for stages with \"skip_unchanged\"".into());
+        }
+
+        // parse the control the stage depends on
+        let condition = match object.get("condition") {
+            Some(s @ Value::Mapping(_)) => Some(Condition::from_yaml(s)?),
+            Some(s) => {
+                return Err(format!(
+                    "Expected field \"condition\" to be a mapping, got {:?}",
+                    s
+                ))
+            }
+            None => None,
+        };
+
+        // parse the render time the stage should stay below, in milliseconds
+        let budget = match object.get("budget").map(Value::as_f64) {
+            Some(Some(ms)) if ms > 0.0 => Some(ms as _),
+            None => None,
+            Some(_) => {
+                return Err(format!(
+                    "Expected \"budget\" to be a positive number of milliseconds, got {:?}",
+                    object.get("budget")
+                ))
+            }
+        };
+
         // read all shaders to strings
         let mut lut = Vec::new();
-        let shaders: [Option<(String, String)>; 3] = {
-            let mut out = [None, None, None];
-            for (k, &name) in ["vs", "fs", "cs"].iter().enumerate() {
+        let shaders: [Option<(String, String)>; 6] = {
+            let mut out = [None, None, None, None, None, None];
+            for (k, &name) in ["vs", "fs", "cs", "tcs", "tes", "gs"].iter().enumerate() {
                 out[k] = match object.get(name) {
-                    Some(Value::String(f)) => match std::fs::read_to_string(f) {
-                        Ok(s) => Some((s, f.into())),
+                    Some(Value::String(f)) => match read_source(f) {
+                        Ok(s) => Some((s.to_string(), f.into())),
                         Err(e) => return Err(format!("{}, {}", e.to_string(), f)),
                     },
                     Some(s) => {
@@ -183,9 +686,34 @@ impl Stage {
             out
         };
 
+        // add uniforms annotated in the shaders, the pipeline file takes precedence
+        let mut controls = Vec::new();
+        for (source, _) in shaders.iter().flatten() {
+            for annotation in Annotation::parse_all(source) {
+                // the same uniform may be annotated in several shaders or includes
+                let known = controls.iter().any(|(name, _)| *name == annotation.name);
+                if let Some(range) = annotation.range.filter(|_| !known) {
+                    controls.push((annotation.name.clone(), range));
+                }
+
+                unis.entry(annotation.name).or_insert(annotation.default);
+            }
+        }
+
+        // image formats are checked against the buffers once those exist
+        let image_formats = shaders
+            .iter()
+            .flatten()
+            .flat_map(|(source, _)| image_formats(source))
+            .map(|(name, format)| (CString::new(name).unwrap(), format))
+            .collect::<Vec<_>>();
+
+        // remember where the shaders came from, so they can be opened from the UI
+        let sources = shaders.iter().flatten().map(|s| s.1.clone()).collect();
+
         match shaders {
             // handle full screen fragment shader stages
-            [None, Some(fs), None] => {
+            [None, Some(fs), None, None, None, None] => {
                 let vs = PASS_VERT;
                 let fs = preprocess(&fs.0, &fs.1, &mut lut)?;
 
@@ -195,31 +723,109 @@ impl Stage {
                     compile_shader(&fs, gl::FRAGMENT_SHADER).map_err(|e| process_error(e, &lut))?;
 
                 let sh_ids = vec![vs_id, fs_id];
-                let prog_id = link_program(&sh_ids)?;
+                let prog_id = link_program_with_outputs(&sh_ids, &fragment_outputs(&fs))?;
 
-                let builder = TextureBuilder::parse(&object, true, true)?;
+                let mut builder = TextureBuilder::parse(&object, true, true)?;
+                if accumulate.is_some() {
+                    builder.set_float(true);
+                }
 
                 if !matches!(builder.resolution.as_slice(), &[] | &[_, _]) {
                     return Err("Expected \"resolution\" to be 2D".into());
                 }
 
-                let kind = StageKind::Frag {};
+                if builder.format.map_or(false, TextureFormat::is_integer) {
+                    return Err("Integer formats are only supported by compute shaders".into());
+                }
+
+                // parse the triangles to run the shader on, in target coordinates from 0 to 1
+                let geometry = match object.get("geometry") {
+                    Some(Value::Sequence(points)) => {
+                        let mut out = Vec::with_capacity(2 * points.len());
+                        for point in points {
+                            let coords: Vec<_> = match point {
+                                Value::Sequence(s) => s.iter().filter_map(Value::as_f64).collect(),
+                                _ => Vec::new(),
+                            };
+
+                            match coords.as_slice() {
+                                &[x, y] => out.extend([2.0 * x as f32 - 1.0, 2.0 * y as f32 - 1.0]),
+                                _ => {
+                                    return Err(format!(
+                                        "Expected points in \"geometry\" to be pairs of numbers [x, y], got {:?}",
+                                        point
+                                    ))
+                                }
+                            }
+                        }
+
+                        if points.is_empty() || points.len() % 3 != 0 {
+                            return Err(format!(
+                                "Expected \"geometry\" to be a list of triangles with three points each, got {} points",
+                                points.len()
+                            ));
+                        }
+
+                        Some(out)
+                    }
+                    None => None,
+                    Some(s) => {
+                        return Err(format!(
+                            "Expected field \"geometry\" to be a list of points, got {:?}",
+                            s
+                        ))
+                    }
+                };
+
+                let kind = StageKind::Frag { geometry };
+
+                let locs = Locations::new(prog_id, &unis);
+                let info = ProgramInfo::new(prog_id);
 
                 Ok(Stage {
                     prog_id,
+                    name,
+                    after,
                     target,
                     kind,
                     sh_ids,
+                    sources,
                     deps,
+                    storage_deps,
+                    image_deps,
+                    image_formats,
+                    barrier: 0,
+                    common_block: false,
                     unis,
+                    locs,
+                    info,
+                    controls,
                     blend,
+                    accumulate,
+                    viewport,
+                    scissor,
+                    target_mip,
+                    init_image,
+                    show,
+                    stencil,
+                    interval,
+                    once,
+                    ran: false,
+                    skip_unchanged,
+                    last_inputs: None,
+                    condition,
+                    budget,
                     perf,
                     builder,
                 })
             }
 
             // handle vertex shader stages
-            [Some(vs), fs_opt, None] => {
+            [Some(vs), fs_opt, None, tcs_opt, tes_opt, gs_opt] => {
+                if tcs_opt.is_some() && tes_opt.is_none() {
+                    return Err("Field \"tcs\" needs a \"tes\" shader as well".into());
+                }
+
                 let vs = preprocess(&vs.0, &vs.1, &mut lut)?;
                 let fs = match fs_opt {
                     Some(fs) => preprocess(&fs.0, &fs.1, &mut lut)?,
@@ -228,11 +834,27 @@ impl Stage {
 
                 let vs_id =
                     compile_shader(&vs, gl::VERTEX_SHADER).map_err(|e| process_error(e, &lut))?;
+                let mut sh_ids = vec![vs_id];
+
+                // tessellation and geometry shaders run between the vertex and fragment shader
+                for (shader, kind) in [
+                    (tcs_opt.as_ref(), gl::TESS_CONTROL_SHADER),
+                    (tes_opt.as_ref(), gl::TESS_EVALUATION_SHADER),
+                    (gs_opt.as_ref(), gl::GEOMETRY_SHADER),
+                ] {
+                    if let Some(shader) = shader {
+                        let source = preprocess(&shader.0, &shader.1, &mut lut)?;
+                        let id =
+                            compile_shader(&source, kind).map_err(|e| process_error(e, &lut))?;
+                        sh_ids.push(id);
+                    }
+                }
+
                 let fs_id =
                     compile_shader(&fs, gl::FRAGMENT_SHADER).map_err(|e| process_error(e, &lut))?;
+                sh_ids.push(fs_id);
 
-                let sh_ids = vec![vs_id, fs_id];
-                let prog_id = link_program(&sh_ids)?;
+                let prog_id = link_program_with_outputs(&sh_ids, &fragment_outputs(&fs))?;
 
                 let count = match object.get("count") {
                     Some(s) => match s.as_u64() {
@@ -256,11 +878,62 @@ impl Stage {
                         Some("TRIANGLE_FAN") => gl::TRIANGLE_FAN,
                         Some("TRIANGLE_STRIP") => gl::TRIANGLE_STRIP,
                         Some("TRIANGLES") => gl::TRIANGLES,
+                        Some("PATCHES") => gl::PATCHES,
                         _ => return Err(format!("Invalid vertex mode: {:?}", s)),
                     },
+                    _ if tes_opt.is_some() => gl::PATCHES,
                     _ => gl::TRIANGLES,
                 };
 
+                // tessellated stages draw patches instead of primitives
+                if (mode == gl::PATCHES) != tes_opt.is_some() {
+                    return Err(
+                        "Tessellation needs a \"tes\" shader and mode \"PATCHES\", stages without one can't have the other"
+                            .into(),
+                    );
+                }
+
+                // the geometry shader has to accept the primitives that are drawn
+                if gs_opt.is_some() && tes_opt.is_none() {
+                    let mut input_type = 0;
+                    unsafe {
+                        gl::GetProgramiv(prog_id, gl::GEOMETRY_INPUT_TYPE, &mut input_type);
+                        gl_debug_check!();
+                    }
+
+                    let expected = match mode {
+                        gl::POINTS => gl::POINTS,
+                        gl::LINES | gl::LINE_STRIP | gl::LINE_LOOP => gl::LINES,
+                        _ => gl::TRIANGLES,
+                    };
+
+                    if input_type as GLenum != expected {
+                        return Err(format!(
+                            "Geometry shader expects {} as input, but the stage draws {}",
+                            primitive_name(input_type as _),
+                            primitive_name(expected)
+                        ));
+                    }
+                }
+
+                let patch_vertices =
+                    match object.get("patch_vertices").map(Value::as_u64) {
+                        Some(_) if tes_opt.is_none() => return Err(
+                            "Field \"patch_vertices\" only applies to stages with a \"tes\" shader"
+                                .into(),
+                        ),
+                        Some(Some(n)) if n > 0 && n <= max_patch_vertices() as u64 => Some(n as _),
+                        None if tes_opt.is_some() => Some(3),
+                        None => None,
+                        Some(_) => {
+                            return Err(format!(
+                            "Expected \"patch_vertices\" to be an integer from 1 to {}, got {:?}",
+                            max_patch_vertices(),
+                            object.get("patch_vertices")
+                        ))
+                        }
+                    };
+
                 let thickness = match object
                     .get("thickness")
                     .or(object.get("stroke_weight"))
@@ -278,33 +951,92 @@ impl Stage {
                     }
                 };
 
-                let builder = TextureBuilder::parse(&object, true, true)?;
+                // draw many disconnected strips with a single call
+                let strips = match object.get("strip_length").map(Value::as_u64) {
+                    Some(Some(n)) if n > 0 => match mode {
+                        gl::LINE_LOOP | gl::LINE_STRIP | gl::TRIANGLE_FAN | gl::TRIANGLE_STRIP => {
+                            Some(strip_indices(count, n as _))
+                        }
+                        _ => {
+                            return Err(format!(
+                                "Field \"strip_length\" needs a strip, loop or fan mode, got {:?}",
+                                object.get("mode")
+                            ))
+                        }
+                    },
+                    None => None,
+                    Some(_) => {
+                        return Err(format!(
+                            "Expected \"strip_length\" to be a positive integer, got {:?}",
+                            object.get("strip_length")
+                        ))
+                    }
+                };
+
+                let mut builder = TextureBuilder::parse(&object, true, true)?;
+                if accumulate.is_some() {
+                    builder.set_float(true);
+                }
 
                 if !matches!(builder.resolution.as_slice(), &[] | &[_, _]) {
                     return Err("Expected \"resolution\" to be 2D".into());
                 }
 
+                if builder.format.map_or(false, TextureFormat::is_integer) {
+                    return Err("Integer formats are only supported by compute shaders".into());
+                }
+
                 let kind = StageKind::Vert {
                     count,
                     mode,
                     thickness,
+                    strips,
+                    patch_vertices,
                 };
 
+                let locs = Locations::new(prog_id, &unis);
+                let info = ProgramInfo::new(prog_id);
+
                 Ok(Stage {
                     prog_id,
+                    name,
+                    after,
                     target,
                     kind,
                     sh_ids,
+                    sources,
                     deps,
+                    storage_deps,
+                    image_deps,
+                    image_formats,
+                    barrier: 0,
+                    common_block: false,
                     unis,
+                    locs,
+                    info,
+                    controls,
                     blend,
+                    accumulate,
+                    viewport,
+                    scissor,
+                    target_mip,
+                    init_image,
+                    show,
+                    stencil,
+                    interval,
+                    once,
+                    ran: false,
+                    skip_unchanged,
+                    last_inputs: None,
+                    condition,
+                    budget,
                     perf,
                     builder,
                 })
             }
 
             // handle compute shader stages
-            [None, None, Some(cs)] => {
+            [None, None, Some(cs), None, None, None] => {
                 let cs = preprocess(&cs.0, &cs.1, &mut lut)?;
 
                 let cs_id =
@@ -312,7 +1044,7 @@ impl Stage {
                 let sh_ids = vec![cs_id];
                 let prog_id = link_program(&sh_ids)?;
 
-                // get target resolution
+                // get number of work groups
                 let dispatch = match object
                     .get("dispatch_size")
                     .or_else(|| object.get("dispatch"))
@@ -347,7 +1079,7 @@ impl Stage {
                             };
                         }
 
-                        out
+                        Some(out)
                     }
                     Some(s) => {
                         return Err(format!(
@@ -355,33 +1087,141 @@ impl Stage {
                         s
                     ))
                     }
-                    None => {
-                        return Err(
-                            "Field \"dispatch_size\" is mandatory for compute shaders".into()
-                        )
-                    }
+                    None => None,
                 };
 
                 let builder = TextureBuilder::parse(&object, true, false)?;
 
-                if builder.resolution.as_slice().is_empty() {
+                if show {
+                    return Err("Compute shaders can't show their target on the screen".into());
+                }
+
+                let format = builder.texture_format();
+                if !format.is_image_compatible() {
+                    return Err(format!(
+                        "Compute shaders can't write to {:?} images, use 4 channels instead",
+                        format.qualifier()
+                    ));
+                }
+
+                // compute shaders without a target only write to storage buffers
+                if target.is_some() && builder.resolution.as_slice().is_empty() {
                     return Err("Field \"resolution\" is mandatory for compute shaders".into());
                 }
 
-                if target.is_none() {
-                    return Err("Field \"target\" is mandatory for compute shaders".into());
+                // the size of a work group is set by the shader
+                let mut local_size = [0; 3];
+                unsafe {
+                    gl::GetProgramiv(
+                        prog_id,
+                        gl::COMPUTE_WORK_GROUP_SIZE,
+                        local_size.as_mut_ptr(),
+                    )
+                };
+                let local_size = local_size.map(|n| n.max(1) as GLuint);
+
+                let mut target_res = [1; 3];
+                for (res, &n) in target_res.iter_mut().zip(builder.resolution.iter()) {
+                    *res = n;
+                }
+
+                let dispatch = match dispatch {
+                    Some(dispatch) => {
+                        // writing to scattered texels with fewer invocations is fine,
+                        // but it's usually a mistake in the dispatch size
+                        let covered = [0, 1, 2].map(|k| local_size[k] * dispatch[k]);
+                        if target.is_some() && (0..3).any(|k| covered[k] < target_res[k]) {
+                            log::warn!(
+                                "Dispatch of {:?} work groups of size {:?} only covers {:?} of target {:?} with resolution {:?}",
+                                dispatch,
+                                local_size,
+                                covered,
+                                target,
+                                target_res
+                            );
+                        }
+
+                        dispatch
+                    }
+
+                    // cover the whole target, if there is one
+                    None if target.is_some() => {
+                        let dispatch =
+                            [0, 1, 2].map(|k| (target_res[k] + local_size[k] - 1) / local_size[k]);
+                        if dispatch.iter().any(|&n| n > 65535) {
+                            return Err(format!(
+                                "Target resolution {:?} needs more than 65535 work groups in a dimension, please set \"dispatch_size\"",
+                                target_res
+                            ));
+                        }
+
+                        dispatch
+                    }
+
+                    None => return Err(
+                        "Field \"dispatch_size\" is mandatory for compute shaders without a target"
+                            .into(),
+                    ),
+                };
+
+                if accumulate.is_some() {
+                    return Err("Compute shaders do not support \"accumulate\"".into());
+                }
+
+                if stencil.is_some() {
+                    return Err("Compute shaders do not support \"stencil\"".into());
+                }
+
+                if viewport.is_some() {
+                    return Err("Compute shaders do not support \"viewport\"".into());
+                }
+
+                if target_mip > 0 {
+                    return Err("Compute shaders do not support \"target_mip\"".into());
+                }
+
+                if scissor.is_some() {
+                    return Err("Compute shaders do not support \"scissor\"".into());
                 }
 
                 let kind = StageKind::Comp { dispatch };
 
+                let locs = Locations::new(prog_id, &unis);
+                let info = ProgramInfo::new(prog_id);
+
                 Ok(Stage {
                     prog_id,
+                    name,
+                    after,
                     target,
                     kind,
                     sh_ids,
+                    sources,
                     deps,
+                    storage_deps,
+                    image_deps,
+                    image_formats,
+                    barrier: 0,
+                    common_block: false,
                     unis,
+                    locs,
+                    info,
+                    controls,
                     blend,
+                    accumulate,
+                    viewport,
+                    scissor,
+                    target_mip,
+                    init_image,
+                    show,
+                    stencil,
+                    interval,
+                    once,
+                    ran: false,
+                    skip_unchanged,
+                    last_inputs: None,
+                    condition,
+                    budget,
                     perf,
                     builder,
                 })
@@ -392,6 +1232,63 @@ impl Stage {
         }
     }
 
+    /// Whether the render target has to keep its contents between draws,
+    /// which rules out swapping between a front and a back buffer.
+    pub fn single_buffered(&self) -> bool {
+        self.accumulate.is_some()
+            || self.viewport.is_some()
+            || self.scissor.is_some()
+            || self.target_mip > 0
+            || matches!(self.kind, StageKind::Frag { geometry: Some(_) })
+    }
+
+    /// Name shown in the UI, falls back to the position in the pipeline.
+    pub fn label(&self, index: usize) -> String {
+        match &self.name {
+            Some(name) => name.clone(),
+            None => format!("Stage {}", index),
+        }
+    }
+
+    /// Identifies the stage in error messages, by its name or its shader files.
+    pub fn describe(&self) -> String {
+        match &self.name {
+            Some(name) => format!("stage {:?}", name),
+            None => format!("stage with shader {:?}", self.sources),
+        }
+    }
+
+    /// Whether the stage reads the target it draws to, or adds to its contents.
+    pub fn feedback(&self) -> bool {
+        self.accumulate.is_some() || matches!(&self.target, Some(name) if self.deps.contains(name))
+    }
+
+    /// Textures, images and storage buffers the stage reads
+    pub fn inputs(&self) -> impl Iterator<Item = &CString> {
+        self.deps
+            .iter()
+            .chain(&self.storage_deps)
+            .chain(&self.image_deps)
+    }
+
+    /// Textures, images and storage buffers the stage may write
+    pub fn outputs(&self) -> impl Iterator<Item = &CString> {
+        self.target
+            .iter()
+            .chain(&self.image_deps)
+            .chain(&self.storage_deps)
+    }
+
+    /// Size of the mip level that is drawn to, given the size of the whole target
+    pub fn mip_resolution(&self, res: [u32; 3]) -> [u32; 3] {
+        let level = |n: u32| match n {
+            0 => 0,
+            n => n.checked_shr(self.target_mip).unwrap_or(0).max(1),
+        };
+
+        [level(res[0]), level(res[1]), level(res[2])]
+    }
+
     pub fn resolution(&self) -> Option<[u32; 3]> {
         match self.builder.resolution.as_slice() {
             &[w] => Some([w, 0, 0]),
@@ -402,6 +1299,18 @@ impl Stage {
     }
 }
 
+/// Name of a primitive type as used in GLSL layout qualifiers.
+fn primitive_name(primitive: GLenum) -> &'static str {
+    match primitive {
+        gl::POINTS => "points",
+        gl::LINES => "lines",
+        gl::LINES_ADJACENCY => "lines_adjacency",
+        gl::TRIANGLES => "triangles",
+        gl::TRIANGLES_ADJACENCY => "triangles_adjacency",
+        _ => "unknown primitives",
+    }
+}
+
 impl Drop for Stage {
     fn drop(&mut self) {
         unsafe {
@@ -414,3 +1323,27 @@ impl Drop for Stage {
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn own_writes_are_unchanged_inputs() {
+        // a compute stage that only writes to its image
+        let image = CString::new("image").unwrap();
+        let mut versions = HashMap::new();
+
+        let before = dep_versions([&image], &versions);
+        bump_versions([&image], &mut versions);
+        let recorded = dep_versions([&image], &versions);
+        assert_ne!(before, recorded);
+
+        // nothing else wrote to the image, so the next frame is skipped
+        assert_eq!(dep_versions([&image], &versions), recorded);
+
+        // another stage writing to it makes the stage run again
+        bump_versions([&image], &mut versions);
+        assert_ne!(dep_versions([&image], &versions), recorded);
+    }
+}
@@ -0,0 +1,71 @@
+use std::{
+    fs::File,
+    io::{BufWriter, Write},
+    path::Path,
+    time::{Duration, Instant},
+};
+
+use super::Stage;
+
+/// Time between two snapshots of the stage timings
+const SNAPSHOT_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Periodically writes the render time of every stage to a CSV file.
+///
+/// Each snapshot adds one row per stage, containing the running average of
+/// its render time, so regressions can be traced back after a long session.
+#[derive(Debug)]
+pub struct StageTimings {
+    writer: BufWriter<File>,
+    start: Instant,
+    last_snapshot: Instant,
+}
+
+impl StageTimings {
+    pub fn create(path: impl AsRef<Path>) -> std::io::Result<Self> {
+        let mut writer = BufWriter::new(File::create(path)?);
+        writeln!(writer, "time,pipeline,stage,target,ms")?;
+        writer.flush()?;
+
+        let now = Instant::now();
+        Ok(Self {
+            writer,
+            start: now,
+            last_snapshot: now,
+        })
+    }
+
+    /// Writes a snapshot of the given stages, if the last one is old enough.
+    pub fn record(&mut self, pipeline: &str, stages: &[Stage]) {
+        if self.last_snapshot.elapsed() < SNAPSHOT_INTERVAL {
+            return;
+        }
+
+        self.last_snapshot = Instant::now();
+        if let Err(err) = self.write_snapshot(pipeline, stages) {
+            log::error!("Failed to write stage timings: {}", err);
+        }
+    }
+
+    fn write_snapshot(&mut self, pipeline: &str, stages: &[Stage]) -> std::io::Result<()> {
+        let time = self.start.elapsed().as_secs_f32();
+        for (k, stage) in stages.iter().enumerate() {
+            let target = match &stage.target {
+                Some(name) => name.to_string_lossy(),
+                None => "screen".into(),
+            };
+
+            writeln!(
+                self.writer,
+                "{:.3},{},{},{},{:.4}",
+                time,
+                pipeline,
+                k,
+                target,
+                stage.perf.get()
+            )?;
+        }
+
+        self.writer.flush()
+    }
+}
@@ -0,0 +1,229 @@
+use std::{
+    io::BufReader,
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use rodio::{Decoder, OutputStream, OutputStreamHandle, Sink, Source};
+
+/// The samples of an audio file, decoded on a background thread.
+#[derive(Debug)]
+pub struct TrackData {
+    pub path: PathBuf,
+    samples: Arc<[f32]>,
+    channels: u16,
+    sample_rate: u32,
+}
+
+impl TrackData {
+    /// Decodes the whole audio file at the given path.
+    pub fn decode(path: impl AsRef<Path>) -> Result<Self, String> {
+        let path = path.as_ref().to_path_buf();
+        let file = std::fs::File::open(&path)
+            .map_err(|e| format!("Failed to open track {:?}: {}", path, e))?;
+
+        let start = Instant::now();
+        let decoder = Decoder::new(BufReader::new(file))
+            .map_err(|e| format!("Failed to decode track {:?}: {}", path, e))?;
+
+        let channels = decoder.channels();
+        let sample_rate = decoder.sample_rate();
+        let samples: Vec<f32> = decoder.convert_samples().collect();
+
+        let decode_time = start.elapsed().as_secs_f32();
+        log::info!("Decoded track {:?} in {}s", path, decode_time);
+
+        Ok(Self {
+            path,
+            samples: samples.into(),
+            channels,
+            sample_rate,
+        })
+    }
+}
+
+/// Plays the samples of a track from an offset, without copying them.
+struct TrackSource {
+    samples: Arc<[f32]>,
+    index: usize,
+    channels: u16,
+    sample_rate: u32,
+}
+
+impl Iterator for TrackSource {
+    type Item = f32;
+
+    fn next(&mut self) -> Option<f32> {
+        let sample = self.samples.get(self.index).copied();
+        self.index += 1;
+        sample
+    }
+}
+
+impl Source for TrackSource {
+    fn current_frame_len(&self) -> Option<usize> {
+        Some(self.samples.len().saturating_sub(self.index))
+    }
+
+    fn channels(&self) -> u16 {
+        self.channels
+    }
+
+    fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    fn total_duration(&self) -> Option<Duration> {
+        None
+    }
+}
+
+/// An audio file played back in sync with the timeline.
+///
+/// The whole file is decoded up front, so the samples around the playback
+/// position can be fed to the audio analysis instead of the input device.
+pub struct Track {
+    pub path: PathBuf,
+    samples: Arc<[f32]>,
+    channels: u16,
+    sample_rate: u32,
+    _stream: OutputStream,
+    handle: OutputStreamHandle,
+    sink: Option<Sink>,
+    started: Option<Instant>,
+    position: f32,
+}
+
+impl std::fmt::Debug for Track {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct(stringify!(Track))
+            .field("path", &self.path)
+            .field("channels", &self.channels)
+            .field("sample_rate", &self.sample_rate)
+            .field("position", &self.position())
+            .finish()
+    }
+}
+
+impl Track {
+    /// Opens the default output device to play a decoded audio file.
+    ///
+    /// The output stream can't be moved between threads, so unlike decoding
+    /// this has to happen on the main thread.
+    pub fn new(data: TrackData) -> Result<Self, String> {
+        let TrackData {
+            path,
+            samples,
+            channels,
+            sample_rate,
+        } = data;
+
+        let (stream, handle) = OutputStream::try_default()
+            .map_err(|e| format!("Failed to open audio output device: {}", e))?;
+
+        Ok(Self {
+            path,
+            samples,
+            channels,
+            sample_rate,
+            _stream: stream,
+            handle,
+            sink: None,
+            started: None,
+            position: 0.0,
+        })
+    }
+
+    /// Length of the track in seconds
+    pub fn duration(&self) -> f32 {
+        self.frames() as f32 / self.sample_rate as f32
+    }
+
+    /// Playback position in seconds
+    pub fn position(&self) -> f32 {
+        match self.started {
+            Some(start) => (self.position + start.elapsed().as_secs_f32()).min(self.duration()),
+            None => self.position,
+        }
+    }
+
+    /// Whether the track is playing and hasn't reached its end yet.
+    pub fn is_playing(&self) -> bool {
+        self.started.is_some() && self.position() < self.duration()
+    }
+
+    pub fn play(&mut self) {
+        if self.is_playing() {
+            return;
+        }
+
+        // a track that played to the end keeps its final position
+        self.pause();
+
+        let sink = match Sink::try_new(&self.handle) {
+            Ok(s) => s,
+            Err(err) => {
+                log::error!("Failed to play track {:?}: {}", self.path, err);
+                return;
+            }
+        };
+
+        sink.append(TrackSource {
+            samples: self.samples.clone(),
+            index: self.frame_at(self.position) * self.channels as usize,
+            channels: self.channels,
+            sample_rate: self.sample_rate,
+        });
+
+        self.sink = Some(sink);
+        self.started = Some(Instant::now());
+    }
+
+    pub fn pause(&mut self) {
+        self.position = self.position();
+        self.started = None;
+        self.sink = None;
+    }
+
+    /// Jumps to the given position in seconds, playback continues from there.
+    pub fn seek(&mut self, time: f32) {
+        let playing = self.is_playing();
+        self.pause();
+        self.position = time.max(0.0).min(self.duration());
+        if playing {
+            self.play();
+        }
+    }
+
+    /// Fills the buffers with the samples leading up to the playback position.
+    ///
+    /// Mono tracks are written to both channels.
+    pub fn window(&self, left: &mut [f32], right: &mut [f32]) {
+        let channels = self.channels as usize;
+        let end = self.frame_at(self.position());
+
+        let size = left.len();
+        for (k, (l, r)) in left.iter_mut().zip(right.iter_mut()).enumerate() {
+            let frame = match (end + k).checked_sub(size) {
+                Some(n) => n,
+                None => {
+                    *l = 0.0;
+                    *r = 0.0;
+                    continue;
+                }
+            };
+
+            *l = self.samples[frame * channels];
+            *r = self.samples[frame * channels + (channels > 1) as usize];
+        }
+    }
+
+    fn frames(&self) -> usize {
+        self.samples.len() / self.channels.max(1) as usize
+    }
+
+    fn frame_at(&self, time: f32) -> usize {
+        ((time * self.sample_rate as f32) as usize).min(self.frames())
+    }
+}
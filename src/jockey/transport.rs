@@ -0,0 +1,93 @@
+use std::{
+    net::UdpSocket,
+    time::{Duration, Instant},
+};
+
+use serde_yaml::Value;
+
+/// Time after the last message until the transport no longer counts as active
+const ACTIVE_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Largest message accepted from the socket
+const MAX_MESSAGE_LEN: usize = 1024;
+
+/// Receives playback positions from an external transport, like a DAW or a
+/// show control system, over a UDP socket on the local machine.
+///
+/// Every datagram is a JSON object like `{"time": 12.5, "speed": 1.0}`.
+/// Both fields are optional, so a stopped transport can just send its speed.
+#[derive(Debug)]
+pub struct Transport {
+    socket: Option<UdpSocket>,
+    pub port: Option<u16>,
+    pub time: Option<f32>,
+    pub speed: Option<f32>,
+    last_message: Option<Instant>,
+}
+
+impl Transport {
+    pub fn new(port: Option<u16>) -> Self {
+        let socket = port.and_then(|port| {
+            let socket = match UdpSocket::bind(("127.0.0.1", port)) {
+                Ok(s) => s,
+                Err(err) => {
+                    log::error!("Failed to listen for transport on port {}: {}", port, err);
+                    return None;
+                }
+            };
+
+            if let Err(err) = socket.set_nonblocking(true) {
+                log::error!("Failed to make transport socket non-blocking: {}", err);
+                return None;
+            }
+
+            log::info!("Listening for transport messages on port {}", port);
+            Some(socket)
+        });
+
+        Self {
+            socket,
+            port,
+            time: None,
+            speed: None,
+            last_message: None,
+        }
+    }
+
+    /// Reads all pending messages, only the most recent values are kept.
+    pub fn handle_input(&mut self) {
+        let socket = match &self.socket {
+            Some(s) => s,
+            None => return,
+        };
+
+        let mut buf = [0; MAX_MESSAGE_LEN];
+        while let Ok(len) = socket.recv(&mut buf) {
+            let object: Value = match serde_yaml::from_slice(&buf[..len]) {
+                Ok(v) => v,
+                Err(err) => {
+                    log::warn!("Failed to parse transport message: {}", err);
+                    continue;
+                }
+            };
+
+            if let Some(time) = object.get("time").and_then(Value::as_f64) {
+                self.time = Some(time as _);
+            }
+
+            if let Some(speed) = object.get("speed").and_then(Value::as_f64) {
+                self.speed = Some(speed as _);
+            }
+
+            self.last_message = Some(Instant::now());
+        }
+    }
+
+    /// Whether a message was received recently
+    pub fn active(&self) -> bool {
+        match self.last_message {
+            Some(last) => last.elapsed() < ACTIVE_TIMEOUT,
+            None => false,
+        }
+    }
+}
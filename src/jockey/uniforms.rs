@@ -1,10 +1,13 @@
-use std::{ffi::CString, mem::MaybeUninit};
+use std::{collections::HashMap, ffi::CString, mem::MaybeUninit};
 
 use anyhow::{bail, Result};
 use gl::types::*;
 use lazy_static::lazy_static;
+use regex::Regex;
 use serde_yaml::Value;
 
+use super::{BEAT_HISTORY_N, LFO_N, MIDI_N};
+
 lazy_static! {
     // slerpys golf coding stuff
     pub static ref R_NAME: CString = CString::new("R").unwrap();
@@ -12,11 +15,18 @@ lazy_static! {
 
     // miscellaneous
     pub static ref RESOLUTION_NAME: CString = CString::new("resolution").unwrap();
+    pub static ref ASPECT_NAME: CString = CString::new("aspect").unwrap();
+    pub static ref DPI_SCALE_NAME: CString = CString::new("dpi_scale").unwrap();
+    pub static ref REFRESH_RATE_NAME: CString = CString::new("refresh_rate").unwrap();
+    pub static ref TARGET_DT_NAME: CString = CString::new("target_dt").unwrap();
     pub static ref PASS_INDEX_NAME: CString = CString::new("pass_index").unwrap();
-    pub static ref OUT_COLOR_NAME: CString = CString::new("out_color").unwrap();
+    pub static ref PASS_COUNT_NAME: CString = CString::new("pass_count").unwrap();
     pub static ref POSITION_NAME: CString = CString::new("position").unwrap();
     pub static ref VERTEX_COUNT_NAME: CString = CString::new("vertex_count").unwrap();
+    pub static ref FOCUSED_NAME: CString = CString::new("focused").unwrap();
+    pub static ref FIRST_FRAME_NAME: CString = CString::new("first_frame").unwrap();
     pub static ref NOISE_NAME: CString = CString::new("noise").unwrap();
+    pub static ref BLUE_NOISE_NAME: CString = CString::new("blue_noise").unwrap();
 
     // time tracking
     pub static ref TIME_NAME: CString = CString::new("time").unwrap();
@@ -26,8 +36,16 @@ lazy_static! {
 
     // direct user input
     pub static ref BEAT_NAME: CString = CString::new("beat").unwrap();
+    pub static ref BEAT_PHASE_NAME: CString = CString::new("beat_phase").unwrap();
+    pub static ref BEAT_SINE_NAME: CString = CString::new("beat_sine").unwrap();
+    pub static ref BEAT_KICK_NAME: CString = CString::new("beat_kick").unwrap();
+    pub static ref BEAT_HISTORY_NAME: CString = CString::new("beat_history").unwrap();
+    pub static ref LFO_NAME: CString = CString::new("lfo").unwrap();
     pub static ref SLIDERS_NAME: CString = CString::new("sliders").unwrap();
+    pub static ref XFADE_NAME: CString = CString::new("xfade").unwrap();
     pub static ref BUTTONS_NAME: CString = CString::new("buttons").unwrap();
+    pub static ref HELD_NOTES_NAME: CString = CString::new("held_notes").unwrap();
+    pub static ref HELD_NOTE_COUNT_NAME: CString = CString::new("held_note_count").unwrap();
 
     // volume input
     pub static ref VOLUME_NAME: CString = CString::new("volume").unwrap();
@@ -58,6 +76,264 @@ lazy_static! {
     pub static ref HIGH_SMOOTH_NAME: CString = CString::new("high_smooth").unwrap();
     pub static ref HIGH_INTEGRATED_NAME: CString = CString::new("high_integrated").unwrap();
     pub static ref HIGH_SMOOTH_INTEGRATED_NAME: CString = CString::new("high_smooth_integrated").unwrap();
+
+    // uniform block
+    pub static ref COMMON_BLOCK_NAME: CString = CString::new("Sh4derJockey").unwrap();
+}
+
+/// Binding point of the uniform block holding the common uniforms
+pub const COMMON_BLOCK_BINDING: GLuint = 0;
+
+/// Contents of the `Sh4derJockey` uniform block
+///
+/// The fields follow the std140 layout of the block declared in
+/// `shaders/sh4derjockey.glsl`, where every `vec3` and every array element
+/// takes up 16 bytes.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct CommonBlock {
+    pub time: f32,
+    pub time_since_build: f32,
+    pub time_delta: f32,
+    pub frame_count: i32,
+
+    pub beat: f32,
+    pub beat_phase: f32,
+    pub beat_sine: f32,
+    pub xfade: f32,
+
+    pub refresh_rate: f32,
+    pub target_dt: f32,
+    pub dpi_scale: f32,
+    /// Pads the row, the `vec3` after it starts at the next 16 bytes
+    pub _padding: f32,
+
+    pub volume: [f32; 4],
+    pub volume_integrated: [f32; 4],
+    pub bass: [f32; 4],
+    pub bass_smooth: [f32; 4],
+    pub bass_integrated: [f32; 4],
+    pub bass_smooth_integrated: [f32; 4],
+    pub mid: [f32; 4],
+    pub mid_smooth: [f32; 4],
+    pub mid_integrated: [f32; 4],
+    pub mid_smooth_integrated: [f32; 4],
+    pub high: [f32; 4],
+    pub high_smooth: [f32; 4],
+    pub high_integrated: [f32; 4],
+    pub high_smooth_integrated: [f32; 4],
+
+    pub beat_history: [[f32; 4]; BEAT_HISTORY_N],
+    pub sliders: [[f32; 4]; MIDI_N],
+    pub buttons: [[f32; 4]; MIDI_N],
+    pub lfo: [[f32; 4]; LFO_N],
+    /// Only the first component is used, the rest pads the block to a full row
+    pub beat_kick: [f32; 4],
+}
+
+/// Locations of the uniforms and attributes of a stage
+///
+/// Queried once after the program is linked, so drawing a stage doesn't
+/// have to ask the driver every frame. Unused uniforms have a location of -1.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Locations {
+    pub position: GLint,
+    pub resolution: GLint,
+    pub aspect: GLint,
+    pub pass_index: GLint,
+    pub pass_count: GLint,
+    pub r: GLint,
+    pub k: GLint,
+    pub dpi_scale: GLint,
+    pub refresh_rate: GLint,
+    pub target_dt: GLint,
+    pub time: GLint,
+    pub time_since_build: GLint,
+    pub frame_count: GLint,
+    pub time_delta: GLint,
+    pub beat: GLint,
+    pub beat_phase: GLint,
+    pub beat_sine: GLint,
+    pub beat_kick: GLint,
+    pub beat_history: GLint,
+    pub lfo: GLint,
+    pub volume: GLint,
+    pub volume_integrated: GLint,
+    pub bass: GLint,
+    pub bass_smooth: GLint,
+    pub bass_integrated: GLint,
+    pub bass_smooth_integrated: GLint,
+    pub mid: GLint,
+    pub mid_smooth: GLint,
+    pub mid_integrated: GLint,
+    pub mid_smooth_integrated: GLint,
+    pub high: GLint,
+    pub high_smooth: GLint,
+    pub high_integrated: GLint,
+    pub high_smooth_integrated: GLint,
+    pub sliders: GLint,
+    pub buttons: GLint,
+    pub xfade: GLint,
+    pub held_notes: GLint,
+    pub held_note_count: GLint,
+    pub vertex_count: GLint,
+    pub focused: GLint,
+    pub first_frame: GLint,
+
+    /// Custom uniforms, by name
+    pub unis: HashMap<CString, GLint>,
+    /// Sampler and `_res` uniforms of the texture dependencies, in the same order
+    pub deps: Vec<(GLint, GLint)>,
+}
+
+impl Locations {
+    pub fn new(prog_id: GLuint, unis: &HashMap<CString, Uniform>) -> Self {
+        let loc = |name: &CString| unsafe { gl::GetUniformLocation(prog_id, name.as_ptr()) };
+        let position = unsafe { gl::GetAttribLocation(prog_id, POSITION_NAME.as_ptr()) };
+
+        Self {
+            position,
+            resolution: loc(&RESOLUTION_NAME),
+            aspect: loc(&ASPECT_NAME),
+            pass_index: loc(&PASS_INDEX_NAME),
+            pass_count: loc(&PASS_COUNT_NAME),
+            r: loc(&R_NAME),
+            k: loc(&K_NAME),
+            dpi_scale: loc(&DPI_SCALE_NAME),
+            refresh_rate: loc(&REFRESH_RATE_NAME),
+            target_dt: loc(&TARGET_DT_NAME),
+            time: loc(&TIME_NAME),
+            time_since_build: loc(&TIME_SINCE_BUILD_NAME),
+            frame_count: loc(&FRAME_COUNT_NAME),
+            time_delta: loc(&TIME_DELTA_NAME),
+            beat: loc(&BEAT_NAME),
+            beat_phase: loc(&BEAT_PHASE_NAME),
+            beat_sine: loc(&BEAT_SINE_NAME),
+            beat_kick: loc(&BEAT_KICK_NAME),
+            beat_history: loc(&BEAT_HISTORY_NAME),
+            lfo: loc(&LFO_NAME),
+            volume: loc(&VOLUME_NAME),
+            volume_integrated: loc(&VOLUME_INTEGRATED_NAME),
+            bass: loc(&BASS_NAME),
+            bass_smooth: loc(&BASS_SMOOTH_NAME),
+            bass_integrated: loc(&BASS_INTEGRATED_NAME),
+            bass_smooth_integrated: loc(&BASS_SMOOTH_INTEGRATED_NAME),
+            mid: loc(&MID_NAME),
+            mid_smooth: loc(&MID_SMOOTH_NAME),
+            mid_integrated: loc(&MID_INTEGRATED_NAME),
+            mid_smooth_integrated: loc(&MID_SMOOTH_INTEGRATED_NAME),
+            high: loc(&HIGH_NAME),
+            high_smooth: loc(&HIGH_SMOOTH_NAME),
+            high_integrated: loc(&HIGH_INTEGRATED_NAME),
+            high_smooth_integrated: loc(&HIGH_SMOOTH_INTEGRATED_NAME),
+            sliders: loc(&SLIDERS_NAME),
+            buttons: loc(&BUTTONS_NAME),
+            xfade: loc(&XFADE_NAME),
+            held_notes: loc(&HELD_NOTES_NAME),
+            held_note_count: loc(&HELD_NOTE_COUNT_NAME),
+            vertex_count: loc(&VERTEX_COUNT_NAME),
+            focused: loc(&FOCUSED_NAME),
+            first_frame: loc(&FIRST_FRAME_NAME),
+            unis: unis.keys().map(|name| (name.clone(), loc(name))).collect(),
+            deps: Vec::new(),
+        }
+    }
+
+    /// Adds the locations of a texture dependency and its resolution.
+    pub fn add_dep(&mut self, prog_id: GLuint, name: &CString) {
+        let mut res_name = name.as_bytes().to_vec();
+        res_name.extend_from_slice(b"_res");
+        let res_name = CString::new(res_name).unwrap();
+
+        unsafe {
+            self.deps.push((
+                gl::GetUniformLocation(prog_id, name.as_ptr()),
+                gl::GetUniformLocation(prog_id, res_name.as_ptr()),
+            ));
+        }
+    }
+}
+
+/// Metadata of a uniform, declared in a comment next to it in the shader
+///
+/// ```glsl
+/// uniform float gamma; // @range(1.0, 3.0) @default(2.2)
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+pub struct Annotation {
+    pub name: CString,
+    pub default: Uniform,
+    pub range: Option<(f32, f32)>,
+}
+
+impl Annotation {
+    /// Collects all annotated `float` and `vecN` uniforms in a shader.
+    ///
+    /// Malformed annotations are skipped with a warning.
+    pub fn parse_all(source: &str) -> Vec<Self> {
+        lazy_static! {
+            static ref UNIFORM_RE: Regex =
+                Regex::new(r"^\s*uniform\s+(float|vec2|vec3|vec4)\s+(\w+)\s*;\s*//(.*)$").unwrap();
+            static ref RANGE_RE: Regex = Regex::new(r"@range\(([^)]*)\)").unwrap();
+            static ref DEFAULT_RE: Regex = Regex::new(r"@default\(([^)]*)\)").unwrap();
+        }
+
+        fn numbers(list: &str) -> Option<Vec<f32>> {
+            list.split(',').map(|s| s.trim().parse().ok()).collect()
+        }
+
+        let mut out = Vec::new();
+        for line in source.lines() {
+            let caps = match UNIFORM_RE.captures(line) {
+                Some(s) => s,
+                None => continue,
+            };
+
+            let size = match &caps[1] {
+                "float" => 1,
+                ty => ty[3..].parse().unwrap(),
+            };
+            let name = &caps[2];
+            let comment = &caps[3];
+
+            let range_caps = RANGE_RE.captures(comment);
+            let default_caps = DEFAULT_RE.captures(comment);
+            if range_caps.is_none() && default_caps.is_none() {
+                continue;
+            }
+
+            let range = match range_caps.map(|c| numbers(&c[1])) {
+                Some(Some(v)) if v.len() == 2 && v[0] < v[1] => Some((v[0], v[1])),
+                None => None,
+                Some(_) => {
+                    log::warn!("Ignoring malformed range of uniform {:?}", name);
+                    None
+                }
+            };
+
+            // a single number fills all components
+            let default = match default_caps.map(|c| numbers(&c[1])) {
+                Some(Some(v)) if v.len() == size => Uniform::from_vector(&v),
+                Some(Some(v)) if v.len() == 1 => Uniform::from_vector(&vec![v[0]; size]),
+                None => {
+                    let low = range.map_or(0.0, |r| r.0);
+                    Uniform::from_vector(&vec![low; size])
+                }
+                Some(_) => {
+                    log::warn!("Ignoring malformed default of uniform {:?}", name);
+                    continue;
+                }
+            };
+
+            out.push(Self {
+                name: CString::new(name).unwrap(),
+                default: default.unwrap(),
+                range,
+            });
+        }
+
+        out
+    }
 }
 
 #[derive(Debug, Clone, Copy, PartialEq)]
@@ -167,6 +443,28 @@ impl Uniform {
         Ok(this)
     }
 
+    /// Creates a float or vector uniform from its components.
+    pub fn from_vector(components: &[GLfloat]) -> Option<Self> {
+        match *components {
+            [x] => Some(Self::Float(x)),
+            [x, y] => Some(Self::Vec2(x, y)),
+            [x, y, z] => Some(Self::Vec3(x, y, z)),
+            [x, y, z, w] => Some(Self::Vec4(x, y, z, w)),
+            _ => None,
+        }
+    }
+
+    /// Returns the components of a float or vector uniform.
+    pub fn to_vector(&self) -> Option<Vec<GLfloat>> {
+        match *self {
+            Self::Float(x) => Some(vec![x]),
+            Self::Vec2(x, y) => Some(vec![x, y]),
+            Self::Vec3(x, y, z) => Some(vec![x, y, z]),
+            Self::Vec4(x, y, z, w) => Some(vec![x, y, z, w]),
+            _ => None,
+        }
+    }
+
     pub fn bind(&self, location: GLint) {
         unsafe {
             match self {
@@ -220,6 +518,63 @@ impl Uniform {
         Ok(())
     }
 
+    /// Type the uniform has to be declared with to be set by `Uniform*fv`
+    pub fn gl_type(&self) -> GLenum {
+        match self {
+            Uniform::Float(_) => gl::FLOAT,
+            Uniform::Vec2(..) => gl::FLOAT_VEC2,
+            Uniform::Vec3(..) => gl::FLOAT_VEC3,
+            Uniform::Vec4(..) => gl::FLOAT_VEC4,
+            Uniform::Mat2(_) => gl::FLOAT_MAT2,
+            Uniform::Mat3(_) => gl::FLOAT_MAT3,
+            Uniform::Mat4(_) => gl::FLOAT_MAT4,
+            Uniform::Mat2x3(_) => gl::FLOAT_MAT2x3,
+            Uniform::Mat3x2(_) => gl::FLOAT_MAT3x2,
+            Uniform::Mat2x4(_) => gl::FLOAT_MAT2x4,
+            Uniform::Mat4x2(_) => gl::FLOAT_MAT4x2,
+            Uniform::Mat3x4(_) => gl::FLOAT_MAT3x4,
+            Uniform::Mat4x3(_) => gl::FLOAT_MAT4x3,
+        }
+    }
+
+    /// Name of the matching GLSL type
+    pub fn glsl_type(&self) -> &'static str {
+        match self {
+            Uniform::Float(_) => "float",
+            Uniform::Vec2(..) => "vec2",
+            Uniform::Vec3(..) => "vec3",
+            Uniform::Vec4(..) => "vec4",
+            Uniform::Mat2(_) => "mat2",
+            Uniform::Mat3(_) => "mat3",
+            Uniform::Mat4(_) => "mat4",
+            Uniform::Mat2x3(_) => "mat2x3",
+            Uniform::Mat3x2(_) => "mat3x2",
+            Uniform::Mat2x4(_) => "mat2x4",
+            Uniform::Mat4x2(_) => "mat4x2",
+            Uniform::Mat3x4(_) => "mat3x4",
+            Uniform::Mat4x3(_) => "mat4x3",
+        }
+    }
+
+    /// GLSL expression with the current value, matrices are written column by column
+    pub fn to_glsl(&self) -> String {
+        let components = match self {
+            Uniform::Float(x) => return glsl_float(*x),
+            Uniform::Vec2(x, y) => vec![*x, *y],
+            Uniform::Vec3(x, y, z) => vec![*x, *y, *z],
+            Uniform::Vec4(x, y, z, w) => vec![*x, *y, *z, *w],
+            Uniform::Mat2(vs) => vs.to_vec(),
+            Uniform::Mat3(vs) => vs.to_vec(),
+            Uniform::Mat4(vs) => vs.to_vec(),
+            Uniform::Mat2x3(vs) | Uniform::Mat3x2(vs) => vs.to_vec(),
+            Uniform::Mat2x4(vs) | Uniform::Mat4x2(vs) => vs.to_vec(),
+            Uniform::Mat3x4(vs) | Uniform::Mat4x3(vs) => vs.to_vec(),
+        };
+
+        let components: Vec<_> = components.into_iter().map(glsl_float).collect();
+        format!("{}({})", self.glsl_type(), components.join(", "))
+    }
+
     fn mat_slice_mut(&mut self) -> Option<&mut [GLfloat]> {
         match self {
             Uniform::Mat2(vs) => Some(vs),
@@ -236,6 +591,20 @@ impl Uniform {
     }
 }
 
+/// Formats a float as a GLSL literal, which always needs a decimal point or an exponent
+///
+/// GLSL has no literals for NaN and infinity, those are replaced by `0.0`
+/// and the largest float that can be written, respectively.
+pub fn glsl_float(x: GLfloat) -> String {
+    let x = match x {
+        x if x.is_nan() => 0.0,
+        x if x.is_infinite() => 1e38_f32.copysign(x),
+        x => x,
+    };
+
+    format!("{:?}", x)
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
@@ -298,4 +667,64 @@ mod test {
             0.0, 0.0, -4.0
         ]));
     }
+
+    #[test]
+    fn parse_annotations() {
+        let source = "
+            uniform float gamma; // @range(1.0, 3.0) @default(2.2)
+            uniform vec3 tint; // @default(0.5)
+            uniform vec2 offset; // @range(-1, 1)
+            uniform float plain; // just a comment
+            uniform float broken; // @default(1, 2)
+        ";
+
+        let annotations = Annotation::parse_all(source);
+        assert_eq!(
+            annotations,
+            vec![
+                Annotation {
+                    name: CString::new("gamma").unwrap(),
+                    default: Uniform::Float(2.2),
+                    range: Some((1.0, 3.0)),
+                },
+                Annotation {
+                    name: CString::new("tint").unwrap(),
+                    default: Uniform::Vec3(0.5, 0.5, 0.5),
+                    range: None,
+                },
+                Annotation {
+                    name: CString::new("offset").unwrap(),
+                    default: Uniform::Vec2(-1.0, -1.0),
+                    range: Some((-1.0, 1.0)),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn common_block_layout() {
+        // std140 size of the block in shaders/sh4derjockey.glsl
+        assert_eq!(
+            std::mem::size_of::<CommonBlock>(),
+            48 + 14 * 16 + 80 * 16 + 16
+        );
+    }
+
+    #[test]
+    fn uniform_to_glsl() {
+        assert_eq!(Uniform::Float(2.0).to_glsl(), "2.0");
+        assert_eq!(
+            Uniform::Vec3(0.5, -1.0, 3.25).to_glsl(),
+            "vec3(0.5, -1.0, 3.25)"
+        );
+        assert_eq!(
+            Uniform::Mat2([1.0, 3.0, 2.0, 4.0]).to_glsl(),
+            "mat2(1.0, 3.0, 2.0, 4.0)"
+        );
+        assert_eq!(Uniform::Float(f32::NAN).to_glsl(), "0.0");
+        assert_eq!(
+            Uniform::Vec2(f32::INFINITY, f32::NEG_INFINITY).to_glsl(),
+            "vec2(1e38, -1e38)"
+        );
+    }
 }
@@ -0,0 +1,128 @@
+use std::{
+    sync::mpsc::{channel, Receiver},
+    time::Instant,
+};
+
+use image::{AnimationDecoder, RgbaImage};
+
+use crate::util::*;
+
+/// Delay used for frames which don't specify one, like most browsers do
+const DEFAULT_FRAME_DELAY: f32 = 0.1;
+
+/// A looping animation, which is played back as a texture.
+///
+/// The frames are decoded on a background thread and are added to the
+/// animation as they arrive, so long animations start playing right away.
+pub struct Video {
+    frames: Vec<(f32, RgbaImage)>,
+    duration: f32,
+    receiver: Receiver<(f32, RgbaImage)>,
+    current: Option<usize>,
+    pub speed: f32,
+    pub looping: bool,
+}
+
+impl std::fmt::Debug for Video {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct(stringify!(Video))
+            .field("frames", &self.frames.len())
+            .field("duration", &self.duration)
+            .field("speed", &self.speed)
+            .field("looping", &self.looping)
+            .finish()
+    }
+}
+
+impl Video {
+    /// Starts decoding the GIF file at the given path.
+    pub fn open(path: &str, speed: f32, looping: bool) -> Result<Self, String> {
+        let file = std::fs::File::open(path)
+            .map_err(|e| format!("Failed to open video {:?}: {}", path, e))?;
+
+        let decoder = image::codecs::gif::GifDecoder::new(file)
+            .map_err(|e| format!("Failed to decode video {:?}: {}", path, e))?;
+
+        let (tx, rx) = channel();
+        let path = path.to_owned();
+        std::thread::spawn(move || {
+            let start = Instant::now();
+            for frame in decoder.into_frames() {
+                let frame = match frame {
+                    Ok(s) => s,
+                    Err(err) => {
+                        log::error!("Failed to decode frame of video {:?}: {}", path, err);
+                        break;
+                    }
+                };
+
+                let (numer, denom) = frame.delay().numer_denom_ms();
+                let delay = match numer as f32 / denom as f32 / 1000.0 {
+                    d if d > 0.0 => d,
+                    _ => DEFAULT_FRAME_DELAY,
+                };
+
+                let mut image = frame.into_buffer();
+                image::imageops::flip_vertical_in_place(&mut image);
+
+                // the pipeline was dropped, stop decoding
+                if tx.send((delay, image)).is_err() {
+                    return;
+                }
+            }
+
+            let decode_time = start.elapsed().as_secs_f32();
+            log::info!("Decoded video {:?} in {}s", path, decode_time);
+        });
+
+        Ok(Self {
+            frames: Vec::new(),
+            duration: 0.0,
+            receiver: rx,
+            current: None,
+            speed,
+            looping,
+        })
+    }
+
+    /// Uploads the frame that should be shown at the given time.
+    pub fn update_texture(&mut self, time: f32, tex: &mut Texture2D) {
+        // collect newly decoded frames
+        for (delay, image) in self.receiver.try_iter() {
+            self.frames.push((self.duration, image));
+            self.duration += delay;
+        }
+
+        if self.frames.is_empty() {
+            return;
+        }
+
+        let time = time * self.speed;
+        let time = match self.looping {
+            true => time.rem_euclid(self.duration),
+            false => time.clamp(0.0, self.duration),
+        };
+
+        let index = self.frames.partition_point(|(start, _)| *start <= time);
+        let index = index.saturating_sub(1);
+        if self.current == Some(index) {
+            return;
+        }
+
+        self.current = Some(index);
+        let image = &self.frames[index].1;
+        if tex.resolution() != [image.width(), image.height(), 0] {
+            *tex = Texture2D::with_params(
+                [image.width(), image.height()],
+                tex.min_filter.get(),
+                tex.mag_filter.get(),
+                tex.wrap_mode,
+                tex.format,
+                tex.mipmap,
+                image.as_ptr() as _,
+            );
+        } else {
+            tex.write(image.as_ptr() as _);
+        }
+    }
+}
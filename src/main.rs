@@ -6,7 +6,7 @@ mod util;
 mod jockey;
 
 use std::{
-    path::Path,
+    path::{Path, PathBuf},
     sync::atomic::{AtomicBool, Ordering},
     time::Duration,
 };
@@ -34,6 +34,59 @@ struct Args {
     #[clap(short, long, action = clap::ArgAction::Count, global = true)]
     #[clap(help = "Use verbose output (can be applied multiple times)")]
     verbose: u8,
+
+    #[clap(long, value_name = "FILE", global = true)]
+    #[clap(help = "Also write the log to a file, the previous log is kept as FILE.old")]
+    log_file: Option<PathBuf>,
+
+    #[clap(long, value_name = "FILE", global = true)]
+    #[clap(help = "Periodically write the render time of every stage to a CSV file")]
+    timings: Option<PathBuf>,
+
+    #[clap(long, global = true)]
+    #[clap(help = "Exit with a non-zero status code if the first pipeline fails to build")]
+    strict: bool,
+
+    #[clap(long, global = true)]
+    #[clap(help = "Only render fragment stages, without compute, feedback or mipmaps")]
+    safe: bool,
+
+    #[clap(long)]
+    #[clap(help = "Log the debug messages of the OpenGL driver, if it supports them")]
+    gl_debug: bool,
+
+    #[clap(long)]
+    #[clap(help = "Show the imgui metrics and demo windows for debugging the control panel")]
+    debug_ui: bool,
+
+    #[clap(long, value_name = "N")]
+    #[clap(help = "Advance time by 1/N seconds every frame, regardless of the actual frame rate")]
+    fps: Option<f32>,
+
+    #[clap(long, value_name = "SECONDS")]
+    #[clap(help = "Start with the timeline at the given time instead of zero")]
+    start_time: Option<f32>,
+
+    #[clap(long)]
+    #[clap(help = "Start with the timeline stopped")]
+    paused: bool,
+
+    #[clap(long)]
+    #[clap(help = "Open the output window without a titlebar and borders")]
+    borderless: bool,
+
+    #[clap(long)]
+    #[clap(help = "Keep the output window above all other windows")]
+    always_on_top: bool,
+
+    #[clap(
+        long,
+        value_name = "X,Y",
+        value_delimiter = ',',
+        allow_hyphen_values = true
+    )]
+    #[clap(help = "Move the output window to the given position on the desktop")]
+    window_position: Option<Vec<i32>>,
 }
 
 #[derive(Parser)]
@@ -45,6 +98,20 @@ enum SubCommand {
     #[clap(about = "Start the tool in the current working directory (default)")]
     #[command(alias("r"))]
     Run,
+
+    #[clap(about = "Build all pipelines in the current working directory and exit")]
+    #[command(alias("v"))]
+    Validate,
+
+    #[clap(about = "Render a pipeline without the control panel and print its render times")]
+    #[command(alias("b"))]
+    Bench {
+        #[clap(default_value_t = 600, help = "Number of frames to measure")]
+        frames: usize,
+
+        #[clap(long, help = "Pipeline file to render, defaults to the first one")]
+        pipeline: Option<String>,
+    },
 }
 
 fn main() {
@@ -61,13 +128,28 @@ fn main() {
     let mut config = ConfigBuilder::new();
     let log_utc = config.set_time_offset_to_local().is_err();
 
-    TermLogger::init(
+    let config = config.build();
+    let mut loggers: Vec<Box<dyn SharedLogger>> = vec![TermLogger::new(
         log_level,
-        config.build(),
+        config.clone(),
         TerminalMode::Mixed,
         ColorChoice::Always,
-    )
-    .unwrap();
+    )];
+
+    // the log file always records pipeline loads and errors
+    let mut log_file_err = None;
+    if let Some(path) = &args.log_file {
+        match open_log_file(path) {
+            Ok(file) => loggers.push(WriteLogger::new(
+                log_level.max(LevelFilter::Info),
+                config,
+                file,
+            )),
+            Err(err) => log_file_err = Some(err),
+        }
+    }
+
+    CombinedLogger::init(loggers).unwrap();
 
     log::info!("Version: {}", VERSION.as_str());
     log::info!("Log level: {}", log_level);
@@ -76,6 +158,10 @@ fn main() {
         log::warn!("Failed to resolve local time, logging in UTC");
     }
 
+    if let Some(err) = log_file_err {
+        log::error!("Failed to open log file: {}", err);
+    }
+
     if let Some(SubCommand::Init) = args.subcmd {
         let plf = Path::new("./pipeline.yaml");
         let shf = Path::new("./scene.frag");
@@ -100,6 +186,26 @@ fn main() {
         return;
     }
 
+    if let Some(SubCommand::Validate) = args.subcmd {
+        let code = match jockey::validate_pipelines() {
+            true => 0,
+            false => 1,
+        };
+
+        std::process::exit(code);
+    }
+
+    if let Some(SubCommand::Bench { frames, pipeline }) = &args.subcmd {
+        let mut jockey = Jockey::init(args.gl_debug);
+        jockey.safe_mode = args.safe;
+        let code = match jockey.bench(*frames, pipeline.as_deref()) {
+            true => 0,
+            false => 1,
+        };
+
+        std::process::exit(code);
+    }
+
     // set termination signal handler
     let kill_signal: &'static AtomicBool = Box::leak(Box::new(AtomicBool::new(false)));
     ctrlc::set_handler(move || {
@@ -114,8 +220,78 @@ fn main() {
     })
     .unwrap();
 
+    // writing the log or the timings must not rebuild the pipeline
+    if let Some(path) = &args.log_file {
+        let mut old = path.as_os_str().to_owned();
+        old.push(".old");
+        jockey::ignore_output_file(path);
+        jockey::ignore_output_file(Path::new(&old));
+    }
+
+    if let Some(path) = &args.timings {
+        jockey::ignore_output_file(path);
+    }
+
     // create the jockey
-    let mut jockey = Jockey::init();
+    let mut jockey = Jockey::init(args.gl_debug);
+    jockey.safe_mode = args.safe;
+    if args.safe {
+        log::warn!("Safe mode is active, compute, vertex and feedback stages are skipped");
+    }
+
+    if args.gl_debug && !util::enable_debug_output() {
+        log::warn!("The OpenGL driver doesn't support debug messages (KHR_debug)");
+    }
+
+    jockey.show_metrics = args.debug_ui;
+    jockey.show_demo = args.debug_ui;
+
+    match args.fps {
+        Some(fps) if fps > 0.0 => jockey.fixed_dt = Some(1.0 / fps),
+        Some(fps) => log::error!("Expected --fps to be positive, got {}", fps),
+        None => (),
+    }
+
+    if let Some(time) = args.start_time {
+        jockey.time = time;
+        jockey.time_range.0 = jockey.time_range.0.min(time);
+        jockey.time_range.1 = jockey.time_range.1.max(time);
+    }
+
+    if args.paused {
+        jockey.speed = 0.0;
+    }
+
+    // the flags override the config file, until it's reloaded
+    let window = jockey.ctx.context.window();
+    if args.borderless {
+        window.set_decorations(false);
+    }
+
+    if args.always_on_top {
+        window.set_always_on_top(true);
+    }
+
+    match args.window_position.as_deref() {
+        Some(&[x, y]) => jockey::move_window(window, [x, y]),
+        Some(xs) => log::error!("Expected --window-position to be X,Y, got {:?}", xs),
+        None => (),
+    }
+
+    if args.strict {
+        if let Err(err) = jockey.wait_for_pipeline() {
+            let summary = err.lines().next().unwrap_or_default();
+            eprintln!("Failed to load pipeline: {}", summary);
+            std::process::exit(1);
+        }
+    }
+
+    if let Some(path) = &args.timings {
+        match jockey::StageTimings::create(path) {
+            Ok(timings) => jockey.timings = Some(timings),
+            Err(err) => log::error!("Failed to create stage timings file: {}", err),
+        }
+    }
 
     // close console window
     #[cfg(all(windows, not(debug_assertions)))]
@@ -140,6 +316,17 @@ fn main() {
     log::info!("Bye bye!");
 }
 
+/// Opens a fresh log file, keeping the log of the previous session around.
+fn open_log_file(path: &Path) -> std::io::Result<std::fs::File> {
+    if path.exists() {
+        let mut old = path.as_os_str().to_owned();
+        old.push(".old");
+        std::fs::rename(path, old)?;
+    }
+
+    std::fs::File::create(path)
+}
+
 // https://github.com/kirillkovalenko/nssm/blob/master/console.cpp
 #[cfg(all(windows, not(debug_assertions)))]
 fn close_console() {
@@ -5,6 +5,8 @@ use std::ops::{Add, Div};
 pub struct RunningAverage<T, const SIZE: usize> {
     pub buffer: [T; SIZE],
     pub index: usize,
+    /// Number of elements pushed so far, up to `SIZE`
+    pub len: usize,
 }
 
 impl<T, const SIZE: usize> std::fmt::Debug for RunningAverage<T, SIZE> {
@@ -12,6 +14,7 @@ impl<T, const SIZE: usize> std::fmt::Debug for RunningAverage<T, SIZE> {
         f.debug_struct(stringify!(RunningAverage))
             .field("buffer", &"[..]")
             .field("index", &self.index)
+            .field("len", &self.len)
             .finish()
     }
 }
@@ -25,6 +28,7 @@ where
         Self {
             buffer: [T::from(0); SIZE],
             index: 0,
+            len: 0,
         }
     }
 
@@ -35,6 +39,17 @@ where
     pub fn push(&mut self, value: T) {
         self.buffer[self.index] = value;
         self.index = (self.index + 1) % SIZE;
+        self.len = (self.len + 1).min(SIZE);
+    }
+
+    /// Returns all elements pushed so far, without the zeros it started with.
+    pub fn values(&self) -> &[T] {
+        &self.buffer[..self.len]
+    }
+
+    /// Returns the most recently added element.
+    pub fn last(&self) -> T {
+        self.buffer[(self.index + SIZE - 1) % SIZE]
     }
 
     /// Computes the average of all elements in the ring buffer.
@@ -60,6 +75,37 @@ where
     }
 }
 
+impl<const SIZE: usize> RunningAverage<f32, SIZE> {
+    /// Smallest element pushed so far, or zero if the buffer is empty.
+    pub fn min(&self) -> f32 {
+        match self.values() {
+            [] => 0.0,
+            xs => xs.iter().copied().fold(f32::INFINITY, f32::min),
+        }
+    }
+
+    /// Largest element pushed so far, or zero if the buffer is empty.
+    pub fn max(&self) -> f32 {
+        match self.values() {
+            [] => 0.0,
+            xs => xs.iter().copied().fold(f32::NEG_INFINITY, f32::max),
+        }
+    }
+
+    /// Standard deviation of the elements pushed so far.
+    pub fn std_dev(&self) -> f32 {
+        let xs = self.values();
+        if xs.is_empty() {
+            return 0.0;
+        }
+
+        let n = xs.len() as f32;
+        let mean = xs.iter().sum::<f32>() / n;
+        let variance = xs.iter().map(|x| (x - mean) * (x - mean)).sum::<f32>() / n;
+        variance.sqrt()
+    }
+}
+
 #[cfg(test)]
 mod test {
     use super::RunningAverage;
@@ -91,4 +137,36 @@ mod test {
         }
         assert_eq!(ra.get(), 3.0);
     }
+
+    #[test]
+    fn running_average_spread() {
+        let mut ra = RunningAverage::<f32, 8>::new();
+        assert_eq!(ra.min(), 0.0);
+        assert_eq!(ra.std_dev(), 0.0);
+
+        for &x in [3.0, 5.0, 3.0, 5.0].iter() {
+            ra.push(x);
+        }
+        assert_eq!(ra.min(), 3.0);
+        assert_eq!(ra.max(), 5.0);
+        assert_eq!(ra.std_dev(), 1.0);
+
+        for _ in 0..8 {
+            ra.push(4.0);
+        }
+        assert_eq!(ra.min(), 4.0);
+        assert_eq!(ra.max(), 4.0);
+        assert_eq!(ra.std_dev(), 0.0);
+    }
+
+    #[test]
+    fn running_average_last() {
+        let mut ra = RunningAverage::<i32, 3>::new();
+        assert_eq!(ra.last(), 0);
+
+        for k in 1..=4 {
+            ra.push(k);
+            assert_eq!(ra.last(), k);
+        }
+    }
 }
@@ -47,4 +47,10 @@ impl Cache {
     pub fn fetch(path: &str) -> Option<Rc<dyn Texture>> {
         Self::internal().get(path).map(|s| Rc::clone(&s.tex))
     }
+
+    /// Drops all cached textures, pipelines still using them keep them alive.
+    pub fn clear() {
+        Self::init();
+        Self::internal_mut().clear();
+    }
 }
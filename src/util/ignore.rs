@@ -0,0 +1,147 @@
+use std::path::Path;
+
+use regex::Regex;
+
+/// Patterns of temporary files written by common editors, and by the tool itself
+const DEFAULT_PATTERNS: &[&str] = &[
+    ".git/",
+    "*.swp",
+    "*.swo",
+    "*.swx",
+    "*~",
+    ".#*",
+    "#*#",
+    "4913",
+    "*.tmp",
+    ".DS_Store",
+    "automation.dat",
+];
+
+/// A list of gitignore-style patterns.
+///
+/// Patterns without a slash match files and folders anywhere in the tree,
+/// all other patterns are relative to the root. `*` and `?` do not match
+/// slashes, while `**` matches any number of folders. Negated patterns are
+/// not supported.
+#[derive(Debug, Clone)]
+pub struct IgnoreList {
+    patterns: Vec<Regex>,
+}
+
+impl IgnoreList {
+    /// Creates a list containing only the default editor patterns.
+    pub fn new() -> Self {
+        let mut this = Self {
+            patterns: Vec::new(),
+        };
+
+        for pattern in DEFAULT_PATTERNS {
+            this.add_glob(pattern);
+        }
+
+        this
+    }
+
+    /// Creates a list from the default patterns and the lines of a file.
+    ///
+    /// A missing file is treated like an empty one.
+    pub fn load(path: impl AsRef<Path>) -> Self {
+        let mut this = Self::new();
+        if let Ok(content) = std::fs::read_to_string(path) {
+            for line in content.lines() {
+                this.add(line);
+            }
+        }
+
+        this
+    }
+
+    /// Adds a single pattern, ignoring empty lines and comments.
+    pub fn add(&mut self, pattern: &str) {
+        let pattern = pattern.trim();
+        if pattern.is_empty() || pattern.starts_with('#') || pattern.starts_with('!') {
+            return;
+        }
+
+        self.add_glob(pattern);
+    }
+
+    fn add_glob(&mut self, pattern: &str) {
+        let pattern = pattern.trim_end_matches('/');
+        let anchored = pattern.contains('/');
+        let pattern = pattern.trim_start_matches('/');
+
+        let mut re = String::from(match anchored {
+            true => "^",
+            false => "(^|/)",
+        });
+
+        let mut chars = pattern.chars().peekable();
+        while let Some(c) = chars.next() {
+            match c {
+                '*' if chars.peek() == Some(&'*') => {
+                    chars.next();
+                    match chars.peek() {
+                        Some('/') => {
+                            chars.next();
+                            re.push_str("(.*/)?");
+                        }
+                        _ => re.push_str(".*"),
+                    }
+                }
+                '*' => re.push_str("[^/]*"),
+                '?' => re.push_str("[^/]"),
+                c => re.push_str(&regex::escape(&c.to_string())),
+            }
+        }
+
+        // also match everything inside of matching folders
+        re.push_str("(/|$)");
+
+        match Regex::new(&re) {
+            Ok(re) => self.patterns.push(re),
+            Err(err) => log::warn!("Invalid ignore pattern {:?}: {}", pattern, err),
+        }
+    }
+
+    /// Checks whether a path relative to the root matches any pattern.
+    pub fn is_ignored(&self, path: impl AsRef<Path>) -> bool {
+        let path = path.as_ref().to_string_lossy().replace('\\', "/");
+        let path = path.trim_start_matches("./");
+        self.patterns.iter().any(|re| re.is_match(path))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn ignore_defaults() {
+        let list = IgnoreList::new();
+        assert!(list.is_ignored("scene.frag.swp"));
+        assert!(list.is_ignored("shaders/.scene.frag.swp"));
+        assert!(list.is_ignored("scene.frag~"));
+        assert!(list.is_ignored(".git/index"));
+        assert!(!list.is_ignored("scene.frag"));
+        assert!(!list.is_ignored("pipeline.yaml"));
+    }
+
+    #[test]
+    fn ignore_patterns() {
+        let mut list = IgnoreList::new();
+        list.add("# comment");
+        list.add("*.png");
+        list.add("/renders/");
+        list.add("assets/**/*.obj");
+
+        assert!(list.is_ignored("logo.png"));
+        assert!(list.is_ignored("textures/logo.png"));
+        assert!(list.is_ignored("renders/frame.exr"));
+        assert!(!list.is_ignored("shaders/renders/common.glsl"));
+        assert!(list.is_ignored("assets/models/tree.obj"));
+        assert!(list.is_ignored("assets/tree.obj"));
+        assert!(!list.is_ignored("tree.obj"));
+        assert!(!list.is_ignored("# comment"));
+    }
+}
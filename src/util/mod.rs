@@ -1,6 +1,10 @@
 use std::{
-    collections::HashSet,
-    ffi::{c_void, CString},
+    cell::RefCell,
+    collections::{HashMap, HashSet},
+    ffi::{c_void, CStr, CString},
+    path::{Path, PathBuf},
+    rc::Rc,
+    time::{Duration, SystemTime},
 };
 
 use gl::types::*;
@@ -9,13 +13,19 @@ use regex::Regex;
 
 mod average;
 mod cache;
+mod ignore;
 mod ringbuffer;
+mod storage;
 mod texture;
+mod uniform_buffer;
 
 pub use average::*;
 pub use cache::*;
+pub use ignore::*;
 pub use ringbuffer::*;
+pub use storage::*;
 pub use texture::*;
+pub use uniform_buffer::*;
 
 pub fn gcd(mut x: u32, mut y: u32) -> u32 {
     if x == 0 {
@@ -99,26 +109,79 @@ const FULLSCREEN_RECT: [GLfloat; 12] = [
 ];
 
 pub fn draw_fullscreen(vao: GLuint) {
+    draw_triangles(vao, &FULLSCREEN_RECT);
+}
+
+/// Draws triangles from a list of 2D points in clip space.
+pub fn draw_triangles(vao: GLuint, points: &[GLfloat]) {
     unsafe {
         gl::BindVertexArray(vao);
         gl::BindBuffer(gl::ARRAY_BUFFER, vao);
         gl_debug_check!();
 
-        let data_size = FULLSCREEN_RECT.len() * std::mem::size_of::<GLfloat>();
+        let data_size = std::mem::size_of_val(points);
         gl::BufferData(
             gl::ARRAY_BUFFER,
             data_size as _,
-            std::mem::transmute(&FULLSCREEN_RECT[0]),
+            points.as_ptr() as _,
             gl::STATIC_DRAW,
         );
         gl_debug_check!();
 
-        let vert_count = FULLSCREEN_RECT.len() as GLsizei / 2;
+        let vert_count = points.len() as GLsizei / 2;
         gl::DrawArrays(gl::TRIANGLES, 0, vert_count);
         gl_debug_check!();
     }
 }
 
+/// Index which ends the current strip in indexed draws.
+pub const PRIMITIVE_RESTART_INDEX: GLuint = GLuint::MAX;
+
+/// Indices of `count` vertices split into strips of `strip_length` vertices,
+/// separated by [`PRIMITIVE_RESTART_INDEX`].
+pub fn strip_indices(count: GLsizei, strip_length: GLsizei) -> Vec<GLuint> {
+    let (count, strip_length) = (count.max(0) as GLuint, strip_length.max(1) as GLuint);
+    let mut indices = Vec::with_capacity((count + count / strip_length) as _);
+    for k in 0..count {
+        if k > 0 && k % strip_length == 0 {
+            indices.push(PRIMITIVE_RESTART_INDEX);
+        }
+        indices.push(k);
+    }
+    indices
+}
+
+/// Draws vertices in the order of `indices`, starting a new primitive at
+/// every [`PRIMITIVE_RESTART_INDEX`].
+pub fn draw_indexed(vao: GLuint, ebo: GLuint, indices: &[GLuint], mode: GLenum) {
+    unsafe {
+        gl::BindVertexArray(vao);
+        gl::BindBuffer(gl::ARRAY_BUFFER, vao);
+        gl_debug_check!();
+
+        gl::BufferData(gl::ARRAY_BUFFER, 0, std::ptr::null(), gl::STATIC_DRAW);
+        gl_debug_check!();
+
+        gl::BindBuffer(gl::ELEMENT_ARRAY_BUFFER, ebo);
+        gl::BufferData(
+            gl::ELEMENT_ARRAY_BUFFER,
+            std::mem::size_of_val(indices) as _,
+            indices.as_ptr() as _,
+            gl::STATIC_DRAW,
+        );
+        gl_debug_check!();
+
+        gl::Enable(gl::PRIMITIVE_RESTART);
+        gl::PrimitiveRestartIndex(PRIMITIVE_RESTART_INDEX);
+        gl::DrawElements(mode, indices.len() as _, gl::UNSIGNED_INT, std::ptr::null());
+        gl::Disable(gl::PRIMITIVE_RESTART);
+        gl_debug_check!();
+
+        gl::BindBuffer(gl::ELEMENT_ARRAY_BUFFER, 0);
+        gl_debug_check!();
+    }
+}
+
 pub fn draw_vertices(vao: GLuint, count: GLsizei, mode: GLenum) {
     unsafe {
         gl::BindVertexArray(vao);
@@ -168,9 +231,26 @@ pub fn compile_shader(src: &str, ty: GLenum) -> Result<GLuint, String> {
 ///
 /// Creates a new program and attaches the given shaders to that program.
 pub fn link_program(sh: &[GLuint]) -> Result<GLuint, String> {
+    link_program_with_outputs(sh, &[])
+}
+
+/// Links a program, binding the given fragment shader outputs without an
+/// explicit location to the lowest locations not taken by any other output.
+pub fn link_program_with_outputs(
+    sh: &[GLuint],
+    outputs: &[(String, Option<GLuint>)],
+) -> Result<GLuint, String> {
     unsafe {
         let program = gl::CreateProgram();
 
+        // Assign output locations, these only take effect when linking
+        let taken: Vec<_> = outputs.iter().filter_map(|(_, loc)| *loc).collect();
+        let mut free = (0..).filter(|loc| !taken.contains(loc));
+        for (name, _) in outputs.iter().filter(|(_, loc)| loc.is_none()) {
+            let name = CString::new(name.as_str()).unwrap();
+            gl::BindFragDataLocation(program, free.next().unwrap(), name.as_ptr());
+        }
+
         // Link program
         sh.iter().for_each(|&s| gl::AttachShader(program, s));
         gl::LinkProgram(program);
@@ -197,6 +277,175 @@ pub fn link_program(sh: &[GLuint]) -> Result<GLuint, String> {
     }
 }
 
+/// Whether the uniform with the given name is an image, as opposed to a sampler.
+pub fn uniform_is_image(prog_id: GLuint, name: &CStr) -> bool {
+    unsafe {
+        let index = gl::GetProgramResourceIndex(prog_id, gl::UNIFORM, name.as_ptr());
+        if index == gl::INVALID_INDEX {
+            return false;
+        }
+
+        let mut type_ = 0;
+        gl::GetProgramResourceiv(
+            prog_id,
+            gl::UNIFORM,
+            index,
+            1,
+            &gl::TYPE,
+            1,
+            std::ptr::null_mut(),
+            &mut type_,
+        );
+        gl_debug_check!();
+
+        matches!(
+            type_ as GLenum,
+            gl::IMAGE_1D
+                | gl::IMAGE_2D
+                | gl::IMAGE_3D
+                | gl::INT_IMAGE_1D
+                | gl::INT_IMAGE_2D
+                | gl::INT_IMAGE_3D
+                | gl::UNSIGNED_INT_IMAGE_1D
+                | gl::UNSIGNED_INT_IMAGE_2D
+                | gl::UNSIGNED_INT_IMAGE_3D
+        )
+    }
+}
+
+/// A uniform or attribute which is still used by a program after linking.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ActiveVariable {
+    pub name: String,
+    pub type_: GLenum,
+    /// Number of elements, larger than 1 for arrays
+    pub size: GLint,
+    /// Location of the variable, -1 for uniforms in a block
+    pub location: GLint,
+}
+
+impl ActiveVariable {
+    /// GLSL name of the type, if it's a common one.
+    pub fn type_name(&self) -> Option<&'static str> {
+        let name = match self.type_ {
+            gl::FLOAT => "float",
+            gl::FLOAT_VEC2 => "vec2",
+            gl::FLOAT_VEC3 => "vec3",
+            gl::FLOAT_VEC4 => "vec4",
+            gl::INT => "int",
+            gl::INT_VEC2 => "ivec2",
+            gl::INT_VEC3 => "ivec3",
+            gl::INT_VEC4 => "ivec4",
+            gl::UNSIGNED_INT => "uint",
+            gl::UNSIGNED_INT_VEC2 => "uvec2",
+            gl::UNSIGNED_INT_VEC3 => "uvec3",
+            gl::UNSIGNED_INT_VEC4 => "uvec4",
+            gl::BOOL => "bool",
+            gl::FLOAT_MAT2 => "mat2",
+            gl::FLOAT_MAT3 => "mat3",
+            gl::FLOAT_MAT4 => "mat4",
+            gl::FLOAT_MAT2x3 => "mat2x3",
+            gl::FLOAT_MAT3x2 => "mat3x2",
+            gl::FLOAT_MAT2x4 => "mat2x4",
+            gl::FLOAT_MAT4x2 => "mat4x2",
+            gl::FLOAT_MAT3x4 => "mat3x4",
+            gl::FLOAT_MAT4x3 => "mat4x3",
+            gl::SAMPLER_1D => "sampler1D",
+            gl::SAMPLER_2D => "sampler2D",
+            gl::SAMPLER_3D => "sampler3D",
+            gl::IMAGE_1D => "image1D",
+            gl::IMAGE_2D => "image2D",
+            gl::IMAGE_3D => "image3D",
+            _ => return None,
+        };
+
+        Some(name)
+    }
+}
+
+/// The active uniforms and attributes of a linked program.
+///
+/// The linker removes everything which doesn't contribute to the output,
+/// so a uniform missing here is never set, even if it's declared.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ProgramInfo {
+    pub uniforms: Vec<ActiveVariable>,
+    pub attributes: Vec<ActiveVariable>,
+}
+
+impl ProgramInfo {
+    pub fn new(prog_id: GLuint) -> Self {
+        unsafe {
+            let mut uniforms = Vec::new();
+            let mut count = 0;
+            let mut max_len = 0;
+            gl::GetProgramiv(prog_id, gl::ACTIVE_UNIFORMS, &mut count);
+            gl::GetProgramiv(prog_id, gl::ACTIVE_UNIFORM_MAX_LENGTH, &mut max_len);
+
+            for index in 0..count {
+                let mut buf = vec![0_u8; max_len.max(1) as usize];
+                let (mut len, mut size, mut type_) = (0, 0, 0);
+                gl::GetActiveUniform(
+                    prog_id,
+                    index as _,
+                    buf.len() as _,
+                    &mut len,
+                    &mut size,
+                    &mut type_,
+                    buf.as_mut_ptr() as _,
+                );
+
+                buf.truncate(len as _);
+                let name = CString::new(buf).unwrap();
+                let location = gl::GetUniformLocation(prog_id, name.as_ptr());
+                uniforms.push(ActiveVariable {
+                    name: name.to_string_lossy().into_owned(),
+                    type_,
+                    size,
+                    location,
+                });
+            }
+
+            let mut attributes = Vec::new();
+            gl::GetProgramiv(prog_id, gl::ACTIVE_ATTRIBUTES, &mut count);
+            gl::GetProgramiv(prog_id, gl::ACTIVE_ATTRIBUTE_MAX_LENGTH, &mut max_len);
+
+            for index in 0..count {
+                let mut buf = vec![0_u8; max_len.max(1) as usize];
+                let (mut len, mut size, mut type_) = (0, 0, 0);
+                gl::GetActiveAttrib(
+                    prog_id,
+                    index as _,
+                    buf.len() as _,
+                    &mut len,
+                    &mut size,
+                    &mut type_,
+                    buf.as_mut_ptr() as _,
+                );
+
+                buf.truncate(len as _);
+                let name = CString::new(buf).unwrap();
+                let location = gl::GetAttribLocation(prog_id, name.as_ptr());
+                attributes.push(ActiveVariable {
+                    name: name.to_string_lossy().into_owned(),
+                    type_,
+                    size,
+                    location,
+                });
+            }
+            gl_debug_check!();
+
+            uniforms.sort_by(|a, b| a.name.cmp(&b.name));
+            attributes.sort_by(|a, b| a.name.cmp(&b.name));
+
+            Self {
+                uniforms,
+                attributes,
+            }
+        }
+    }
+}
+
 #[allow(non_snake_case)]
 pub unsafe fn gl_TexImageND(
     target: GLenum,
@@ -248,16 +497,151 @@ pub unsafe fn gl_TexImageND(
     }
 }
 
-fn in_block(prefix: &str, start: &str, end: &str) -> bool {
-    debug_assert_ne!(start, end);
+/// Whether the end of `prefix` lies inside a `//` or `/* */` comment.
+///
+/// The code is scanned from the start, so markers of one kind of comment
+/// inside the other, like `// see /* */`, are ignored.
+fn in_comment(prefix: &str) -> bool {
+    let bytes = prefix.as_bytes();
+    let mut line = false;
+    let mut block = false;
+    let mut i = 0;
+
+    while i < bytes.len() {
+        match (bytes[i], bytes.get(i + 1)) {
+            (b'\n', _) if line => line = false,
+            (b'*', Some(b'/')) if block => {
+                block = false;
+                i += 1;
+            }
+            (b'/', Some(b'/')) if !line && !block => line = true,
+            (b'/', Some(b'*')) if !line && !block => {
+                block = true;
+                i += 1;
+            }
+            _ => {}
+        }
+        i += 1;
+    }
 
-    let start_opt = prefix.rfind(start);
-    let end_opt = prefix.rfind(end);
+    line || block
+}
 
-    match (start_opt, end_opt) {
-        (Some(s), Some(e)) => s + start.len() > e,
-        (Some(_), None) => true,
-        (None, _) => false,
+/// Tracks which `#if` branches of a shader are active while it's preprocessed,
+/// so includes in disabled code aren't expanded.
+///
+/// Only `defined(NAME)` and integer literals are understood, branches with
+/// any other condition count as active.
+#[derive(Debug)]
+struct Conditionals {
+    defines: HashSet<String>,
+    stack: Vec<Branch>,
+}
+
+#[derive(Debug)]
+struct Branch {
+    /// Whether the enclosing branch is active
+    outer: bool,
+    active: bool,
+    /// Whether an earlier branch of this `#if` was taken, `None` if unknown
+    taken: Option<bool>,
+}
+
+impl Conditionals {
+    fn new() -> Self {
+        Self {
+            defines: std::iter::once("SH4DERJOCKEY".to_string()).collect(),
+            stack: Vec::new(),
+        }
+    }
+
+    fn active(&self) -> bool {
+        self.stack.last().map_or(true, |b| b.outer && b.active)
+    }
+
+    /// Evaluates a condition, `None` if it's too complex.
+    fn eval(&self, condition: &str) -> Option<bool> {
+        lazy_static! {
+            static ref DEFINED_RE: Regex =
+                Regex::new(r#"^(?P<not>!\s*)?defined\s*(\(\s*(?P<a>\w+)\s*\)|\s(?P<b>\w+))$"#)
+                    .expect("failed to compile regex");
+        }
+
+        let condition = condition.split("//").next().unwrap_or_default().trim();
+        if let Ok(n) = condition.parse::<i64>() {
+            return Some(n != 0);
+        }
+
+        let caps = DEFINED_RE.captures(condition)?;
+        let name = caps.name("a").or_else(|| caps.name("b"))?.as_str();
+        Some(self.defines.contains(name) != caps.name("not").is_some())
+    }
+
+    /// Updates the state with a line of code, other lines than conditional
+    /// directives are ignored.
+    fn update(&mut self, line: &str) {
+        lazy_static! {
+            static ref DIRECTIVE_RE: Regex = Regex::new(
+                r#"^\s*#\s*(?P<kind>ifdef|ifndef|if|elif|else|endif|define|undef)\b(?P<rest>.*)$"#
+            )
+            .expect("failed to compile regex");
+        }
+
+        let caps = match DIRECTIVE_RE.captures(line) {
+            Some(caps) => caps,
+            None => return,
+        };
+
+        let rest = caps["rest"].trim();
+        let name = rest
+            .split(|c: char| !c.is_alphanumeric() && c != '_')
+            .next();
+        let name = name.unwrap_or_default().to_string();
+        let active = self.active();
+
+        match &caps["kind"] {
+            "if" | "ifdef" | "ifndef" => {
+                let condition = match &caps["kind"] {
+                    "ifdef" => Some(self.defines.contains(&name)),
+                    "ifndef" => Some(!self.defines.contains(&name)),
+                    _ => self.eval(rest),
+                };
+
+                self.stack.push(Branch {
+                    outer: active,
+                    active: condition.unwrap_or(true),
+                    taken: condition,
+                });
+            }
+            "elif" => {
+                let condition = self.eval(rest);
+                if let Some(branch) = self.stack.last_mut() {
+                    match branch.taken {
+                        Some(true) => branch.active = false,
+                        Some(false) => {
+                            branch.active = condition.unwrap_or(true);
+                            branch.taken = condition;
+                        }
+                        None => branch.active = true,
+                    }
+                }
+            }
+            "else" => {
+                if let Some(branch) = self.stack.last_mut() {
+                    branch.active = branch.taken != Some(true);
+                }
+            }
+            "endif" => {
+                self.stack.pop();
+            }
+            "define" if active => {
+                self.defines.insert(name);
+            }
+            "undef" if active => {
+                self.defines.remove(&name);
+            }
+            _ => {}
+        }
     }
 }
 
@@ -276,6 +660,63 @@ pub fn process_error(mut err: String, lut: &[String]) -> String {
     err
 }
 
+/// Finds the outputs declared by a fragment shader in order of declaration,
+/// along with their location if the shader sets one with a layout qualifier.
+pub fn fragment_outputs(code: &str) -> Vec<(String, Option<GLuint>)> {
+    lazy_static! {
+        static ref OUT_RE: Regex = Regex::new(
+            r#"(?m)^\s*(layout\s*\(\s*location\s*=\s*(?P<loc>\d+)\s*\)\s*)?out\s+(\w+\s+)+(?P<name>\w+)\s*(\[[^\]]*\])?\s*;"#
+        ).expect("failed to compile regex");
+    }
+
+    let out_re: &Regex = &OUT_RE;
+    out_re
+        .captures_iter(code)
+        .map(|cap| {
+            let loc = cap.name("loc").and_then(|m| m.as_str().parse().ok());
+            (cap["name"].to_string(), loc)
+        })
+        .collect()
+}
+
+/// Finds the images declared by a shader along with their format qualifier,
+/// e.g. `rgba8` for `layout(rgba8, binding = 0) uniform image2D img;`.
+pub fn image_formats(code: &str) -> Vec<(String, String)> {
+    lazy_static! {
+        static ref IMAGE_RE: Regex = Regex::new(
+            r#"(?m)^\s*layout\s*\((?P<layout>[^)]*)\)\s*(\w+\s+)*[iu]?image[123]D\s+(?P<name>\w+)\s*;"#
+        ).expect("failed to compile regex");
+    }
+
+    let image_re: &Regex = &IMAGE_RE;
+    image_re
+        .captures_iter(code)
+        .filter_map(|cap| {
+            // the format is the only qualifier without a value
+            let format = cap["layout"]
+                .split(',')
+                .map(str::trim)
+                .find(|s| !s.contains('=') && !s.is_empty())?;
+            Some((cap["name"].to_string(), format.to_string()))
+        })
+        .collect()
+}
+
+/// Identifies a source file regardless of the path it was included with,
+/// built-in includes and files that can't be found keep their name.
+fn include_key(file_name: &str) -> String {
+    match std::fs::canonicalize(file_name) {
+        Ok(path) => path.to_string_lossy().into_owned(),
+        Err(_) => file_name.into(),
+    }
+}
+
+/// Name of the built-in include declaring the common uniform block
+pub const COMMON_BLOCK_INCLUDE: &str = "sh4derjockey.glsl";
+
+/// Source of the built-in include
+pub const COMMON_BLOCK_SOURCE: &str = include_str!("../jockey/shaders/sh4derjockey.glsl");
+
 pub fn preprocess(
     code: &str,
     file_name: &str,
@@ -299,6 +740,7 @@ pub fn preprocess(
         once_ignore: &mut HashSet<String>,
         lut: &mut Vec<String>,
         need_def: &mut bool,
+        conditionals: &mut Conditionals,
     ) -> Result<Vec<String>, String> {
         let mut lines = Vec::<String>::new();
         let mut need_ln = true;
@@ -317,20 +759,20 @@ pub fn preprocess(
         #[cfg(not(test))]
         let file_id = file_index_jank(file_id as u32);
 
+        // the same file may be included through different paths
+        let src_key = include_key(src_name);
+
         // respect pragma once
         let once_re: &Regex = &ONCE_RE;
         if let Some(once) = once_re.find(code) {
             let prefix = &code[..once.start()];
-            if !once_ignore.insert(src_name.into())
-                && !in_block(prefix, "//", "\n")
-                && !in_block(prefix, "/*", "*/")
-            {
+            if !once_ignore.insert(src_key.clone()) && !in_comment(prefix) {
                 return Ok(Vec::new());
             }
         }
 
         // detect include cycles
-        if !cycle_seen.insert(src_name.into()) {
+        if !cycle_seen.insert(src_key) {
             return Err(format!(
                 "Cycle detected! File {} has been included further down the tree",
                 src_name
@@ -339,8 +781,17 @@ pub fn preprocess(
 
         // process code line by line
         for (k, line) in code.lines().enumerate() {
+            // follow #if branches, unless the directive is commented out
+            if line.trim_start().starts_with('#') {
+                let offset = unsafe { line.as_ptr().offset_from(code.as_ptr()) };
+                if !in_comment(&code[..offset as usize]) {
+                    conditionals.update(line);
+                }
+            }
+
+            // includes in disabled branches are left to the compiler, which skips them
             let include_re: &Regex = &INCLUDE_RE;
-            if let Some(include) = include_re.find(line) {
+            if let Some(include) = include_re.find(line).filter(|_| conditionals.active()) {
                 let file_name = include_re
                     .captures(include.as_str())
                     .unwrap()
@@ -353,17 +804,23 @@ pub fn preprocess(
                 let prefix = &code[..offset as usize];
 
                 // check for comments
-                if !(in_block(prefix, "//", "\n") || in_block(prefix, "/*", "*/")) {
+                if !in_comment(prefix) {
                     // fetch file
                     #[cfg(not(test))]
-                    let file = match std::fs::read_to_string(file_name) {
-                        Ok(s) => s,
-                        Err(e) => return Err(format!("{}, {}", e.to_string(), file_name)),
+                    let file = match file_name {
+                        COMMON_BLOCK_INCLUDE => COMMON_BLOCK_SOURCE.to_string(),
+                        _ => match read_source(file_name) {
+                            Ok(s) => s.to_string(),
+                            Err(e) => return Err(format!("{}, {}", e.to_string(), file_name)),
+                        },
                     };
 
                     // dummy for unit tests
                     #[cfg(test)]
-                    let file = "#pragma once\nint hoge = 0;\n".to_string();
+                    let file = match file_name {
+                        COMMON_BLOCK_INCLUDE => COMMON_BLOCK_SOURCE.to_string(),
+                        _ => "#pragma once\nint hoge = 0;\n".to_string(),
+                    };
 
                     // recursively process file
                     let mut file_lines = recurse(
@@ -373,6 +830,7 @@ pub fn preprocess(
                         once_ignore,
                         lut,
                         need_def,
+                        conditionals,
                     )?;
                     lines.append(&mut file_lines);
 
@@ -410,11 +868,23 @@ pub fn preprocess(
         &mut HashSet::new(),
         file_name_lut,
         &mut true,
+        &mut Conditionals::new(),
     )?;
 
     Ok(lines.join("\n"))
 }
 
+/// Resolves a file path to an absolute one without symlinks, like
+/// `canonicalize`, but also for files that don't exist yet.
+pub fn canonical_file(path: &Path) -> Option<PathBuf> {
+    let parent = match path.parent() {
+        Some(p) if !p.as_os_str().is_empty() => p,
+        _ => Path::new("."),
+    };
+
+    Some(parent.canonicalize().ok()?.join(path.file_name()?))
+}
+
 pub fn interlace<T: Clone>(first: &[T], second: &[T], out: &mut [T]) {
     debug_assert_eq!(first.len() + second.len(), out.len());
 
@@ -441,30 +911,208 @@ pub fn deinterlace<T: Clone>(slice: &[T]) -> (Vec<T>, Vec<T>) {
     )
 }
 
+/// Coarsest modification time resolution of common file systems, e.g. FAT
+const MTIME_RESOLUTION: Duration = Duration::from_secs(2);
+
+thread_local! {
+    static SOURCES: RefCell<HashMap<PathBuf, (SystemTime, u64, Rc<str>)>> =
+        RefCell::new(HashMap::new());
+}
+
+/// Reads a shader source file, reusing the contents from earlier reads
+/// as long as the modification time and length of the file stay the same.
+///
+/// Headers included by many stages are only read from disk once this way.
+/// Files modified within the last two seconds are always read, since two
+/// saves in quick succession may leave the same modification time.
+pub fn read_source(path: &str) -> std::io::Result<Rc<str>> {
+    let path = PathBuf::from(path);
+    let metadata = std::fs::metadata(&path)?;
+    let (modified, len) = (metadata.modified()?, metadata.len());
+    let recent = modified
+        .elapsed()
+        .map_or(true, |age| age < MTIME_RESOLUTION);
+
+    let cached = SOURCES.with(|sources| match sources.borrow().get(&path) {
+        Some((time, size, source)) if !recent && *time == modified && *size == len => {
+            Some(Rc::clone(source))
+        }
+        _ => None,
+    });
+
+    if let Some(source) = cached {
+        return Ok(source);
+    }
+
+    let source: Rc<str> = std::fs::read_to_string(&path)?.into();
+    SOURCES.with(|sources| {
+        let entry = (modified, len, Rc::clone(&source));
+        sources.borrow_mut().insert(path, entry);
+    });
+
+    Ok(source)
+}
+
+/// Forgets all sources read so far, so the next reads hit the disk again.
+pub fn clear_source_cache() {
+    SOURCES.with(|sources| sources.borrow_mut().clear());
+}
+
+/// Opens a file with the default application of the operating system.
+pub fn open_file(path: &str) -> std::io::Result<()> {
+    let mut command = if cfg!(windows) {
+        let mut command = std::process::Command::new("cmd");
+        command.args(["/C", "start", ""]);
+        command
+    } else if cfg!(target_os = "macos") {
+        std::process::Command::new("open")
+    } else {
+        std::process::Command::new("xdg-open")
+    };
+
+    command.arg(path).spawn().map(|_| ())
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
 
     #[test]
-    fn in_block_simple() {
-        assert!(in_block("aa ( bb", "(", ")"));
-        assert!(in_block("( aa ) bb (", "(", ")"));
+    fn canonical_file_missing() {
+        let dir = std::env::temp_dir().canonicalize().unwrap();
+        let path = dir.join(".").join("sh4der-jockey-missing.log.old");
+        assert_eq!(
+            canonical_file(&path),
+            Some(dir.join("sh4der-jockey-missing.log.old"))
+        );
+    }
 
-        assert!(!in_block("( aa ( bb )", "(", ")"));
-        assert!(!in_block("aa bb", "(", ")"));
+    #[test]
+    fn read_source_cache() {
+        let path = std::env::temp_dir().join("sh4der-jockey-read-source.glsl");
+        let name = path.to_str().unwrap();
+
+        let write = |source: &str, modified: SystemTime| {
+            std::fs::write(&path, source).unwrap();
+            let file = std::fs::File::options().write(true).open(&path).unwrap();
+            file.set_modified(modified).unwrap();
+        };
+
+        let old = SystemTime::UNIX_EPOCH + Duration::from_secs(1000);
+        write("int a = 0;", old);
+        let first = read_source(name).unwrap();
+        let second = read_source(name).unwrap();
+        assert!(Rc::ptr_eq(&first, &second));
+
+        // a different modification time invalidates the cached source
+        write("int b = 1;", SystemTime::UNIX_EPOCH);
+        assert_eq!(&*read_source(name).unwrap(), "int b = 1;");
+
+        // so does a different length with the same modification time
+        write("int cc = 2;", SystemTime::UNIX_EPOCH);
+        assert_eq!(&*read_source(name).unwrap(), "int cc = 2;");
+
+        // recently modified files are read every time
+        write("int d = 3;", SystemTime::now());
+        let first = read_source(name).unwrap();
+        let second = read_source(name).unwrap();
+        assert!(!Rc::ptr_eq(&first, &second));
+
+        std::fs::remove_file(&path).ok();
     }
 
     #[test]
-    fn in_block_overlap() {
-        assert!(in_block("(x)", "(x", "x)"));
-        assert!(in_block("(xx)", "(xx", "xx)"));
-        assert!(in_block("(xx)", "(xx", "x)"));
-        assert!(in_block("(xx)", "(x", "xx)"));
-        assert!(in_block("(xxx)", "(xx", "xx)"));
+    fn fragment_outputs_simple() {
+        let code = "
+            #version 440
+            out vec4 out_color;
+            layout(location = 0) out vec4 albedo;
+            layout (location=2) out highp vec2 normal;
+            void helper(out float x);
+            void main() {}
+        ";
+
+        assert_eq!(
+            fragment_outputs(code),
+            vec![
+                ("out_color".to_string(), None),
+                ("albedo".to_string(), Some(0)),
+                ("normal".to_string(), Some(2)),
+            ]
+        );
+    }
+
+    #[test]
+    fn image_formats_simple() {
+        let code = "
+            #version 440
+            layout(rgba8, binding = 0) uniform image2D color;
+            layout (binding=1, r32ui) coherent uniform uimage2D counts;
+            layout(r32f) readonly uniform image3D volume;
+            writeonly uniform image2D unknown;
+            uniform sampler2D tex;
+            void main() {}
+        ";
+
+        assert_eq!(
+            image_formats(code),
+            vec![
+                ("color".to_string(), "rgba8".to_string()),
+                ("counts".to_string(), "r32ui".to_string()),
+                ("volume".to_string(), "r32f".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn include_key_canonical() {
+        let dir = std::env::temp_dir().join("sh4der-jockey-include-key");
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("common.glsl"), "#pragma once\n").unwrap();
+
+        let direct = dir.join("common.glsl");
+        let detour = dir
+            .join(".")
+            .join("..")
+            .join("sh4der-jockey-include-key")
+            .join("common.glsl");
+        assert_eq!(
+            include_key(direct.to_str().unwrap()),
+            include_key(detour.to_str().unwrap())
+        );
 
-        assert!(!in_block("(xx)", "(x", "x)"));
-        assert!(!in_block("(xxx)", "(xx", "x)"));
-        assert!(!in_block("(xxx)", "(x", "xx)"));
+        // missing files are told apart by name
+        assert_eq!(include_key("missing.glsl"), "missing.glsl");
+
+        std::fs::remove_dir_all(&dir).ok();
+    }
+
+    #[test]
+    fn strip_indices_restart() {
+        const R: GLuint = PRIMITIVE_RESTART_INDEX;
+        assert_eq!(strip_indices(7, 3), vec![0, 1, 2, R, 3, 4, 5, R, 6]);
+        assert_eq!(strip_indices(4, 4), vec![0, 1, 2, 3]);
+        assert_eq!(strip_indices(0, 3), vec![]);
+    }
+
+    #[test]
+    fn in_comment_simple() {
+        assert!(in_comment("a // b"));
+        assert!(in_comment("a /* b"));
+        assert!(in_comment("a /* b \n c"));
+
+        assert!(!in_comment("a // b\n c"));
+        assert!(!in_comment("a /* b */ c"));
+        assert!(!in_comment("a b"));
+    }
+
+    #[test]
+    fn in_comment_nested_markers() {
+        assert!(!in_comment("// see /* here\n"));
+        assert!(!in_comment("/* // */ a"));
+        assert!(in_comment("/* a\n // b */ c /* d"));
+        assert!(in_comment("/*/ a"));
+        assert!(!in_comment("/**/ a"));
     }
 
     #[test]
@@ -523,6 +1171,15 @@ mod test {
         assert_eq!(result, expected);
     }
 
+    #[test]
+    fn preprocess_include_builtin() {
+        let original = "#version 123\n#include <sh4derjockey.glsl>\nmain(){}";
+        let mut lut = Vec::new();
+        let result = preprocess(original, "test", &mut lut).unwrap();
+        assert!(result.contains("uniform Sh4derJockey {"), "{}", result);
+        assert_eq!(lut, ["test", COMMON_BLOCK_INCLUDE]);
+    }
+
     #[test]
     fn preprocess_include_in_comment_single() {
         let original = "#version 123\n//#pragma include \"foo.glsl\"\nmain(){}";
@@ -541,6 +1198,63 @@ mod test {
         assert_eq!(result, expected);
     }
 
+    #[test]
+    fn preprocess_include_in_disabled_branch() {
+        let original = "#version 123\n#if 0\n#pragma include \"foo.glsl\"\n#endif\nmain(){}";
+        let expected = "#version 123\n#define SH4DERJOCKEY 1\n#line 2 0\n#if 0\n#pragma include \"foo.glsl\"\n#endif\nmain(){}";
+        let mut lut = Vec::new();
+        let result = preprocess(original, "test", &mut lut).unwrap();
+        assert_eq!(result, expected);
+    }
+
+    #[test]
+    fn preprocess_include_in_enabled_branch() {
+        let original = "#version 123\n#define FOO\n#ifndef FOO\n#include \"a.glsl\"\n#elif defined(SH4DERJOCKEY)\n#include \"b.glsl\"\n#endif\nmain(){}";
+        let mut lut = Vec::new();
+        let result = preprocess(original, "test", &mut lut).unwrap();
+        assert_eq!(result.matches("int hoge = 0;").count(), 1, "{}", result);
+        assert_eq!(lut, ["test", "b.glsl"]);
+    }
+
+    #[test]
+    fn conditionals_nested() {
+        let mut cond = Conditionals::new();
+        for line in ["#ifdef SH4DERJOCKEY", "#if 0", "#define BAR", "#else"] {
+            cond.update(line);
+        }
+        assert!(cond.active());
+        assert!(!cond.defines.contains("BAR"));
+
+        // unknown conditions count as active, and so does their #else
+        cond.update("#if FOO > 2");
+        assert!(cond.active());
+        cond.update("#else");
+        assert!(cond.active());
+
+        for line in ["#endif", "#endif", "#endif"] {
+            cond.update(line);
+        }
+        assert!(cond.stack.is_empty());
+    }
+
+    #[test]
+    fn preprocess_include_in_comment_multiline() {
+        let original = "#version 123\n/*\n#pragma include \"foo.glsl\"\n*/\nmain(){}";
+        let mut lut = Vec::new();
+        let result = preprocess(original, "test", &mut lut).unwrap();
+        assert!(!result.contains("int hoge = 0;"), "{}", result);
+        assert_eq!(lut, ["test"]);
+    }
+
+    #[test]
+    fn preprocess_include_after_comment_markers() {
+        let original = "#version 123\n// old /* notes\n#pragma include \"foo.glsl\"\nmain(){}";
+        let mut lut = Vec::new();
+        let result = preprocess(original, "test", &mut lut).unwrap();
+        assert!(result.contains("int hoge = 0;"), "{}", result);
+        assert_eq!(lut, ["test", "foo.glsl"]);
+    }
+
     #[test]
     fn preprocess_include_pragma_once() {
         let original =
@@ -552,6 +1266,184 @@ mod test {
     }
 }
 
+/// Strings identifying the OpenGL driver, handy for bug reports.
+#[derive(Debug, Clone, Default)]
+pub struct GlInfo {
+    pub version: String,
+    pub renderer: String,
+    pub vendor: String,
+    pub glsl_version: String,
+    pub max_texture_units: usize,
+    /// Zero if tessellation isn't supported
+    pub max_patch_vertices: usize,
+}
+
+impl GlInfo {
+    /// Queries the driver of the current context.
+    pub fn query() -> Self {
+        let get = |name| unsafe {
+            let ptr = gl::GetString(name);
+            if ptr.is_null() {
+                return String::from("unknown");
+            }
+
+            CStr::from_ptr(ptr as _).to_string_lossy().into_owned()
+        };
+
+        Self {
+            version: get(gl::VERSION),
+            renderer: get(gl::RENDERER),
+            vendor: get(gl::VENDOR),
+            glsl_version: get(gl::SHADING_LANGUAGE_VERSION),
+            max_texture_units: max_texture_units(),
+            max_patch_vertices: max_patch_vertices(),
+        }
+    }
+}
+
+/// Number of textures a shader can sample from at once.
+pub fn max_texture_units() -> usize {
+    let mut units = 0;
+    unsafe {
+        gl::GetIntegerv(gl::MAX_TEXTURE_IMAGE_UNITS, &mut units);
+        gl_debug_check!();
+    }
+
+    units.max(0) as _
+}
+
+/// Number of vertices a tessellation patch can have at most, zero if the
+/// context doesn't support tessellation.
+pub fn max_patch_vertices() -> usize {
+    let mut count = 0;
+    unsafe {
+        // contexts before OpenGL 4.0 don't know the enum
+        gl::GetIntegerv(gl::MAX_PATCH_VERTICES, &mut count);
+        gl_ignore!();
+    }
+
+    count.max(0) as _
+}
+
+/// Highest degree of anisotropic filtering, zero if the context doesn't
+/// support it.
+pub fn max_anisotropy() -> f32 {
+    let mut max = 0.0;
+    unsafe {
+        // core since OpenGL 4.6, older drivers only know the extension
+        gl::GetFloatv(gl::MAX_TEXTURE_MAX_ANISOTROPY, &mut max);
+        gl_ignore!();
+    }
+
+    max
+}
+
+/// Sets the degree of anisotropic filtering of a texture, clamped to what
+/// the context supports. Does nothing without support for it.
+pub fn set_anisotropy(target: GLenum, tex_id: GLuint, amount: f32) {
+    let max = max_anisotropy();
+    if max < 1.0 {
+        return;
+    }
+
+    unsafe {
+        gl::BindTexture(target, tex_id);
+        gl::TexParameterf(target, gl::TEXTURE_MAX_ANISOTROPY, amount.min(max));
+        gl::BindTexture(target, 0);
+        gl_debug_check!();
+    }
+}
+
+/// Routes the messages of the driver for the current context to the log.
+///
+/// Returns false if the driver doesn't support `KHR_debug`.
+pub fn enable_debug_output() -> bool {
+    // the function pointer is not enough, some platforms resolve any name
+    if !gl::DebugMessageCallback::is_loaded() || !supports_debug_output() {
+        return false;
+    }
+
+    unsafe {
+        gl::Enable(gl::DEBUG_OUTPUT);
+        gl::Enable(gl::DEBUG_OUTPUT_SYNCHRONOUS);
+        gl::DebugMessageCallback(Some(log_debug_message), std::ptr::null());
+        gl_debug_check!();
+    }
+
+    true
+}
+
+/// Whether the current context has debug output, either through OpenGL 4.3
+/// or the `KHR_debug` extension.
+fn supports_debug_output() -> bool {
+    let (mut major, mut minor, mut count) = (0, 0, 0);
+    unsafe {
+        gl::GetIntegerv(gl::MAJOR_VERSION, &mut major);
+        gl::GetIntegerv(gl::MINOR_VERSION, &mut minor);
+        gl::GetIntegerv(gl::NUM_EXTENSIONS, &mut count);
+        gl_debug_check!();
+    }
+
+    if (major, minor) >= (4, 3) {
+        return true;
+    }
+
+    (0..count).any(|k| unsafe {
+        let ptr = gl::GetStringi(gl::EXTENSIONS, k as _);
+        !ptr.is_null() && CStr::from_ptr(ptr as _).to_bytes() == b"KHR_debug"
+    })
+}
+
+extern "system" fn log_debug_message(
+    source: GLenum,
+    type_: GLenum,
+    id: GLuint,
+    severity: GLenum,
+    length: GLsizei,
+    message: *const GLchar,
+    _user_param: *mut c_void,
+) {
+    let message = unsafe {
+        let bytes = std::slice::from_raw_parts(message as *const u8, length.max(0) as _);
+        String::from_utf8_lossy(bytes)
+    };
+
+    let source = match source {
+        gl::DEBUG_SOURCE_API => "api",
+        gl::DEBUG_SOURCE_WINDOW_SYSTEM => "window system",
+        gl::DEBUG_SOURCE_SHADER_COMPILER => "shader compiler",
+        gl::DEBUG_SOURCE_THIRD_PARTY => "third party",
+        gl::DEBUG_SOURCE_APPLICATION => "application",
+        _ => "other",
+    };
+
+    let type_ = match type_ {
+        gl::DEBUG_TYPE_ERROR => "error",
+        gl::DEBUG_TYPE_DEPRECATED_BEHAVIOR => "deprecated behavior",
+        gl::DEBUG_TYPE_UNDEFINED_BEHAVIOR => "undefined behavior",
+        gl::DEBUG_TYPE_PORTABILITY => "portability",
+        gl::DEBUG_TYPE_PERFORMANCE => "performance",
+        gl::DEBUG_TYPE_MARKER => "marker",
+        _ => "other",
+    };
+
+    let level = match severity {
+        gl::DEBUG_SEVERITY_HIGH => log::Level::Error,
+        gl::DEBUG_SEVERITY_MEDIUM => log::Level::Warn,
+        gl::DEBUG_SEVERITY_LOW => log::Level::Info,
+        _ => log::Level::Debug,
+    };
+
+    log::log!(
+        level,
+        "OpenGL {} ({}, {}): {}",
+        type_,
+        source,
+        id,
+        message.trim_end()
+    );
+}
+
 #[allow(dead_code)]
 pub fn test_compute_capabilities() {
     unsafe {
@@ -0,0 +1,59 @@
+use gl::types::*;
+
+use crate::*;
+
+/// A shader storage buffer object.
+///
+/// Unlike textures, storage buffers hold arbitrary data, like particle
+/// positions, which is read and written by shaders through a buffer block.
+#[derive(Debug)]
+pub struct StorageBuffer {
+    pub id: GLuint,
+    pub size: usize,
+    /// Whether the contents survive a rebuild of the pipeline
+    pub persistent: bool,
+}
+
+impl StorageBuffer {
+    /// Creates a new storage buffer of the given size in bytes.
+    ///
+    /// The contents of the buffer are initialized with zeros.
+    pub fn new(size: usize) -> Self {
+        let data = vec![0_u8; size];
+
+        unsafe {
+            let mut id = 0;
+            gl::GenBuffers(1, &mut id);
+            gl::BindBuffer(gl::SHADER_STORAGE_BUFFER, id);
+            gl::BufferData(
+                gl::SHADER_STORAGE_BUFFER,
+                size as _,
+                data.as_ptr() as _,
+                gl::DYNAMIC_COPY,
+            );
+            gl::BindBuffer(gl::SHADER_STORAGE_BUFFER, 0);
+            gl_debug_check!();
+
+            Self {
+                id,
+                size,
+                persistent: false,
+            }
+        }
+    }
+
+    pub fn bind(&self, binding_unit: u32) {
+        unsafe {
+            gl::BindBufferBase(gl::SHADER_STORAGE_BUFFER, binding_unit, self.id);
+            gl_debug_check!();
+        }
+    }
+}
+
+impl Drop for StorageBuffer {
+    fn drop(&mut self) {
+        unsafe {
+            gl::DeleteBuffers(1, &self.id);
+        }
+    }
+}
@@ -1,5 +1,10 @@
 #![allow(dead_code)]
-use std::{cell::RefCell, fmt::Debug, rc::Rc, u8};
+use std::{
+    cell::{Cell, RefCell},
+    fmt::Debug,
+    rc::Rc,
+    u8,
+};
 
 use as_any::AsAny;
 use image::DynamicImage;
@@ -14,7 +19,107 @@ pub trait Texture: Debug + AsAny {
     fn resolution(&self) -> [u32; 3];
     fn texture_id(&self) -> GLuint;
     fn framebuffer_id(&self) -> Option<GLuint>;
+    fn format(&self) -> TextureFormat;
     fn swap(&self) {}
+
+    /// Current magnification filter, if it can be changed at runtime
+    fn filter(&self) -> Option<GLenum> {
+        None
+    }
+
+    /// Switches between `LINEAR` and `NEAREST` filtering, mipmaps are kept.
+    fn set_filter(&self, _filter: GLenum) {}
+
+    /// Overwrites a 2D texture with RGBA pixels of its full size, which are
+    /// converted to the format of the texture. Integer textures are skipped.
+    fn fill_rgba8(&self, _pixels: &[u8]) {}
+
+    /// Clears the texture to a color. Only render targets with a float or
+    /// normalized format support this.
+    fn clear(&self, _color: [f32; 4]) {}
+
+    /// Format the texture is bound with as an image, `None` if it can only be
+    /// sampled.
+    fn image_format(&self) -> Option<TextureFormat> {
+        None
+    }
+}
+
+/// Uploads RGBA pixels to level 0 of a 2D texture and regenerates its mipmaps.
+fn fill_texture_2d(tex_id: GLuint, [width, height]: [u32; 2], pixels: &[u8], mipmap: bool) {
+    debug_assert_eq!(pixels.len(), 4 * width as usize * height as usize);
+    unsafe {
+        gl::BindTexture(gl::TEXTURE_2D, tex_id);
+        gl::PixelStorei(gl::UNPACK_ALIGNMENT, 1);
+        gl::TexSubImage2D(
+            gl::TEXTURE_2D,
+            0,
+            0,
+            0,
+            width as _,
+            height as _,
+            gl::RGBA,
+            gl::UNSIGNED_BYTE,
+            pixels.as_ptr() as _,
+        );
+        gl_debug_check!();
+
+        if mipmap {
+            gl::GenerateMipmap(gl::TEXTURE_2D);
+            gl_debug_check!();
+        }
+    }
+}
+
+/// Minification filter matching a magnification filter
+fn min_filter_for(filter: GLenum, mipmap: bool) -> GLenum {
+    match (filter, mipmap) {
+        (gl::LINEAR, true) => gl::LINEAR_MIPMAP_LINEAR,
+        (gl::NEAREST, true) => gl::NEAREST_MIPMAP_NEAREST,
+        (filter, _) => filter,
+    }
+}
+
+/// Number of levels of a full mip chain, from the given size down to 1x1.
+pub fn mip_level_count(width: u32, height: u32) -> u32 {
+    32 - width.max(height).max(1).leading_zeros()
+}
+
+/// A combined depth and stencil renderbuffer.
+///
+/// This is reference counted, so the front and back buffer of a
+/// `DoubleFrameBuffer` can share the same stencil values.
+#[derive(Debug)]
+pub struct StencilBuffer {
+    pub rb_id: GLuint,
+}
+
+impl StencilBuffer {
+    pub fn new(width: u32, height: u32) -> Self {
+        unsafe {
+            let mut rb_id = 0;
+            gl::GenRenderbuffers(1, &mut rb_id);
+            gl::BindRenderbuffer(gl::RENDERBUFFER, rb_id);
+            gl::RenderbufferStorage(
+                gl::RENDERBUFFER,
+                gl::DEPTH24_STENCIL8,
+                width.max(1) as _,
+                height.max(1) as _,
+            );
+            gl::BindRenderbuffer(gl::RENDERBUFFER, 0);
+            gl_debug_check!();
+
+            Self { rb_id }
+        }
+    }
+}
+
+impl Drop for StencilBuffer {
+    fn drop(&mut self) {
+        unsafe {
+            gl::DeleteRenderbuffers(1, &self.rb_id);
+        }
+    }
 }
 
 #[derive(Debug)]
@@ -22,6 +127,10 @@ pub struct FrameBuffer {
     pub tex_id: GLuint,
     pub fb_id: GLuint,
     res: [u32; 2],
+    stencil: Option<Rc<StencilBuffer>>,
+    format: TextureFormat,
+    filter: Cell<GLenum>,
+    mipmap: bool,
 }
 
 impl Texture for FrameBuffer {
@@ -40,9 +149,61 @@ impl Texture for FrameBuffer {
         self.tex_id
     }
 
+    fn format(&self) -> TextureFormat {
+        self.format
+    }
+
     fn framebuffer_id(&self) -> Option<GLuint> {
         Some(self.fb_id)
     }
+
+    fn filter(&self) -> Option<GLenum> {
+        match self.format.is_integer() {
+            true => None,
+            false => Some(self.filter.get()),
+        }
+    }
+
+    fn set_filter(&self, filter: GLenum) {
+        if self.format.is_integer() {
+            return;
+        }
+
+        unsafe {
+            gl::BindTexture(gl::TEXTURE_2D, self.tex_id);
+            let min_filter = min_filter_for(filter, self.mipmap);
+            gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MIN_FILTER, min_filter as _);
+            gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MAG_FILTER, filter as _);
+            gl_debug_check!();
+        }
+
+        self.filter.set(filter);
+    }
+
+    fn fill_rgba8(&self, pixels: &[u8]) {
+        if !self.format.is_integer() {
+            fill_texture_2d(self.tex_id, self.res, pixels, self.mipmap);
+        }
+    }
+
+    fn clear(&self, [r, g, b, a]: [f32; 4]) {
+        if self.format.is_integer() {
+            return;
+        }
+
+        unsafe {
+            gl::BindFramebuffer(gl::FRAMEBUFFER, self.fb_id);
+            gl::ClearColor(r, g, b, a);
+            gl::Clear(gl::COLOR_BUFFER_BIT);
+            gl::BindFramebuffer(gl::FRAMEBUFFER, 0);
+
+            if self.mipmap {
+                gl::BindTexture(gl::TEXTURE_2D, self.tex_id);
+                gl::GenerateMipmap(gl::TEXTURE_2D);
+            }
+            gl_debug_check!();
+        }
+    }
 }
 
 impl FrameBuffer {
@@ -54,7 +215,7 @@ impl FrameBuffer {
             gl::NEAREST,
             gl::CLAMP_TO_EDGE,
             false,
-            false,
+            TextureFormat::RGBA8,
         )
     }
 
@@ -65,7 +226,7 @@ impl FrameBuffer {
         mag_filter: GLenum,
         wrap_mode: GLenum,
         mipmap: bool,
-        float: bool,
+        format: TextureFormat,
     ) -> Self {
         let width = width.max(1);
         let height = height.max(1);
@@ -91,10 +252,7 @@ impl FrameBuffer {
             gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_WRAP_T, wrap_mode as _);
             gl_debug_check!();
 
-            let (internal_format, type_) = match float {
-                true => (gl::RGBA32F, gl::FLOAT),
-                false => (gl::RGBA8, gl::UNSIGNED_BYTE),
-            };
+            let (internal_format, color_format, type_) = Texture2D::get_formats(format);
 
             gl::TexImage2D(
                 gl::TEXTURE_2D,
@@ -103,7 +261,7 @@ impl FrameBuffer {
                 width as _,
                 height as _,
                 0,
-                gl::RGBA,
+                color_format,
                 type_,
                 std::ptr::null(),
             );
@@ -131,9 +289,49 @@ impl FrameBuffer {
                 tex_id,
                 fb_id,
                 res: [width, height],
+                stencil: None,
+                format,
+                filter: Cell::new(mag_filter),
+                mipmap,
             }
         }
     }
+
+    /// Limits the mip chain to the given number of levels, including level 0.
+    ///
+    /// `GenerateMipmap` stops at the last level, the smaller ones are never used.
+    pub fn set_mip_levels(&self, levels: u32) {
+        unsafe {
+            gl::BindTexture(gl::TEXTURE_2D, self.tex_id);
+            gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MAX_LEVEL, levels as GLint - 1);
+            gl::BindTexture(gl::TEXTURE_2D, 0);
+            gl_debug_check!();
+        }
+    }
+
+    pub fn set_anisotropy(&self, amount: f32) {
+        set_anisotropy(gl::TEXTURE_2D, self.tex_id, amount);
+    }
+
+    pub fn attach_stencil(&mut self, stencil: Rc<StencilBuffer>) {
+        unsafe {
+            gl::BindFramebuffer(gl::FRAMEBUFFER, self.fb_id);
+            gl::FramebufferRenderbuffer(
+                gl::FRAMEBUFFER,
+                gl::DEPTH_STENCIL_ATTACHMENT,
+                gl::RENDERBUFFER,
+                stencil.rb_id,
+            );
+
+            gl_debug_check!();
+            debug_assert_eq!(
+                gl::CheckFramebufferStatus(gl::FRAMEBUFFER),
+                gl::FRAMEBUFFER_COMPLETE
+            );
+        }
+
+        self.stencil = Some(stencil);
+    }
 }
 
 impl Drop for FrameBuffer {
@@ -164,6 +362,10 @@ impl Texture for DoubleFrameBuffer {
         self.back.borrow().texture_id()
     }
 
+    fn format(&self) -> TextureFormat {
+        self.back.borrow().format()
+    }
+
     fn framebuffer_id(&self) -> Option<GLuint> {
         self.back.borrow().framebuffer_id()
     }
@@ -171,6 +373,25 @@ impl Texture for DoubleFrameBuffer {
     fn swap(&self) {
         self.front.swap(&self.back)
     }
+
+    fn filter(&self) -> Option<GLenum> {
+        self.front.borrow().filter()
+    }
+
+    fn fill_rgba8(&self, pixels: &[u8]) {
+        self.front.borrow().fill_rgba8(pixels);
+        self.back.borrow().fill_rgba8(pixels);
+    }
+
+    fn clear(&self, color: [f32; 4]) {
+        self.front.borrow().clear(color);
+        self.back.borrow().clear(color);
+    }
+
+    fn set_filter(&self, filter: GLenum) {
+        self.front.borrow().set_filter(filter);
+        self.back.borrow().set_filter(filter);
+    }
 }
 
 impl DoubleFrameBuffer {
@@ -182,7 +403,7 @@ impl DoubleFrameBuffer {
             gl::NEAREST,
             gl::CLAMP_TO_EDGE,
             false,
-            false,
+            TextureFormat::RGBA8,
         )
     }
 
@@ -193,17 +414,34 @@ impl DoubleFrameBuffer {
         mag_filter: GLenum,
         wrap_mode: GLenum,
         mipmap: bool,
-        float: bool,
+        format: TextureFormat,
     ) -> Self {
         Self {
             front: RefCell::new(FrameBuffer::with_params(
-                width, height, min_filter, mag_filter, wrap_mode, mipmap, float,
+                width, height, min_filter, mag_filter, wrap_mode, mipmap, format,
             )),
             back: RefCell::new(FrameBuffer::with_params(
-                width, height, min_filter, mag_filter, wrap_mode, mipmap, float,
+                width, height, min_filter, mag_filter, wrap_mode, mipmap, format,
             )),
         }
     }
+
+    pub fn set_mip_levels(&self, levels: u32) {
+        self.front.borrow().set_mip_levels(levels);
+        self.back.borrow().set_mip_levels(levels);
+    }
+
+    pub fn set_anisotropy(&self, amount: f32) {
+        self.front.borrow().set_anisotropy(amount);
+        self.back.borrow().set_anisotropy(amount);
+    }
+
+    pub fn attach_stencil(&mut self) {
+        let [width, height, _] = self.resolution();
+        let stencil = Rc::new(StencilBuffer::new(width, height));
+        self.front.get_mut().attach_stencil(stencil.clone());
+        self.back.get_mut().attach_stencil(stencil);
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -215,6 +453,12 @@ pub struct TextureBuilder {
     pub channels: u8,
     pub float: bool,
     pub mipmap: bool,
+    /// Number of mip levels of a render target, all down to 1x1 if not set
+    pub mip_levels: Option<u32>,
+    pub stencil: bool,
+    pub format: Option<TextureFormat>,
+    /// Degree of anisotropic filtering, off if not set
+    pub anisotropy: Option<f32>,
 }
 
 impl TextureBuilder {
@@ -227,6 +471,10 @@ impl TextureBuilder {
             channels: 4,
             float: false,
             mipmap: false,
+            mip_levels: None,
+            stencil: false,
+            format: None,
+            anisotropy: None,
         }
     }
 
@@ -284,6 +532,23 @@ impl TextureBuilder {
             Some(s) => return Err(format!("Expected \"mipmap\" to be a bool, got {:?}", s)),
         };
 
+        // get number of mip levels, which implies mipmapping
+        let mip_levels = match object
+            .get("mip_levels")
+            .map(Value::as_u64)
+            .filter(|_| support_mipmap && support_res)
+        {
+            Some(Some(n)) if n > 0 => Some(n as u32),
+            None => None,
+            Some(_) => {
+                return Err(format!(
+                    "Expected \"mip_levels\" to be a positive integer, got {:?}",
+                    object.get("mip_levels")
+                ))
+            }
+        };
+        let mipmap = mipmap || mip_levels.is_some();
+
         // get texture filtering mode
         let wrap_mode = match object
             .get("wrap_mode")
@@ -301,8 +566,35 @@ impl TextureBuilder {
             }
         };
 
+        // get explicit texture format
+        let format = match object.get("format").map(Value::as_str) {
+            Some(Some(name)) => match TextureFormat::from_name(name) {
+                Some(format) => Some(format),
+                None => {
+                    return Err(format!(
+                        "Expected \"format\" to be one of r8, rg8, rgba8, r16f, rg16f, rgba16f, rg11b10f, r32f, rg32f, rgba32f, r32ui, rg32ui or rgba32ui, got {:?}",
+                        name
+                    ))
+                }
+            },
+            None => None,
+            Some(None) => {
+                return Err(format!(
+                    "Expected \"format\" to be a string, got {:?}",
+                    object.get("format")
+                ))
+            }
+        };
+
+        // integer textures can't be filtered
+        let integer = format.map_or(false, TextureFormat::is_integer);
+
         // get texture filtering mode
         let mag_filter = match object.get("filter").map(Value::as_str) {
+            None if integer => gl::NEAREST,
+            Some(Some("linear")) if integer => {
+                return Err("Integer formats do not support \"linear\" filtering".into())
+            }
             Some(Some("linear")) | None => gl::LINEAR,
             Some(Some("nearest")) => gl::NEAREST,
             Some(s) => {
@@ -320,6 +612,18 @@ impl TextureBuilder {
             _ => unreachable!(),
         };
 
+        // sharpens textures seen at grazing angles, capped by the hardware
+        let anisotropy = match object.get("anisotropy").map(Value::as_f64) {
+            Some(Some(n)) if n >= 1.0 => Some(n as f32),
+            None => None,
+            Some(_) => {
+                return Err(format!(
+                    "Expected \"anisotropy\" to be a number of at least 1, got {:?}",
+                    object.get("anisotropy")
+                ))
+            }
+        };
+
         // get float format flag
         let float = match object.get("float").map(Value::as_bool) {
             Some(Some(flag)) => flag,
@@ -335,6 +639,10 @@ impl TextureBuilder {
             channels: 4,
             float,
             mipmap,
+            mip_levels,
+            stencil: false,
+            format,
+            anisotropy,
         })
     }
 
@@ -353,6 +661,16 @@ impl TextureBuilder {
         self
     }
 
+    pub fn set_mipmap(&mut self, mipmap: bool) -> &mut Self {
+        self.min_filter = match (self.mag_filter, mipmap) {
+            (gl::LINEAR, true) => gl::LINEAR_MIPMAP_LINEAR,
+            (gl::NEAREST, true) => gl::NEAREST_MIPMAP_NEAREST,
+            (filter, _) => filter,
+        };
+        self.mipmap = mipmap;
+        self
+    }
+
     pub fn build_framebuffer(&self, screen_size: (u32, u32)) -> Rc<FrameBuffer> {
         let [width, height] = match self.resolution.as_slice() {
             &[w, h] => [w, h],
@@ -360,15 +678,29 @@ impl TextureBuilder {
             _ => unreachable!(),
         };
 
-        Rc::new(FrameBuffer::with_params(
+        let mut fb = FrameBuffer::with_params(
             width,
             height,
             self.min_filter,
             self.mag_filter,
             self.wrap_mode,
             self.mipmap,
-            self.float,
-        ))
+            self.texture_format(),
+        );
+
+        if self.stencil {
+            fb.attach_stencil(Rc::new(StencilBuffer::new(width, height)));
+        }
+
+        if let Some(levels) = self.mip_levels.filter(|_| self.mipmap) {
+            fb.set_mip_levels(levels);
+        }
+
+        if let Some(amount) = self.anisotropy {
+            fb.set_anisotropy(amount);
+        }
+
+        Rc::new(fb)
     }
 
     pub fn build_double_framebuffer(&self, screen_size: (u32, u32)) -> Rc<DoubleFrameBuffer> {
@@ -378,18 +710,36 @@ impl TextureBuilder {
             _ => unreachable!(),
         };
 
-        Rc::new(DoubleFrameBuffer::with_params(
+        let mut fb = DoubleFrameBuffer::with_params(
             width,
             height,
             self.min_filter,
             self.mag_filter,
             self.wrap_mode,
             self.mipmap,
-            self.float,
-        ))
+            self.texture_format(),
+        );
+
+        if self.stencil {
+            fb.attach_stencil();
+        }
+
+        if let Some(levels) = self.mip_levels.filter(|_| self.mipmap) {
+            fb.set_mip_levels(levels);
+        }
+
+        if let Some(amount) = self.anisotropy {
+            fb.set_anisotropy(amount);
+        }
+
+        Rc::new(fb)
     }
 
-    fn texture_format(&self) -> TextureFormat {
+    pub fn texture_format(&self) -> TextureFormat {
+        if let Some(format) = self.format {
+            return format;
+        }
+
         match (self.channels, self.float) {
             (1, false) => TextureFormat::R8,
             (2, false) => TextureFormat::RG8,
@@ -413,7 +763,7 @@ impl TextureBuilder {
 
     pub fn build_texture_with_data(&self, data: *const c_void) -> Rc<dyn Texture> {
         let format = self.texture_format();
-        match self.resolution.as_slice() {
+        let tex: Rc<dyn Texture> = match self.resolution.as_slice() {
             &[w] => Rc::new(Texture1D::with_params(
                 [w],
                 self.min_filter,
@@ -442,7 +792,15 @@ impl TextureBuilder {
                 data,
             )),
             _ => unreachable!(),
+        };
+
+        if let Some(amount) = self.anisotropy {
+            let target =
+                [gl::TEXTURE_1D, gl::TEXTURE_2D, gl::TEXTURE_3D][self.resolution.len() - 1];
+            set_anisotropy(target, tex.texture_id(), amount);
         }
+
+        tex
     }
 
     pub fn build_image_with_data(&self, data: *const c_void) -> Rc<dyn Texture> {
@@ -490,7 +848,7 @@ pub enum TextureKind {
     Texture3D { res: [u32; 3] },
 }
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum TextureFormat {
     R8 = gl::R8 as _,
     RG8 = gl::RG8 as _,
@@ -500,6 +858,80 @@ pub enum TextureFormat {
     RG32F = gl::RG32F as _,
     RGB32F = gl::RGB32F as _,
     RGBA32F = gl::RGBA32F as _,
+    R16F = gl::R16F as _,
+    RG16F = gl::RG16F as _,
+    RGBA16F = gl::RGBA16F as _,
+    RG11FB10F = gl::R11F_G11F_B10F as _,
+    R32UI = gl::R32UI as _,
+    RG32UI = gl::RG32UI as _,
+    RGBA32UI = gl::RGBA32UI as _,
+}
+
+impl TextureFormat {
+    /// Looks up a format by the name of its GLSL image format qualifier.
+    ///
+    /// Apart from `rg11b10f`, which is short for `r11f_g11f_b10f`, only
+    /// formats which can be bound as an image are supported.
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "r8" => Some(Self::R8),
+            "rg8" => Some(Self::RG8),
+            "rgba8" => Some(Self::RGBA8),
+            "r32f" => Some(Self::R32F),
+            "rg32f" => Some(Self::RG32F),
+            "rgba32f" => Some(Self::RGBA32F),
+            "r16f" => Some(Self::R16F),
+            "rg16f" => Some(Self::RG16F),
+            "rgba16f" => Some(Self::RGBA16F),
+            "rg11b10f" => Some(Self::RG11FB10F),
+            "r32ui" => Some(Self::R32UI),
+            "rg32ui" => Some(Self::RG32UI),
+            "rgba32ui" => Some(Self::RGBA32UI),
+            _ => None,
+        }
+    }
+
+    pub fn is_integer(self) -> bool {
+        matches!(self, Self::R32UI | Self::RG32UI | Self::RGBA32UI)
+    }
+
+    /// Whether compute shaders can write to textures of this format.
+    ///
+    /// Image load and store has no formats with three channels.
+    pub fn is_image_compatible(self) -> bool {
+        !matches!(self, Self::RGB8 | Self::RGB32F)
+    }
+
+    /// Looks up a format by its GLSL image format qualifier.
+    pub fn from_qualifier(qualifier: &str) -> Option<Self> {
+        match qualifier {
+            "r11f_g11f_b10f" => Some(Self::RG11FB10F),
+            "rg11b10f" => None,
+            name => Self::from_name(name),
+        }
+    }
+
+    /// The GLSL image format qualifier of this format, formats which can't be
+    /// bound as an image get a name in the same style.
+    pub fn qualifier(self) -> &'static str {
+        match self {
+            Self::R8 => "r8",
+            Self::RG8 => "rg8",
+            Self::RGB8 => "rgb8",
+            Self::RGBA8 => "rgba8",
+            Self::R32F => "r32f",
+            Self::RG32F => "rg32f",
+            Self::RGB32F => "rgb32f",
+            Self::RGBA32F => "rgba32f",
+            Self::R16F => "r16f",
+            Self::RG16F => "rg16f",
+            Self::RGBA16F => "rgba16f",
+            Self::RG11FB10F => "r11f_g11f_b10f",
+            Self::R32UI => "r32ui",
+            Self::RG32UI => "rg32ui",
+            Self::RGBA32UI => "rgba32ui",
+        }
+    }
 }
 
 macro_rules! impl_texture {
@@ -509,8 +941,8 @@ macro_rules! impl_texture {
             pub id: GLuint,
             pub format: TextureFormat,
             pub res: [u32; $dim],
-            pub min_filter: GLenum,
-            pub mag_filter: GLenum,
+            pub min_filter: Cell<GLenum>,
+            pub mag_filter: Cell<GLenum>,
             pub wrap_mode: GLenum,
             pub mipmap: bool,
         }
@@ -546,9 +978,53 @@ macro_rules! impl_texture {
                 self.id
             }
 
+            fn format(&self) -> TextureFormat {
+                self.format
+            }
+
+            fn image_format(&self) -> Option<TextureFormat> {
+                match $is_image {
+                    true => Some(self.format),
+                    false => None,
+                }
+            }
+
             fn framebuffer_id(&self) -> Option<GLuint> {
                 None
             }
+
+            fn filter(&self) -> Option<GLenum> {
+                match self.format.is_integer() {
+                    true => None,
+                    false => Some(self.mag_filter.get()),
+                }
+            }
+
+            fn set_filter(&self, filter: GLenum) {
+                if self.format.is_integer() {
+                    return;
+                }
+
+                let min_filter = min_filter_for(filter, self.mipmap);
+                unsafe {
+                    gl::BindTexture($enum_type, self.id);
+                    gl::TexParameteri($enum_type, gl::TEXTURE_MIN_FILTER, min_filter as _);
+                    gl::TexParameteri($enum_type, gl::TEXTURE_MAG_FILTER, filter as _);
+                    gl_debug_check!();
+                }
+
+                self.min_filter.set(min_filter);
+                self.mag_filter.set(filter);
+            }
+
+            fn fill_rgba8(&self, pixels: &[u8]) {
+                if $dim != 2 || self.format.is_integer() {
+                    return;
+                }
+
+                let res = [self.res[0], self.res[$dim - 1]];
+                fill_texture_2d(self.id, res, pixels, self.mipmap);
+            }
         }
 
         impl $name {
@@ -566,10 +1042,17 @@ macro_rules! impl_texture {
 
             pub fn get_formats(format: TextureFormat) -> (i32, u32, u32) {
                 let color_format = match format {
-                    TextureFormat::R8 | TextureFormat::R32F => gl::RED,
-                    TextureFormat::RG8 | TextureFormat::RG32F => gl::RG,
-                    TextureFormat::RGB8 | TextureFormat::RGB32F => gl::RGB,
-                    TextureFormat::RGBA32F | TextureFormat::RGBA8 => gl::RGBA,
+                    TextureFormat::R8 | TextureFormat::R16F | TextureFormat::R32F => gl::RED,
+                    TextureFormat::RG8 | TextureFormat::RG16F | TextureFormat::RG32F => gl::RG,
+                    TextureFormat::RGB8 | TextureFormat::RGB32F | TextureFormat::RG11FB10F => {
+                        gl::RGB
+                    }
+                    TextureFormat::RGBA32F | TextureFormat::RGBA16F | TextureFormat::RGBA8 => {
+                        gl::RGBA
+                    }
+                    TextureFormat::R32UI => gl::RED_INTEGER,
+                    TextureFormat::RG32UI => gl::RG_INTEGER,
+                    TextureFormat::RGBA32UI => gl::RGBA_INTEGER,
                 };
 
                 let type_ = match format {
@@ -577,10 +1060,17 @@ macro_rules! impl_texture {
                     | TextureFormat::RG8
                     | TextureFormat::RGB8
                     | TextureFormat::RGBA8 => gl::UNSIGNED_BYTE,
-                    TextureFormat::R32F
+                    TextureFormat::R16F
+                    | TextureFormat::RG16F
+                    | TextureFormat::RGBA16F
+                    | TextureFormat::R32F
                     | TextureFormat::RG32F
                     | TextureFormat::RGB32F
-                    | TextureFormat::RGBA32F => gl::FLOAT,
+                    | TextureFormat::RGBA32F
+                    | TextureFormat::RG11FB10F => gl::FLOAT,
+                    TextureFormat::R32UI | TextureFormat::RG32UI | TextureFormat::RGBA32UI => {
+                        gl::UNSIGNED_INT
+                    }
                 };
 
                 (format as i32, color_format as u32, type_ as u32)
@@ -619,7 +1109,9 @@ macro_rules! impl_texture {
                     if $dim > 2 {
                         gl::TexParameteri($enum_type, gl::TEXTURE_WRAP_R, wrap_mode as _);
                     }
+                    gl_debug_check!();
 
+                    // only level 0 is uploaded, the others are generated from it
                     gl_TexImageND(
                         $enum_type,
                         0,
@@ -632,6 +1124,13 @@ macro_rules! impl_texture {
                     );
                     gl_debug_check!();
 
+                    // a mipmapped texture is incomplete until all levels exist,
+                    // so generate them now instead of on the first write
+                    if mipmap {
+                        gl::GenerateMipmap($enum_type);
+                        gl_debug_check!();
+                    }
+
                     if $is_image {
                         gl::BindImageTexture(
                             0,
@@ -650,8 +1149,8 @@ macro_rules! impl_texture {
                         format,
                         res: resolution,
                         wrap_mode,
-                        min_filter,
-                        mag_filter,
+                        min_filter: Cell::new(min_filter),
+                        mag_filter: Cell::new(mag_filter),
                         mipmap,
                     }
                 }
@@ -736,6 +1235,63 @@ pub fn make_noise() -> Texture3D {
     tex
 }
 
+/// Creates a tileable 64x64 blue noise texture.
+///
+/// The pattern is generated with the void-and-cluster method: starting from an
+/// empty grid, the pixel in the largest void is ranked next, until all pixels
+/// are ranked. The result is deterministic, so it's the same on every load.
+pub fn make_blue_noise() -> Texture2D {
+    const WIDTH: usize = 64;
+    const SIZE: usize = WIDTH * WIDTH;
+    const SIGMA: f32 = 1.5;
+
+    // gaussian energy of a point, wrapping around the edges
+    let mut kernel = vec![0.0_f32; SIZE];
+    for y in 0..WIDTH {
+        for x in 0..WIDTH {
+            let dx = x.min(WIDTH - x) as f32;
+            let dy = y.min(WIDTH - y) as f32;
+            kernel[y * WIDTH + x] = (-(dx * dx + dy * dy) / (2.0 * SIGMA * SIGMA)).exp();
+        }
+    }
+
+    let mut energy = vec![0.0_f32; SIZE];
+    let mut data = vec![0_u8; SIZE];
+    let mut ranked = vec![false; SIZE];
+
+    for rank in 0..SIZE {
+        let (index, _) = energy.iter().enumerate().filter(|&(k, _)| !ranked[k]).fold(
+            (0, f32::INFINITY),
+            |min, (k, &e)| match e < min.1 {
+                true => (k, e),
+                false => min,
+            },
+        );
+
+        ranked[index] = true;
+        data[index] = (rank * 256 / SIZE) as u8;
+
+        let (px, py) = (index % WIDTH, index / WIDTH);
+        for y in 0..WIDTH {
+            for x in 0..WIDTH {
+                let kx = (x + WIDTH - px) % WIDTH;
+                let ky = (y + WIDTH - py) % WIDTH;
+                energy[y * WIDTH + x] += kernel[ky * WIDTH + kx];
+            }
+        }
+    }
+
+    Texture2D::with_params(
+        [WIDTH as _; 2],
+        gl::NEAREST,
+        gl::NEAREST,
+        gl::REPEAT,
+        TextureFormat::R8,
+        false,
+        data.as_ptr() as _,
+    )
+}
+
 #[deprecated]
 pub fn make_texture_from_image(dyn_image: DynamicImage) -> Texture2D {
     let image = dyn_image.flipv().to_rgba8();
@@ -751,3 +1307,36 @@ pub fn make_texture_from_image(dyn_image: DynamicImage) -> Texture2D {
 
     tex
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn image_compatible_formats() {
+        use TextureFormat::*;
+
+        let formats = [
+            R8, RG8, RGB8, RGBA8, R32F, RG32F, RGB32F, RGBA32F, R16F, RG16F, RGBA16F, RG11FB10F,
+            R32UI, RG32UI, RGBA32UI,
+        ];
+
+        for format in formats {
+            // every format that can be bound as an image has a GLSL qualifier
+            let qualified = TextureFormat::from_qualifier(format.qualifier()) == Some(format);
+            assert_eq!(format.is_image_compatible(), qualified, "{:?}", format);
+        }
+
+        assert!(!RGB8.is_image_compatible());
+        assert!(!RGB32F.is_image_compatible());
+        assert!(RG11FB10F.is_image_compatible());
+    }
+
+    #[test]
+    fn mip_levels() {
+        assert_eq!(mip_level_count(1, 1), 1);
+        assert_eq!(mip_level_count(0, 0), 1);
+        assert_eq!(mip_level_count(1920, 1080), 11);
+        assert_eq!(mip_level_count(256, 512), 10);
+    }
+}
@@ -0,0 +1,61 @@
+use gl::types::*;
+
+use crate::*;
+
+/// A uniform buffer object.
+///
+/// Holds uniforms shared by all stages, which are uploaded once per frame
+/// instead of once per stage.
+#[derive(Debug)]
+pub struct UniformBuffer {
+    pub id: GLuint,
+    pub size: usize,
+}
+
+impl UniformBuffer {
+    /// Creates a new uniform buffer of the given size in bytes.
+    pub fn new(size: usize) -> Self {
+        unsafe {
+            let mut id = 0;
+            gl::GenBuffers(1, &mut id);
+            gl::BindBuffer(gl::UNIFORM_BUFFER, id);
+            gl::BufferData(
+                gl::UNIFORM_BUFFER,
+                size as _,
+                std::ptr::null(),
+                gl::DYNAMIC_DRAW,
+            );
+            gl::BindBuffer(gl::UNIFORM_BUFFER, 0);
+            gl_debug_check!();
+
+            Self { id, size }
+        }
+    }
+
+    /// Replaces the contents of the buffer.
+    pub fn update<T>(&self, data: &T) {
+        debug_assert_eq!(std::mem::size_of::<T>(), self.size);
+
+        unsafe {
+            gl::BindBuffer(gl::UNIFORM_BUFFER, self.id);
+            gl::BufferSubData(gl::UNIFORM_BUFFER, 0, self.size as _, data as *const T as _);
+            gl::BindBuffer(gl::UNIFORM_BUFFER, 0);
+            gl_debug_check!();
+        }
+    }
+
+    pub fn bind(&self, binding_unit: u32) {
+        unsafe {
+            gl::BindBufferBase(gl::UNIFORM_BUFFER, binding_unit, self.id);
+            gl_debug_check!();
+        }
+    }
+}
+
+impl Drop for UniformBuffer {
+    fn drop(&mut self) {
+        unsafe {
+            gl::DeleteBuffers(1, &self.id);
+        }
+    }
+}